@@ -20,6 +20,10 @@ fn main() -> Result<(), anyhow::Error> {
         for section in fapt.open_listing(&list)? {
             let pkg = section?.as_pkg()?;
             let src = pkg.as_src().ok_or(anyhow!("non-source package found"))?;
+            let directory = src
+                .directory
+                .as_ref()
+                .ok_or(anyhow!("index entry without a directory"))?;
 
             let mut urls = Vec::with_capacity(4);
 
@@ -28,7 +32,7 @@ fn main() -> Result<(), anyhow::Error> {
                     continue;
                 }
 
-                urls.push(format!("{}/{}", src.directory, line.name));
+                urls.push(format!("{}/{}", directory, line.name));
             }
 
             assert!(package_version_files
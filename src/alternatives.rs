@@ -0,0 +1,175 @@
+//! The `update-alternatives` database, usually in `/var/lib/dpkg/alternatives/`.
+//!
+//! Each file is a link group, e.g. `editor`, whose master link (`/usr/bin/editor`) points,
+//! via `/etc/alternatives/editor`, at one of the candidates. Slave links, e.g. the man page,
+//! follow the master.
+//!
+//! ```no_run
+//! # use fapt::system::System;
+//! let mut fapt = System::cache_only()?;
+//! fapt.set_dpkg_database("/var/lib/dpkg");
+//! for group in fapt.alternatives()? {
+//!     if let Some(best) = group.best() {
+//!         println!("{}: {} ({})", group.link, best.path, best.priority);
+//!     }
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Error;
+
+/// A link group, and the candidates for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alternatives {
+    /// e.g. `editor`; the name of the file, and of the link in `/etc/alternatives`.
+    pub name: String,
+    pub mode: Mode,
+    /// The master link, e.g. `/usr/bin/editor`.
+    pub link: String,
+    pub slaves: Vec<Slave>,
+    pub candidates: Vec<Candidate>,
+}
+
+/// Whether the highest priority candidate is used, or the admin picked one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Auto,
+    Manual,
+}
+
+/// A link which follows the master, e.g. `editor.1.gz` at `/usr/share/man/man1/editor.1.gz`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Slave {
+    pub name: String,
+    pub link: String,
+}
+
+/// Something the master link can point to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    /// e.g. `/usr/bin/vim.basic`
+    pub path: String,
+    pub priority: i64,
+    /// slave name -> where it points, for the slaves this candidate provides
+    pub slaves: BTreeMap<String, String>,
+}
+
+impl Alternatives {
+    /// The candidate picked in [Mode::Auto]: the first with the highest priority.
+    pub fn best(&self) -> Option<&Candidate> {
+        let mut best: Option<&Candidate> = None;
+        for candidate in &self.candidates {
+            if best.is_none_or(|best| candidate.priority > best.priority) {
+                best = Some(candidate);
+            }
+        }
+        best
+    }
+}
+
+/// Read the file for the link group `name`.
+pub fn read_alternatives<R: Read>(name: &str, mut from: R) -> Result<Alternatives, Error> {
+    let mut text = String::new();
+    from.read_to_string(&mut text)?;
+    let mut lines = text.split('\n');
+
+    let mode = match next_line(&mut lines, "the mode")? {
+        "auto" => Mode::Auto,
+        "manual" => Mode::Manual,
+        other => bail!("invalid mode: {:?}", other),
+    };
+
+    let link = next_line(&mut lines, "the master link")?.to_string();
+    if link.is_empty() {
+        bail!("no master link");
+    }
+
+    let mut slaves = Vec::new();
+    loop {
+        let name = next_line(&mut lines, "a slave, or a blank line")?;
+        if name.is_empty() {
+            break;
+        }
+        slaves.push(Slave {
+            name: name.to_string(),
+            link: next_line(&mut lines, "a slave's link")?.to_string(),
+        });
+    }
+
+    let mut candidates = Vec::new();
+    loop {
+        let path = next_line(&mut lines, "a candidate, or a blank line")?;
+        if path.is_empty() {
+            break;
+        }
+        let priority = next_line(&mut lines, "a priority")?;
+        let priority = priority
+            .parse()
+            .with_context(|| anyhow!("priority of {:?}: {:?}", path, priority))?;
+
+        // one line for every slave, blank if the candidate doesn't provide it
+        let mut provides = BTreeMap::new();
+        for slave in &slaves {
+            let target = next_line(&mut lines, "a slave's target")?;
+            if !target.is_empty() {
+                provides.insert(slave.name.to_string(), target.to_string());
+            }
+        }
+
+        candidates.push(Candidate {
+            path: path.to_string(),
+            priority,
+            slaves: provides,
+        });
+    }
+
+    Ok(Alternatives {
+        name: name.to_string(),
+        mode,
+        link,
+        slaves,
+        candidates,
+    })
+}
+
+fn next_line<'t, I: Iterator<Item = &'t str>>(lines: &mut I, what: &str) -> Result<&'t str, Error> {
+    lines
+        .next()
+        .ok_or_else(|| anyhow!("truncated; expecting {}", what))
+}
+
+/// Every link group in the `dpkg` database at `dpkg`, by name.
+pub(crate) fn alternatives_in(dpkg: &Path) -> Result<Vec<Alternatives>, Error> {
+    let dir = dpkg.join("alternatives");
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::from(e).context(anyhow!("listing {:?}", dir))),
+    };
+
+    let mut ret = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        // left by an interrupted update-alternatives
+        if name.ends_with(".dpkg-tmp") || name.ends_with(".dpkg-new") {
+            continue;
+        }
+        let file = fs::File::open(entry.path()).with_context(|| anyhow!("opening {:?}", name))?;
+        ret.push(read_alternatives(&name, file).with_context(|| anyhow!("reading {:?}", name))?);
+    }
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(ret)
+}
@@ -0,0 +1,105 @@
+//! A content-addressed cache of files from the pool, e.g. `.deb`s.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use reqwest::Url;
+
+use crate::checksum;
+use crate::checksum::HashAlg;
+use crate::fetch;
+
+/// A file we'd like, and what we expect it to contain.
+#[derive(Clone, Debug)]
+pub struct PoolFile {
+    pub url: Url,
+    pub len: u64,
+    pub alg: HashAlg,
+    pub hash: Vec<u8>,
+}
+
+impl PoolFile {
+    /// Where this file lives in the cache, e.g. `SHA256/0123abc...`.
+    pub fn local_path<P: AsRef<Path>>(&self, archives_dir: P) -> PathBuf {
+        archives_dir
+            .as_ref()
+            .join(self.alg.by_hash_name())
+            .join(hex::encode(&self.hash))
+    }
+
+    fn temp_name(&self) -> String {
+        format!("{}-{}", self.alg.by_hash_name(), hex::encode(&self.hash))
+    }
+}
+
+/// Ensure all of the files are in the cache, downloading and verifying any which aren't.
+///
+/// Returns the cache path for each file, in order.
+pub async fn download<P: AsRef<Path>>(
+    client: &fetch::Client,
+    archives_dir: P,
+    files: &[PoolFile],
+) -> Result<Vec<PathBuf>, Error> {
+    let archives_dir = archives_dir.as_ref();
+    fs::create_dir_all(archives_dir)
+        .with_context(|| anyhow!("creating archives directory {:?}", archives_dir))?;
+
+    let temp_dir = tempfile::Builder::new()
+        .prefix(".fapt-archives")
+        .tempdir_in(archives_dir)
+        .with_context(|| anyhow!("creating temporary directory"))?;
+
+    let mut seen = HashSet::with_capacity(files.len());
+    let wanted: Vec<&PoolFile> = files
+        .iter()
+        .filter(|file| {
+            let local = file.local_path(archives_dir);
+            !local.exists() && seen.insert(local)
+        })
+        .collect();
+
+    let downloads: Vec<fetch::Download> = wanted
+        .iter()
+        .map(|file| {
+            fetch::Download::from_to(file.url.clone(), temp_dir.path().join(file.temp_name()))
+        })
+        .collect();
+
+    fetch::fetch(client, &downloads)
+        .await
+        .with_context(|| anyhow!("downloading pool files"))?;
+
+    for file in wanted {
+        store(file, temp_dir.path(), archives_dir)
+            .with_context(|| anyhow!("storing {}", file.url))?;
+    }
+
+    Ok(files
+        .iter()
+        .map(|file| file.local_path(archives_dir))
+        .collect())
+}
+
+fn store(file: &PoolFile, temp_dir: &Path, archives_dir: &Path) -> Result<(), Error> {
+    let temp_path = temp_dir.join(file.temp_name());
+
+    checksum::validate_len_and_hash(
+        fs::File::open(&temp_path)
+            .with_context(|| anyhow!("opening a temp file we just downloaded"))?,
+        file.len,
+        file.alg,
+        &file.hash,
+    )
+    .with_context(|| anyhow!("validating downloaded file"))?;
+
+    let destination = file.local_path(archives_dir);
+    fs::create_dir_all(destination.parent().expect("always inside archives_dir"))?;
+    fs::rename(temp_path, destination)?;
+
+    Ok(())
+}
@@ -17,6 +17,9 @@ struct Cli {
     /// explicitly add a keyring search path
     #[clap(long, value_name = "PREFIX", number_of_values = 1)]
     keyring: Option<Vec<PathBuf>>,
+    /// an apt auth.conf to read credentials from (auth.conf.d is also searched)
+    #[clap(long, value_name = "PATH")]
+    auth_conf: Option<PathBuf>,
     /// explicitly set the cache directory
     #[clap(short = 'c', long, value_name = "DIRECTORY")]
     cache_dir: Option<PathBuf>,
@@ -36,6 +39,22 @@ struct Cli {
 enum Sub {
     Update,
     SourceNinja,
+    /// check a mirror has everything its indices mention
+    CheckMirror {
+        /// the root of the mirror, e.g. 'http://deb.debian.org/debian/'
+        url: String,
+        /// e.g. 'bookworm'
+        suite: String,
+        /// download and hash every file, not just the indices
+        #[clap(long)]
+        deep: bool,
+    },
+    /// check the installed files against the hashes dpkg recorded
+    Debsums {
+        /// where the packages are installed
+        #[clap(long, value_name = "PATH", default_value = "/")]
+        root: PathBuf,
+    },
 }
 
 fn main() -> Result<()> {
@@ -43,7 +62,7 @@ fn main() -> Result<()> {
 
     let mut sources_entries = Vec::with_capacity(16);
     if let Some(prefix) = matches.sources_list {
-        for prefix in expand_dot_d(prefix)? {
+        for prefix in expand_dot_d(prefix, &[])? {
             sources_entries.extend(
                 sources_list::read(io::BufReader::new(fs::File::open(&prefix)?))
                     .with_context(|| anyhow!("loading sources.list: {:?}", prefix))?,
@@ -71,7 +90,11 @@ fn main() -> Result<()> {
         None => vec!["amd64".to_string()],
     };
 
-    if sources_entries.is_empty() {
+    let needs_sources = !matches!(
+        matches.subcommand,
+        Sub::CheckMirror { .. } | Sub::Debsums { .. }
+    );
+    if needs_sources && sources_entries.is_empty() {
         bail!(concat!(
             "No sources-list entries; either specify a non-empty",
             "--sources-list, or provide some --sources-lines"
@@ -79,10 +102,10 @@ fn main() -> Result<()> {
     }
 
     let mut system = System::cache_only()?;
-    system.add_sources_entries(sources_entries.clone().into_iter());
+    system.add_sources_entries(sources_entries.clone().into_iter())?;
     if let Some(keyring_paths) = matches.keyring {
         for keyring_path in keyring_paths {
-            for path in expand_dot_d(keyring_path)? {
+            for path in expand_dot_d(keyring_path, &["asc"])? {
                 system.add_keys_from(
                     fs::File::open(&path)
                         .with_context(|| anyhow!("opening key file: {:?}", path))?,
@@ -93,6 +116,17 @@ fn main() -> Result<()> {
         commands::add_builtin_keys(&mut system);
     }
 
+    if let Some(auth_conf) = matches.auth_conf {
+        for path in expand_dot_d(auth_conf, &[])? {
+            system
+                .add_credentials_from(
+                    fs::File::open(&path)
+                        .with_context(|| anyhow!("opening auth file: {:?}", path))?,
+                )
+                .with_context(|| anyhow!("loading auth file: {:?}", path))?;
+        }
+    }
+
     system.set_arches(&arches);
 
     system.set_dpkg_database(&matches.system_dpkg);
@@ -104,12 +138,45 @@ fn main() -> Result<()> {
         Sub::Update => {
             system.update()?;
         }
+        Sub::CheckMirror { url, suite, deep } => {
+            let report = system.check_mirror(&url, &suite, deep)?;
+            for problem in &report.problems {
+                println!("{}", problem);
+            }
+            ensure!(
+                report.problems.is_empty(),
+                "{} problems in {} files",
+                report.problems.len(),
+                report.checked
+            );
+            println!("{} files ok", report.checked);
+        }
+        Sub::Debsums { root } => {
+            let report = system.debsums(&root)?;
+            for package in &report.unverified {
+                println!("{}: no md5sums", package);
+            }
+            for problem in &report.conffiles {
+                println!("{} (conffile)", problem);
+            }
+            for problem in &report.problems {
+                println!("{}", problem);
+            }
+            ensure!(
+                report.problems.is_empty(),
+                "{} problems in {} files",
+                report.problems.len(),
+                report.checked
+            );
+            println!("{} files checked", report.checked);
+        }
     }
 
     Ok(())
 }
 
-fn expand_dot_d<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
+/// `path`, and the files in `path.d` with the same extension, or any of `also`.
+fn expand_dot_d<P: AsRef<Path>>(path: P, also: &[&str]) -> Result<Vec<PathBuf>, Error> {
     let mut ret = Vec::new();
 
     let path = path.as_ref();
@@ -132,7 +199,11 @@ fn expand_dot_d<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
     if dot_d.is_dir() {
         for file in fs::read_dir(dot_d)? {
             let file = file?.path();
-            if file.is_file() && file.extension() == extension {
+            let other = file
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| also.contains(&ext));
+            if file.is_file() && (file.extension() == extension || other) {
                 ret.push(file);
             }
         }
@@ -1,15 +1,22 @@
 use std::fmt;
+use std::fs;
 use std::io;
 use std::io::Read;
+use std::path::Path;
 
+use anyhow::anyhow;
 use anyhow::ensure;
+use anyhow::Context;
 use anyhow::Error;
 use hex::FromHex;
 use sha2::Digest;
 use sha2::Sha256;
+use sha2::Sha512;
 
 pub type MD5 = [u8; 16];
+pub type SHA1 = [u8; 20];
 pub type SHA256 = [u8; 32];
+pub type SHA512 = [u8; 64];
 
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
 pub struct Hashes {
@@ -29,25 +36,29 @@ impl fmt::Debug for Hashes {
 }
 
 pub fn parse_md5(hash: &str) -> Result<MD5, Error> {
-    let mut arr = [0u8; 16];
-    let v = Vec::from_hex(hash)?;
-    ensure!(
-        arr.len() == v.len(),
-        "a md5 checksum isn't the right length? {}",
-        hash
-    );
-    arr.copy_from_slice(&v);
+    parse_fixed(hash, "md5")
+}
 
-    Ok(arr)
+pub fn parse_sha1(hash: &str) -> Result<SHA1, Error> {
+    parse_fixed(hash, "sha1")
 }
 
 pub fn parse_sha256(hash: &str) -> Result<SHA256, Error> {
-    let mut arr = [0u8; 32];
+    parse_fixed(hash, "sha256")
+}
+
+pub fn parse_sha512(hash: &str) -> Result<SHA512, Error> {
+    parse_fixed(hash, "sha512")
+}
+
+fn parse_fixed<const N: usize>(hash: &str, name: &str) -> Result<[u8; N], Error> {
+    let mut arr = [0u8; N];
 
     let v = Vec::from_hex(hash)?;
     ensure!(
         arr.len() == v.len(),
-        "a sha256 checksum isn't the right length? {}",
+        "a {} checksum isn't the right length? {}",
+        name,
         hash
     );
 
@@ -56,7 +67,6 @@ pub fn parse_sha256(hash: &str) -> Result<SHA256, Error> {
     Ok(arr)
 }
 
-// TODO: also check the md5?
 pub fn validate<R: Read>(mut file: R, checksum: Hashes) -> Result<(), Error> {
     let mut func = Sha256::default();
     io::copy(&mut file, &mut func)?;
@@ -69,3 +79,205 @@ pub fn validate<R: Read>(mut file: R, checksum: Hashes) -> Result<(), Error> {
     );
     Ok(())
 }
+
+/// Every hash `Release` files use for some content, and its length.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Digests {
+    pub(crate) len: u64,
+    pub(crate) md5: MD5,
+    pub(crate) sha1: SHA1,
+    pub(crate) sha256: SHA256,
+    pub(crate) sha512: SHA512,
+}
+
+impl Digests {
+    /// Compute every hash in a single pass.
+    pub(crate) fn of<R: Read>(mut from: R) -> Result<Digests, Error> {
+        let mut md5 = md5::Md5::default();
+        let mut sha1 = sha1::Sha1::default();
+        let mut sha256 = Sha256::default();
+        let mut sha512 = Sha512::default();
+        let mut len = 0;
+
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let found = from.read(&mut buf)?;
+            if 0 == found {
+                break;
+            }
+            let chunk = &buf[..found];
+            md5.update(chunk);
+            sha1.update(chunk);
+            sha256.update(chunk);
+            sha512.update(chunk);
+            len += found as u64;
+        }
+
+        Ok(Digests {
+            len,
+            md5: md5.finalize().into(),
+            sha1: sha1.finalize().into(),
+            sha256: sha256.finalize().into(),
+            sha512: sha512.finalize().into(),
+        })
+    }
+
+    /// Hex-encoded, weakest first.
+    pub(crate) fn hex(&self) -> [(HashAlg, String); 4] {
+        [
+            HashAlg::Md5,
+            HashAlg::Sha1,
+            HashAlg::Sha256,
+            HashAlg::Sha512,
+        ]
+        .map(|alg| (alg, self.hex_of(alg)))
+    }
+
+    /// The digest made with `alg`, hex-encoded.
+    pub(crate) fn hex_of(&self, alg: HashAlg) -> String {
+        match alg {
+            HashAlg::Md5 => hex::encode(self.md5),
+            HashAlg::Sha1 => hex::encode(self.sha1),
+            HashAlg::Sha256 => hex::encode(self.sha256),
+            HashAlg::Sha512 => hex::encode(self.sha512),
+        }
+    }
+}
+
+/// The hashes found in Debian metadata, weakest first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashAlg {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl HashAlg {
+    /// The name used in `by-hash` paths and `Release` files.
+    pub fn by_hash_name(self) -> &'static str {
+        match self {
+            HashAlg::Md5 => "MD5Sum",
+            HashAlg::Sha1 => "SHA1",
+            HashAlg::Sha256 => "SHA256",
+            HashAlg::Sha512 => "SHA512",
+        }
+    }
+
+    pub fn digest<R: Read>(self, mut file: R) -> Result<Vec<u8>, Error> {
+        Ok(match self {
+            HashAlg::Md5 => digest_with(md5::Md5::default(), &mut file)?,
+            HashAlg::Sha1 => digest_with(sha1::Sha1::default(), &mut file)?,
+            HashAlg::Sha256 => digest_with(Sha256::default(), &mut file)?,
+            HashAlg::Sha512 => digest_with(Sha512::default(), &mut file)?,
+        })
+    }
+}
+
+fn digest_with<D: Digest + io::Write, R: Read>(mut func: D, mut file: R) -> Result<Vec<u8>, Error> {
+    io::copy(&mut file, &mut func)?;
+    Ok(func.finalize().to_vec())
+}
+
+/// Check both the length and a hash of some content.
+pub fn validate_len_and_hash<R: Read>(
+    file: R,
+    len: u64,
+    alg: HashAlg,
+    expected: &[u8],
+) -> Result<(), Error> {
+    let mut counted = CountingReader {
+        inner: file,
+        read: 0,
+    };
+    let actual = alg.digest(&mut counted)?;
+    ensure!(
+        len == counted.read,
+        "size mismatch: expected: {}, actual: {}",
+        len,
+        counted.read
+    );
+    ensure!(
+        expected == actual.as_slice(),
+        "{:?} mismatch: expected: {}, actual: {}",
+        alg,
+        hex::encode(expected),
+        hex::encode(actual)
+    );
+    Ok(())
+}
+
+/// Pick the strongest of some (possibly empty) hex-encoded hashes.
+pub fn strongest<'h, I: IntoIterator<Item = (HashAlg, &'h str)>>(
+    hashes: I,
+) -> Result<(HashAlg, Vec<u8>), Error> {
+    let (alg, hash) = strongest_present(hashes.into_iter().filter(|(_, hash)| !hash.is_empty()))?;
+    Ok((alg, Vec::from_hex(hash)?))
+}
+
+/// Pick the strongest of the hashes listed for a file in a `.dsc` or `.changes`,
+/// which always have an md5.
+pub fn strongest_decoded(
+    md5: &MD5,
+    sha1: Option<&SHA1>,
+    sha256: Option<&SHA256>,
+    sha512: Option<&SHA512>,
+) -> (HashAlg, Vec<u8>) {
+    let (alg, hash) = strongest_present(
+        [
+            (HashAlg::Md5, Some(&md5[..])),
+            (HashAlg::Sha1, sha1.map(|hash| &hash[..])),
+            (HashAlg::Sha256, sha256.map(|hash| &hash[..])),
+            (HashAlg::Sha512, sha512.map(|hash| &hash[..])),
+        ]
+        .into_iter()
+        .filter_map(|(alg, hash)| Some((alg, hash?))),
+    )
+    .expect("md5 is always present");
+    (alg, hash.to_vec())
+}
+
+fn strongest_present<T, I: IntoIterator<Item = (HashAlg, T)>>(
+    hashes: I,
+) -> Result<(HashAlg, T), Error> {
+    hashes
+        .into_iter()
+        .max_by_key(|(alg, _)| *alg)
+        .ok_or_else(|| anyhow!("no hashes available"))
+}
+
+/// Check the file called `name`, which must be directly inside `dir`, like
+/// [validate_len_and_hash].
+pub fn validate_in(
+    dir: &Path,
+    name: &str,
+    len: u64,
+    alg: HashAlg,
+    expected: &[u8],
+) -> Result<(), Error> {
+    ensure!(
+        !name.contains('/') && "." != name && ".." != name,
+        "refusing to look outside the directory"
+    );
+
+    let path = dir.join(name);
+    validate_len_and_hash(
+        fs::File::open(&path).with_context(|| anyhow!("opening {:?}", path))?,
+        len,
+        alg,
+        expected,
+    )
+}
+
+struct CountingReader<R> {
+    inner: R,
+    read: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let found = self.inner.read(buf)?;
+        self.read += found as u64;
+        Ok(found)
+    }
+}
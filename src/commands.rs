@@ -31,8 +31,7 @@ pub fn add_sources_entries_from_str<S: AsRef<str>>(
     system: &mut System,
     string: S,
 ) -> Result<(), Error> {
-    system.add_sources_entries(sources_list::read(io::Cursor::new(string.as_ref()))?);
-    Ok(())
+    system.add_sources_entries(sources_list::read(io::Cursor::new(string.as_ref()))?)
 }
 
 /// Walk through all the _Blocks_ (packages!) in a _System_,
@@ -0,0 +1,696 @@
+//! Reading `.deb` files, without `dpkg-deb`.
+//!
+//! ```no_run
+//! # use std::fs;
+//! # use fapt::deb::Deb;
+//! let deb = Deb::read(fs::File::open("hello_2.10-3_amd64.deb")?)?;
+//! println!("{} {}", deb.control.package.name, deb.control.package.version);
+//! for entry in &deb.data {
+//!     println!("{:o} {:>8} {}", entry.mode, entry.size, entry.path);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+
+use crate::checksum;
+use crate::parse::Package;
+use crate::rfc822;
+
+/// The largest member of the control archive we'll read; `md5sums` is the biggest in practice.
+const MAX_CONTROL_MEMBER: u64 = 64 * 1024 * 1024;
+
+/// The scripts dpkg runs, found in the control archive.
+pub const MAINTAINER_SCRIPTS: &[&str] = &["preinst", "postinst", "prerm", "postrm", "config"];
+
+/// A whole `.deb`: its metadata, and a listing of its contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deb {
+    pub control: Control,
+    pub data: Vec<DataEntry>,
+}
+
+/// The contents of `control.tar`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Control {
+    /// The `control` file, parsed as if it came from an index.
+    pub package: Package,
+    /// The `control` file exactly as it was, for the fields parsing doesn't keep.
+    pub text: String,
+    /// Absolute paths, e.g. `/usr/bin/hello`, and their expected md5.
+    pub md5sums: Vec<(String, checksum::MD5)>,
+    pub conffiles: Vec<Conffile>,
+    /// Any of [MAINTAINER_SCRIPTS] which are present.
+    pub scripts: HashMap<String, Vec<u8>>,
+    /// Everything else, e.g. `triggers`, `shlibs`, `templates`.
+    pub other: HashMap<String, Vec<u8>>,
+}
+
+/// An entry in `conffiles`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conffile {
+    /// e.g. `/etc/hello.conf`
+    pub path: String,
+    /// The `remove-on-upgrade` flag is set.
+    pub remove_on_upgrade: bool,
+}
+
+/// An entry in `data.tar`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataEntry {
+    /// Absolute, e.g. `/usr/bin/hello`, or `/` for the root directory.
+    pub path: String,
+    pub kind: EntryKind,
+    /// Permission bits, including the setuid, setgid and sticky bits.
+    pub mode: u32,
+    /// Zero for anything other than regular files.
+    pub size: u64,
+    pub uid: u64,
+    pub gid: u64,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub mtime: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    /// The target, exactly as it appears in the archive.
+    Symlink(String),
+    /// The absolute path of an earlier entry.
+    HardLink(String),
+    CharDevice {
+        major: u32,
+        minor: u32,
+    },
+    BlockDevice {
+        major: u32,
+        minor: u32,
+    },
+    Fifo,
+}
+
+impl Deb {
+    /// Read the control archive, and list the data archive.
+    pub fn read<R: Read>(from: R) -> Result<Deb, Error> {
+        let mut reader = DebReader::new(from)?;
+        let control = reader.control()?;
+        let data = reader.data(|mut archive| {
+            let mut entries = Vec::new();
+            for entry in archive.entries()? {
+                entries.push(DataEntry::from_tar(&entry?)?);
+            }
+            Ok(entries)
+        })?;
+        Ok(Deb { control, data })
+    }
+}
+
+impl Control {
+    /// Read just the control archive, which is much faster than reading the whole `.deb`.
+    pub fn read<R: Read>(from: R) -> Result<Control, Error> {
+        DebReader::new(from)?.control()
+    }
+
+    fn from_tar<R: Read>(mut archive: tar::Archive<R>) -> Result<Control, Error> {
+        let mut files = HashMap::new();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if tar::EntryType::Regular != entry.header().entry_type() {
+                continue;
+            }
+            let name = entry_path(&entry)?;
+            let name = name.trim_start_matches('/').to_string();
+            // the header's claim, which we're about to allocate for
+            ensure!(
+                entry.size() <= MAX_CONTROL_MEMBER,
+                "{:?} in control archive is too large: {} bytes",
+                name,
+                entry.size()
+            );
+            let mut content = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut content)?;
+            files.insert(name, content);
+        }
+
+        let control = files
+            .remove("control")
+            .ok_or_else(|| anyhow!("no control file in control archive"))?;
+        let control = String::from_utf8(control).with_context(|| anyhow!("reading control"))?;
+
+        let package =
+            Package::parse(&mut rfc822::fields_in_block(control.trim()).collect_to_map()?)
+                .with_context(|| anyhow!("parsing control"))?;
+
+        let md5sums = match files.remove("md5sums") {
+            Some(md5sums) => parse_md5sums(&String::from_utf8(md5sums)?)
+                .with_context(|| anyhow!("parsing md5sums"))?,
+            None => Vec::new(),
+        };
+
+        let conffiles = match files.remove("conffiles") {
+            Some(conffiles) => parse_conffiles(&String::from_utf8(conffiles)?)
+                .with_context(|| anyhow!("parsing conffiles"))?,
+            None => Vec::new(),
+        };
+
+        let mut scripts = HashMap::new();
+        for &script in MAINTAINER_SCRIPTS {
+            if let Some(content) = files.remove(script) {
+                scripts.insert(script.to_string(), content);
+            }
+        }
+
+        Ok(Control {
+            package,
+            text: control,
+            md5sums,
+            conffiles,
+            scripts,
+            other: files,
+        })
+    }
+}
+
+impl DataEntry {
+    pub(crate) fn from_tar<R: Read>(entry: &tar::Entry<R>) -> Result<DataEntry, Error> {
+        let header = entry.header();
+        let path = entry_path(entry)?;
+
+        let link_name = || -> Result<String, Error> {
+            Ok(entry
+                .link_name()?
+                .ok_or_else(|| anyhow!("link without a target: {:?}", path))?
+                .to_str()
+                .ok_or_else(|| anyhow!("non-utf-8 link target for {:?}", path))?
+                .to_string())
+        };
+
+        let device = || -> Result<(u32, u32), Error> {
+            Ok((
+                header.device_major()?.unwrap_or(0),
+                header.device_minor()?.unwrap_or(0),
+            ))
+        };
+
+        let kind = match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+            tar::EntryType::Directory => EntryKind::Directory,
+            tar::EntryType::Symlink => EntryKind::Symlink(link_name()?),
+            tar::EntryType::Link => EntryKind::HardLink(absolute(&link_name()?)),
+            tar::EntryType::Char => {
+                let (major, minor) = device()?;
+                EntryKind::CharDevice { major, minor }
+            }
+            tar::EntryType::Block => {
+                let (major, minor) = device()?;
+                EntryKind::BlockDevice { major, minor }
+            }
+            tar::EntryType::Fifo => EntryKind::Fifo,
+            other => bail!("unsupported entry type {:?} for {:?}", other, path),
+        };
+
+        let size = match kind {
+            EntryKind::File => entry.size(),
+            _ => 0,
+        };
+
+        Ok(DataEntry {
+            path,
+            kind,
+            mode: header.mode()? & 0o7777,
+            size,
+            uid: header.uid()?,
+            gid: header.gid()?,
+            user: header.username().ok().flatten().map(|s| s.to_string()),
+            group: header.groupname().ok().flatten().map(|s| s.to_string()),
+            mtime: header.mtime()?,
+        })
+    }
+}
+
+/// What [unpack] did with a `.deb`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unpacked {
+    pub control: Control,
+    /// Every entry in the data archive, in order. The ownership and special permission
+    /// bits are only recorded here, as we can't apply them without being `root`.
+    pub manifest: Vec<ManifestEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub entry: DataEntry,
+    /// Where the entry was written, relative to the root, after any diversion.
+    /// `None` if it was skipped; devices and fifos are never created.
+    pub written_to: Option<String>,
+}
+
+/// Extract the data archive of a `.deb` into `root`.
+///
+/// Only the ordinary permission bits are applied. Nothing is written outside of `root`:
+/// symlinks are followed as if `root` was `/`, and paths containing `..` are refused.
+pub fn unpack<R: Read, P: AsRef<Path>>(from: R, root: P) -> Result<Unpacked, Error> {
+    unpack_diverted(from, root, |path| Some(path.to_string()))
+}
+
+/// As [unpack], but ask `divert` where each path (e.g. `/usr/bin/hello`) should be written,
+/// to honour `dpkg-divert`s, or to leave things out by returning `None`.
+pub fn unpack_diverted<R, P, F>(from: R, root: P, mut divert: F) -> Result<Unpacked, Error>
+where
+    R: Read,
+    P: AsRef<Path>,
+    F: FnMut(&str) -> Option<String>,
+{
+    let root = root.as_ref();
+    fs::create_dir_all(root).with_context(|| anyhow!("creating root {:?}", root))?;
+
+    let mut reader = DebReader::new(from)?;
+    let control = reader.control()?;
+    let manifest = reader.data(|mut archive| {
+        let mut manifest = Vec::new();
+        // package path -> where we put it, for hardlinks
+        let mut written = HashMap::new();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let data = DataEntry::from_tar(&entry)?;
+
+            let written_to = match divert(&data.path) {
+                Some(dest) => unpack_entry(root, &data, &dest, &mut entry, &written)
+                    .with_context(|| anyhow!("unpacking {:?} to {:?}", data.path, dest))?,
+                None => None,
+            };
+
+            if let Some(dest) = &written_to {
+                written.insert(data.path.to_string(), dest.to_string());
+            }
+
+            manifest.push(ManifestEntry {
+                entry: data,
+                written_to,
+            });
+        }
+
+        Ok(manifest)
+    })?;
+
+    Ok(Unpacked { control, manifest })
+}
+
+pub(crate) fn unpack_entry<R: Read>(
+    root: &Path,
+    data: &DataEntry,
+    dest: &str,
+    content: &mut R,
+    written: &HashMap<String, String>,
+) -> Result<Option<String>, Error> {
+    let dest = absolute(dest);
+    ensure!(
+        !dest.split('/').any(|part| ".." == part),
+        "refusing path containing '..'"
+    );
+
+    match &data.kind {
+        EntryKind::Directory => {
+            let dir = resolve_in(root, &dest, true)?;
+            if fs::symlink_metadata(&dir).is_err() {
+                fs::create_dir(&dir)?;
+                set_mode(&dir, data.mode & 0o777 | 0o700)?;
+            }
+            ensure!(dir.is_dir(), "exists, but isn't a directory");
+        }
+        EntryKind::File => {
+            let path = resolve_in(root, &dest, false)?;
+            let mut temp = tempfile::NamedTempFile::new_in(
+                path.parent().expect("resolved paths are inside the root"),
+            )?;
+            io::copy(content, &mut temp)?;
+            set_mode(temp.path(), data.mode & 0o777)?;
+            let mtime = filetime::FileTime::from_unix_time(data.mtime as i64, 0);
+            filetime::set_file_mtime(temp.path(), mtime)?;
+            // renaming replaces anything that's there, instead of writing through a symlink
+            temp.persist(&path)?;
+        }
+        EntryKind::Symlink(target) => {
+            let path = resolve_in(root, &dest, false)?;
+            remove_non_dir(&path)?;
+            symlink(target, &path)?;
+        }
+        EntryKind::HardLink(target) => {
+            let source = written
+                .get(target)
+                .ok_or_else(|| anyhow!("link to {:?}, which wasn't unpacked", target))?;
+            let source = resolve_in(root, source, false)?;
+            let path = resolve_in(root, &dest, false)?;
+            remove_non_dir(&path)?;
+            fs::hard_link(source, &path)?;
+        }
+        EntryKind::CharDevice { .. } | EntryKind::BlockDevice { .. } | EntryKind::Fifo => {
+            return Ok(None)
+        }
+    }
+
+    Ok(Some(dest))
+}
+
+/// Find an absolute `path` inside `root`, following symlinks as if `root` was `/`,
+/// and creating any missing parent directories. The last component is only followed
+/// if `follow_last` is set.
+pub(crate) fn resolve_in(root: &Path, path: &str, follow_last: bool) -> Result<PathBuf, Error> {
+    resolve(root, path, follow_last, true)
+}
+
+/// Like [resolve_in], following the last component, but without changing anything.
+pub(crate) fn lookup_in(root: &Path, path: &str) -> Result<PathBuf, Error> {
+    resolve(root, path, true, false)
+}
+
+fn resolve(root: &Path, path: &str, follow_last: bool, create: bool) -> Result<PathBuf, Error> {
+    let mut pending: Vec<String> = path.split('/').rev().map(|s| s.to_string()).collect();
+    let mut resolved: Vec<String> = Vec::new();
+    let mut hops = 0;
+
+    while let Some(part) = pending.pop() {
+        match part.as_str() {
+            "" | "." => continue,
+            ".." => {
+                // as with `/..`, you can't go above the root
+                resolved.pop();
+                continue;
+            }
+            _ => (),
+        }
+
+        let last = pending.iter().all(|rest| rest.is_empty() || "." == rest);
+        let candidate = resolved
+            .iter()
+            .fold(root.to_path_buf(), |p, s| p.join(s))
+            .join(&part);
+
+        match fs::symlink_metadata(&candidate) {
+            Ok(meta) if meta.file_type().is_symlink() && (!last || follow_last) => {
+                hops += 1;
+                ensure!(
+                    hops <= 40,
+                    "too many levels of symbolic links at {:?}",
+                    candidate
+                );
+                let target = fs::read_link(&candidate)?;
+                let target = target
+                    .to_str()
+                    .ok_or_else(|| anyhow!("non-utf-8 link target at {:?}", candidate))?;
+                if target.starts_with('/') {
+                    resolved.clear();
+                }
+                pending.extend(target.split('/').rev().map(|s| s.to_string()));
+            }
+            Ok(meta) if !last && !meta.is_dir() => {
+                bail!("{:?} isn't a directory", candidate)
+            }
+            Ok(_) => resolved.push(part),
+            Err(e) if io::ErrorKind::NotFound == e.kind() => {
+                if !last && create {
+                    fs::create_dir(&candidate)
+                        .with_context(|| anyhow!("creating {:?}", candidate))?;
+                }
+                resolved.push(part);
+            }
+            Err(e) => return Err(e).with_context(|| anyhow!("inspecting {:?}", candidate)),
+        }
+    }
+
+    Ok(resolved.iter().fold(root.to_path_buf(), |p, s| p.join(s)))
+}
+
+fn remove_non_dir(path: &Path) -> Result<(), Error> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => bail!("{:?} is a directory", path),
+        Ok(_) => Ok(fs::remove_file(path)?),
+        Err(e) if io::ErrorKind::NotFound == e.kind() => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn set_mode(path: &Path, mode: u32) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::set_permissions(path, fs::Permissions::from_mode(mode))?)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_mode(_path: &Path, _mode: u32) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> Result<(), Error> {
+    Ok(std::os::unix::fs::symlink(target, path)?)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &str, _path: &Path) -> Result<(), Error> {
+    bail!("unpacking symlinks is only supported on unix")
+}
+
+/// Walks the members of a `.deb`, which must be in order.
+pub(crate) struct DebReader<R: Read> {
+    archive: ar::Archive<R>,
+}
+
+impl<R: Read> DebReader<R> {
+    pub(crate) fn new(from: R) -> Result<DebReader<R>, Error> {
+        let mut archive = ar::Archive::new(from);
+
+        let mut entry = archive
+            .next_entry()
+            .ok_or_else(|| anyhow!("empty archive"))?
+            .with_context(|| anyhow!("reading archive; not a .deb?"))?;
+
+        ensure!(
+            b"debian-binary" == member_name(entry.header().identifier()),
+            "first member must be debian-binary, not {:?}",
+            String::from_utf8_lossy(entry.header().identifier())
+        );
+
+        let mut version = String::new();
+        entry.read_to_string(&mut version)?;
+        ensure!(
+            version.starts_with("2."),
+            "unsupported .deb format version: {:?}",
+            version.trim()
+        );
+
+        drop(entry);
+
+        Ok(DebReader { archive })
+    }
+
+    pub(crate) fn control(&mut self) -> Result<Control, Error> {
+        // not redundant: the closure is needed to be generic over the lifetime
+        #[allow(clippy::redundant_closure)]
+        self.next_tar("control.tar", |archive| Control::from_tar(archive))
+    }
+
+    /// Must be called after `control`.
+    pub(crate) fn data<T, F>(&mut self, each: F) -> Result<T, Error>
+    where
+        F: for<'a> FnOnce(tar::Archive<Box<dyn Read + 'a>>) -> Result<T, Error>,
+    {
+        self.next_tar("data.tar", each)
+    }
+
+    fn next_tar<T, F>(&mut self, wanted: &str, each: F) -> Result<T, Error>
+    where
+        F: for<'a> FnOnce(tar::Archive<Box<dyn Read + 'a>>) -> Result<T, Error>,
+    {
+        loop {
+            let entry = self
+                .archive
+                .next_entry()
+                .ok_or_else(|| anyhow!("no {} member", wanted))??;
+
+            let name = String::from_utf8(member_name(entry.header().identifier()).to_vec())?;
+
+            // members starting with an underscore are reserved for extensions
+            if name.starts_with('_') {
+                continue;
+            }
+
+            ensure!(
+                name.starts_with(wanted),
+                "expected {}, found {:?}",
+                wanted,
+                name
+            );
+
+            let decompressed = decompress(&name[wanted.len()..], entry)
+                .with_context(|| anyhow!("opening {:?}", name))?;
+
+            return each(tar::Archive::new(decompressed))
+                .with_context(|| anyhow!("reading {:?}", name));
+        }
+    }
+}
+
+/// Some `ar` implementations terminate names with a `/`.
+fn member_name(identifier: &[u8]) -> &[u8] {
+    identifier.strip_suffix(b"/").unwrap_or(identifier)
+}
+
+pub(crate) fn decompress<'r, R: Read + 'r>(
+    extension: &str,
+    from: R,
+) -> Result<Box<dyn Read + 'r>, Error> {
+    Ok(match extension {
+        "" => Box::new(from),
+        ".bz2" => Box::new(bzip2::read::BzDecoder::new(from)),
+        ".gz" => Box::new(flate2::read::GzDecoder::new(from)),
+        ".xz" => Box::new(lzma_rust2::XzReader::new(from, true)),
+        ".zst" => Box::new(ruzstd::decoding::StreamingDecoder::new(from)?),
+        other => bail!("unsupported compression: {:?}", other),
+    })
+}
+
+fn entry_path<R: Read>(entry: &tar::Entry<R>) -> Result<String, Error> {
+    let path = entry.path()?;
+    Ok(absolute(
+        path.to_str()
+            .ok_or_else(|| anyhow!("non-utf-8 path: {:?}", path))?,
+    ))
+}
+
+/// `./usr/bin/` -> `/usr/bin`
+pub(crate) fn absolute(path: &str) -> String {
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    let path = path.trim_end_matches('/');
+    if path.is_empty() || "." == path {
+        "/".to_string()
+    } else {
+        format!("/{}", path)
+    }
+}
+
+fn parse_md5sums(text: &str) -> Result<Vec<(String, checksum::MD5)>, Error> {
+    let mut ret = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (hash, path) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("invalid md5sums line: {:?}", line))?;
+        ret.push((absolute(path.trim_start()), checksum::parse_md5(hash)?));
+    }
+    Ok(ret)
+}
+
+fn parse_conffiles(text: &str) -> Result<Vec<Conffile>, Error> {
+    let mut ret = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (remove_on_upgrade, path) = match line.split_once(' ') {
+            Some(("remove-on-upgrade", path)) => (true, path.trim()),
+            Some((flag, _)) if !flag.starts_with('/') => bail!("unknown conffile flag {:?}", flag),
+            _ => (false, line),
+        };
+        ret.push(Conffile {
+            path: path.to_string(),
+            remove_on_upgrade,
+        });
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::absolute;
+    use super::parse_conffiles;
+    use super::resolve_in;
+    use super::Control;
+
+    #[test]
+    fn paths() {
+        assert_eq!("/", absolute("./"));
+        assert_eq!("/", absolute("."));
+        assert_eq!("/usr/bin", absolute("./usr/bin/"));
+        assert_eq!("/usr/bin/hello", absolute("usr/bin/hello"));
+        assert_eq!("/etc/hello.conf", absolute("/etc/hello.conf"));
+    }
+
+    #[test]
+    fn conffiles() {
+        let parsed = parse_conffiles("/etc/a.conf\nremove-on-upgrade /etc/b.conf\n\n").unwrap();
+        assert_eq!(2, parsed.len());
+        assert_eq!("/etc/a.conf", parsed[0].path);
+        assert!(!parsed[0].remove_on_upgrade);
+        assert_eq!("/etc/b.conf", parsed[1].path);
+        assert!(parsed[1].remove_on_upgrade);
+        assert!(parse_conffiles("frobnicate /etc/c.conf").is_err());
+    }
+
+    #[test]
+    fn huge_control_member() {
+        // just a header, claiming a terabyte follows
+        let mut header = tar::Header::new_gnu();
+        header.set_path("control").unwrap();
+        header.set_size(1 << 40);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        let archive = tar::Archive::new(&header.as_bytes()[..]);
+        let err = Control::from_tar(archive).unwrap_err();
+        assert!(err.to_string().contains("too large"), "{:?}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_stay_inside_root() {
+        use std::os::unix::fs::symlink;
+
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        symlink("/outside", root.join("abs")).unwrap();
+        symlink("../../../outside", root.join("rel")).unwrap();
+        symlink("loop", root.join("loop")).unwrap();
+
+        assert_eq!(
+            root.join("outside/file"),
+            resolve_in(root, "/abs/file", false).unwrap()
+        );
+        assert_eq!(
+            root.join("outside/file"),
+            resolve_in(root, "/rel/file", false).unwrap()
+        );
+        assert!(root.join("outside").is_dir());
+
+        // the last component isn't followed, unless asked
+        assert_eq!(root.join("abs"), resolve_in(root, "/abs", false).unwrap());
+        assert_eq!(
+            root.join("outside"),
+            resolve_in(root, "/abs", true).unwrap()
+        );
+
+        assert!(resolve_in(root, "/loop/file", false).is_err());
+
+        fs::write(root.join("file"), "").unwrap();
+        assert!(resolve_in(root, "/file/child", false).is_err());
+    }
+}
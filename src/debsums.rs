@@ -0,0 +1,266 @@
+//! Checking installed files against the hashes `dpkg` recorded, like `debsums`.
+//!
+//! Each installed package's `info/<pkg>.md5sums` lists the hashes of the files it shipped.
+//! Conffiles are listed in `info/<pkg>.conffiles`, and their hashes are kept in the `status`
+//! file. The admin is entitled to edit conffiles, so problems with them are kept separate.
+//! Files diverted away from a package are checked where they were moved to.
+//!
+//! ```no_run
+//! # use fapt::system::System;
+//! let mut fapt = System::cache_only()?;
+//! fapt.set_dpkg_database("/mnt/image/var/lib/dpkg");
+//! let report = fapt.debsums("/mnt/image")?;
+//! for problem in &report.problems {
+//!     println!("{}", problem);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+
+use crate::checksum::HashAlg;
+use crate::deb;
+use crate::ownership;
+use crate::ownership::Diversion;
+use crate::parse::State;
+use crate::status;
+
+/// What a check of the installed files found.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// How many files were looked at, including conffiles.
+    pub checked: usize,
+    /// Problems with the files listed in `md5sums`.
+    pub problems: Vec<Problem>,
+    /// Problems with conffiles, which may well be deliberate.
+    pub conffiles: Vec<Problem>,
+    /// Installed packages with no `md5sums` to check against.
+    pub unverified: Vec<String>,
+}
+
+/// Something wrong with an installed file. Paths are absolute, as if the root was `/`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    Modified {
+        package: String,
+        path: String,
+    },
+    Missing {
+        package: String,
+        path: String,
+    },
+    /// The file couldn't be hashed, or there's nothing to compare it to.
+    Unverifiable {
+        package: String,
+        path: String,
+        reason: String,
+    },
+}
+
+impl Problem {
+    pub fn package(&self) -> &str {
+        match self {
+            Problem::Modified { package, .. } => package,
+            Problem::Missing { package, .. } => package,
+            Problem::Unverifiable { package, .. } => package,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            Problem::Modified { path, .. } => path,
+            Problem::Missing { path, .. } => path,
+            Problem::Unverifiable { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Modified { package, path } => write!(f, "{}: modified ({})", path, package),
+            Problem::Missing { package, path } => write!(f, "{}: missing ({})", path, package),
+            Problem::Unverifiable {
+                package,
+                path,
+                reason,
+            } => write!(f, "{}: unverifiable: {} ({})", path, reason, package),
+        }
+    }
+}
+
+/// Check the files of every package in the `dpkg` database, as installed under `root`.
+pub(crate) fn check(status: &status::Database, dpkg: &Path, root: &Path) -> Result<Report, Error> {
+    let diversions = ownership::diversions_in(dpkg)?;
+    let diversions: HashMap<&str, &Diversion> = diversions
+        .iter()
+        .map(|diversion| (diversion.from.as_str(), diversion))
+        .collect();
+
+    let mut report = Report::default();
+    for entry in status.entries() {
+        // half-installed packages may have some of their files
+        if let State::NotInstalled | State::ConfigFiles = entry.status.state {
+            continue;
+        }
+        check_package(dpkg, root, &diversions, entry, &mut report)
+            .with_context(|| anyhow!("checking {:?}", entry.package))?;
+    }
+    Ok(report)
+}
+
+fn check_package(
+    dpkg: &Path,
+    root: &Path,
+    diversions: &HashMap<&str, &Diversion>,
+    entry: &status::Entry,
+    report: &mut Report,
+) -> Result<(), Error> {
+    let name = entry.info_name();
+    let info = dpkg.join("info");
+
+    // where the package's copy of `path` really is
+    let real_path = |path: &str| match diversions.get(path) {
+        Some(diversion) if !ownership::diverts(diversion, &name) => diversion.to.to_string(),
+        _ => path.to_string(),
+    };
+
+    // conffile -> the hash dpkg recorded, if any
+    let mut conffiles = BTreeMap::new();
+    if let Some(data) = read_optional(&info.join(format!("{}.conffiles", name)))? {
+        for line in String::from_utf8_lossy(&data)
+            .lines()
+            .filter(|line| !line.is_empty())
+        {
+            // newer dpkg may flag the path
+            let path = line.strip_prefix("remove-on-upgrade ").unwrap_or(line);
+            conffiles.insert(path.to_string(), None);
+        }
+    }
+    for conffile in &entry.conffiles {
+        if conffile.obsolete {
+            conffiles.remove(&conffile.path);
+        } else {
+            conffiles.insert(conffile.path.to_string(), conffile.md5.as_deref());
+        }
+    }
+
+    for (path, hash) in &conffiles {
+        report.checked += 1;
+        let problem = match hash {
+            Some(hash) => check_file(root, &name, &real_path(path), hash),
+            None => Some(Problem::Unverifiable {
+                package: name.to_string(),
+                path: path.to_string(),
+                reason: "no hash recorded".to_string(),
+            }),
+        };
+        report.conffiles.extend(problem);
+    }
+
+    let md5sums = match read_optional(&info.join(format!("{}.md5sums", name)))? {
+        Some(md5sums) => md5sums,
+        None => {
+            report.unverified.push(name);
+            return Ok(());
+        }
+    };
+
+    for line in md5sums.split(|&b| b'\n' == b) {
+        if line.is_empty() {
+            continue;
+        }
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line,
+            Err(_) => {
+                // the path can't be what's on disk once mangled, so can't be checked
+                let line = String::from_utf8_lossy(line);
+                let path = line
+                    .split_once("  ")
+                    .map_or(line.as_ref(), |(_, path)| path);
+                report.problems.push(Problem::Unverifiable {
+                    package: name.to_string(),
+                    path: format!("/{}", path.trim_start_matches('/')),
+                    reason: "non-utf-8 path".to_string(),
+                });
+                continue;
+            }
+        };
+        let (hash, path) = line
+            .split_once("  ")
+            .ok_or_else(|| anyhow!("invalid md5sums line: {:?}", line))?;
+        let path = format!("/{}", path.trim_start_matches('/'));
+        if conffiles.contains_key(&path) {
+            continue;
+        }
+        report.checked += 1;
+        report
+            .problems
+            .extend(check_file(root, &name, &real_path(&path), hash));
+    }
+
+    Ok(())
+}
+
+/// Compare the file at `path`, under `root`, to the `expected` md5sum.
+fn check_file(root: &Path, package: &str, path: &str, expected: &str) -> Option<Problem> {
+    let unverifiable = |reason: String| Problem::Unverifiable {
+        package: package.to_string(),
+        path: path.to_string(),
+        reason,
+    };
+    if path.split('/').any(|part| ".." == part) {
+        return Some(unverifiable("outside the root".to_string()));
+    }
+    // as when unpacking, an absolute symlink in the image points inside the image
+    let full = match deb::lookup_in(root, path) {
+        Ok(full) => full,
+        Err(e) => return Some(unverifiable(e.to_string())),
+    };
+    match fs::symlink_metadata(&full) {
+        Ok(meta) if meta.is_file() => (),
+        Ok(_) => return Some(unverifiable("not a regular file".to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Some(Problem::Missing {
+                package: package.to_string(),
+                path: path.to_string(),
+            })
+        }
+        Err(e) => return Some(unverifiable(e.to_string())),
+    }
+
+    let actual = match fs::File::open(&full)
+        .map_err(Error::from)
+        .and_then(|file| HashAlg::Md5.digest(file))
+    {
+        Ok(actual) => actual,
+        Err(e) => return Some(unverifiable(e.to_string())),
+    };
+
+    if hex::encode(actual).eq_ignore_ascii_case(expected) {
+        None
+    } else {
+        Some(Problem::Modified {
+            package: package.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+/// An info file, which some packages don't have.
+fn read_optional(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    match fs::read(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::from(e).context(anyhow!("reading {:?}", path))),
+    }
+}
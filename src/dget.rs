@@ -0,0 +1,146 @@
+//! Fetch complete source packages, like `dget`.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+
+use crate::archives;
+use crate::checksum::HashAlg;
+use crate::checksum::MD5;
+use crate::checksum::SHA256;
+use crate::fetch;
+use crate::parse::Package;
+use crate::signing;
+use crate::signing::SignatureStatus;
+use crate::system::DownloadedList;
+use crate::system::DownloadedSource;
+
+pub(crate) async fn download_source(
+    client: &fetch::Client,
+    archives_dir: &Path,
+    pkg: &Package,
+    list: &DownloadedList,
+    dest: &Path,
+    maintainer_keys: Option<&signing::Keys>,
+    policy: &signing::SignaturePolicy,
+) -> Result<DownloadedSource, Error> {
+    let src = pkg
+        .as_src()
+        .ok_or_else(|| anyhow!("{:?} is not a source package", pkg.name))?;
+
+    let directory = src
+        .directory
+        .as_ref()
+        .ok_or_else(|| anyhow!("{:?} has no Directory", pkg.name))?;
+
+    let dsc_name = src
+        .files
+        .iter()
+        .map(|file| file.name.as_str())
+        .find(|name| name.ends_with(".dsc"))
+        .ok_or_else(|| anyhow!("{:?} has no .dsc", pkg.name))?;
+
+    let files = src
+        .files
+        .iter()
+        .map(|file| {
+            // copied into dest, below
+            ensure!(
+                !file.name.contains('/') && "." != file.name && ".." != file.name,
+                "refusing to write outside the directory: {:?}",
+                file.name
+            );
+            Ok(archives::PoolFile {
+                url: list
+                    .release
+                    .req
+                    .pool_url(&format!("{}/{}", directory, file.name))?,
+                len: file.size,
+                alg: HashAlg::Sha256,
+                hash: file
+                    .sha256
+                    .ok_or_else(|| anyhow!("no sha256 for {:?}; refusing", file.name))?
+                    .to_vec(),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let cached = archives::download(client, archives_dir, &files).await?;
+
+    fs::create_dir_all(dest).with_context(|| anyhow!("creating {:?}", dest))?;
+
+    for (file, cached) in src.files.iter().zip(cached) {
+        fs::copy(&cached, dest.join(&file.name))
+            .with_context(|| anyhow!("copying {:?} into {:?}", file.name, dest))?;
+    }
+
+    let dsc = dest.join(dsc_name);
+
+    let from = io::BufReader::new(fs::File::open(&dsc)?);
+    let (parsed, signature) = match maintainer_keys {
+        Some(keys) => Package::read_dsc_by(from, keys, policy)
+            .map(|(parsed, _)| (parsed, SignatureStatus::Verified)),
+        None => Package::read_dsc(from, None),
+    }
+    .with_context(|| anyhow!("reading {:?}", dsc))?;
+
+    check_against_index(&parsed, pkg)
+        .with_context(|| anyhow!("checking {:?} against the index", dsc))?;
+
+    Ok(DownloadedSource {
+        dir: dest.to_path_buf(),
+        dsc,
+        signature,
+    })
+}
+
+/// The `.dsc` should describe the same package, with the same files, as the index.
+fn check_against_index(dsc: &Package, pkg: &Package) -> Result<(), Error> {
+    ensure!(
+        dsc.name == pkg.name,
+        "source name mismatch: index: {:?}, dsc: {:?}",
+        pkg.name,
+        dsc.name
+    );
+
+    ensure!(
+        dsc.version == pkg.version,
+        "version mismatch: index: {:?}, dsc: {:?}",
+        pkg.version,
+        dsc.version
+    );
+
+    let indexed = file_set(pkg)?;
+    let listed = file_set(dsc)?;
+
+    ensure!(
+        indexed == listed,
+        "file list mismatch: index: {:?}, dsc: {:?}",
+        indexed,
+        listed
+    );
+
+    Ok(())
+}
+
+type FileKey<'p> = (&'p str, u64, MD5, Option<SHA256>);
+
+/// The files, other than the `.dsc` itself, with the hashes both sides should have.
+///
+/// We've already insisted on sha256s in the index, so a `.dsc` lacking them will not match.
+fn file_set(pkg: &Package) -> Result<HashSet<FileKey<'_>>, Error> {
+    Ok(pkg
+        .as_src()
+        .ok_or_else(|| anyhow!("{:?} is not a source package", pkg.name))?
+        .files
+        .iter()
+        .filter(|file| !file.name.ends_with(".dsc"))
+        .map(|file| (file.name.as_str(), file.size, file.md5, file.sha256))
+        .collect())
+}
@@ -1,4 +1,7 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
+use std::future::Future;
 use std::io;
 use std::io::Write;
 use std::path::Path;
@@ -6,13 +9,85 @@ use std::path::PathBuf;
 
 use anyhow::anyhow;
 use anyhow::bail;
+use anyhow::ensure;
 use anyhow::Context;
 use anyhow::Error;
 use chrono::DateTime;
 use chrono::Utc;
+use futures_util::stream;
+use futures_util::StreamExt;
+use futures_util::TryStreamExt;
 use reqwest::header;
 use tempfile_fast::PersistableTempFile;
 
+use crate::auth::Credentials;
+use crate::proxy::ProxyConfig;
+use crate::tls::TlsConfig;
+
+/// How many requests we'll have in flight at once.
+pub(crate) const PARALLEL_DOWNLOADS: usize = 8;
+
+/// http clients for each host, and the credentials to present to each server.
+pub struct Client {
+    default: reqwest::Client,
+    by_host: HashMap<String, reqwest::Client>,
+    credentials: Credentials,
+}
+
+impl Client {
+    pub fn new(
+        proxies: &ProxyConfig,
+        tls: &HashMap<String, TlsConfig>,
+        credentials: Credentials,
+    ) -> Result<Self, Error> {
+        let builder = || {
+            reqwest::Client::builder()
+                .no_proxy()
+                .proxy(proxies.to_reqwest())
+        };
+
+        let mut by_host = HashMap::with_capacity(tls.len());
+        for (host, config) in tls {
+            by_host.insert(
+                host.to_ascii_lowercase(),
+                config
+                    .apply(builder())
+                    .and_then(|builder| Ok(builder.build()?))
+                    .with_context(|| anyhow!("configuring tls for {:?}", host))?,
+            );
+        }
+
+        Ok(Client {
+            default: builder().build()?,
+            by_host,
+            credentials,
+        })
+    }
+
+    fn http_for(&self, url: &reqwest::Url) -> &reqwest::Client {
+        url.host_str()
+            .and_then(|host| self.by_host.get(&host.to_ascii_lowercase()))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// The server answered, and said it doesn't have the file; as opposed to not answering.
+#[derive(Debug)]
+pub(crate) struct NotFound(reqwest::Url);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} isn't on the server", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// Is `err` because the file isn't there, rather than because we couldn't ask?
+pub(crate) fn is_not_found(err: &Error) -> bool {
+    err.chain().any(|cause| cause.is::<NotFound>())
+}
+
 pub struct Download {
     from: reqwest::Url,
     to: PathBuf,
@@ -27,33 +102,104 @@ impl Download {
     }
 }
 
-pub fn fetch(client: &reqwest::blocking::Client, downloads: &[Download]) -> Result<(), Error> {
-    // TODO: reqwest parallel API, when it's stable
+/// Drive some async operation to completion, for the blocking API.
+///
+/// A new runtime (and hence a new client) is used each time, as `reqwest`
+/// gets upset if its connection pool outlives the runtime that created it.
+/// Runtimes can't be nested, so this fails if called from inside one.
+pub fn block_on<T, F: Future<Output = Result<T, Error>>>(future: F) -> Result<T, Error> {
+    ensure!(
+        tokio::runtime::Handle::try_current().is_err(),
+        "blocking inside an async runtime; use the _async method instead"
+    );
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .with_context(|| anyhow!("starting runtime"))?
+        .block_on(future)
+}
 
-    for download in downloads {
-        write!(io::stderr(), "Downloading: {} ... ", download.from)?;
-        io::stderr().flush()?;
-        fetch_single(client, download)
-            .with_context(|| anyhow!("downloading {} to {:?}", download.from, download.to))?;
-    }
+pub async fn fetch(client: &Client, downloads: &[Download]) -> Result<(), Error> {
+    // collected first, so the stream isn't generic over the closure's lifetimes
+    let pending: Vec<_> = downloads
+        .iter()
+        .map(|download| fetch_logged(client, download))
+        .collect();
 
+    stream::iter(pending)
+        .buffer_unordered(PARALLEL_DOWNLOADS)
+        .try_collect::<Vec<()>>()
+        .await?;
+
+    Ok(())
+}
+
+async fn fetch_logged(client: &Client, download: &Download) -> Result<(), Error> {
+    let outcome = fetch_single(client, download)
+        .await
+        .with_context(|| anyhow!("downloading {} to {:?}", download.from, download.to))?;
+    writeln!(
+        io::stderr(),
+        "Downloading: {} ... {}",
+        download.from,
+        outcome
+    )?;
     Ok(())
 }
 
-fn fetch_single(client: &reqwest::blocking::Client, download: &Download) -> Result<(), Error> {
-    let mut req = client.get(download.from.as_ref());
+/// The length of a file on the server, without downloading it, if the server says.
+pub async fn content_length(client: &Client, url: &reqwest::Url) -> Result<Option<u64>, Error> {
+    let resp = request(client, reqwest::Method::HEAD, url)
+        .send()
+        .await
+        .with_context(|| anyhow!("initiating request"))?;
+
+    let status = resp.status();
+    if reqwest::StatusCode::NOT_FOUND == status {
+        return Err(NotFound(url.clone()).into());
+    } else if !status.is_success() {
+        bail!("couldn't find {}: server responded with {:?}", url, status);
+    }
+
+    // not `content_length()`, which describes the (empty) body
+    Ok(match resp.headers().get(header::CONTENT_LENGTH) {
+        Some(len) => Some(len.to_str()?.parse()?),
+        None => None,
+    })
+}
+
+fn request(
+    client: &Client,
+    method: reqwest::Method,
+    url: &reqwest::Url,
+) -> reqwest::RequestBuilder {
+    let mut req = client.http_for(url).request(method, url.as_ref());
+
+    if let Some((login, password)) = client.credentials.find(url) {
+        req = req.basic_auth(login, Some(password));
+    }
+
+    req
+}
+
+async fn fetch_single(client: &Client, download: &Download) -> Result<&'static str, Error> {
+    let mut req = request(client, reqwest::Method::GET, &download.from);
 
     if download.to.exists() {
         let when: DateTime<Utc> = ::chrono::DateTime::from(download.to.metadata()?.modified()?);
         req = req.header(header::IF_MODIFIED_SINCE, when.to_rfc2822());
     }
 
-    let mut resp = req.send().with_context(|| anyhow!("initiating request"))?;
+    let mut resp = req
+        .send()
+        .await
+        .with_context(|| anyhow!("initiating request"))?;
 
     let status = resp.status();
     if reqwest::StatusCode::NOT_MODIFIED == status {
-        writeln!(io::stderr(), "already up to date.")?;
-        return Ok(());
+        return Ok("already up to date.");
+    } else if reqwest::StatusCode::NOT_FOUND == status {
+        return Err(NotFound(download.from.clone()).into());
     } else if !status.is_success() {
         bail!(
             "couldn't download {}: server responded with {:?}",
@@ -61,34 +207,97 @@ fn fetch_single(client: &reqwest::blocking::Client, download: &Download) -> Resu
             status
         );
     }
-    let parent = download
-        .to
+
+    let mut tmp = temp_file_for(&download.to, resp.headers())?;
+
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .with_context(|| anyhow!("reading response"))?
+    {
+        tmp.write_all(&chunk)
+            .with_context(|| anyhow!("copying data"))?;
+    }
+
+    persist(tmp, &download.to, resp.headers())?;
+
+    Ok("complete.")
+}
+
+fn temp_file_for(to: &Path, headers: &header::HeaderMap) -> Result<PersistableTempFile, Error> {
+    let parent = to
         .parent()
         .ok_or_else(|| anyhow!("path must have parent"))?;
 
     fs::create_dir_all(parent).with_context(|| anyhow!("creating directories: {:?}", parent))?;
 
-    let mut tmp = PersistableTempFile::new_in(parent)
+    let tmp = PersistableTempFile::new_in(parent)
         .with_context(|| anyhow!("couldn't create temporary file"))?;
 
-    if let Some(len) = resp.headers().get(header::CONTENT_LENGTH) {
+    if let Some(len) = headers.get(header::CONTENT_LENGTH) {
         tmp.set_len(len.to_str()?.parse()?)
             .with_context(|| anyhow!("pretending to allocate space"))?;
     }
 
-    io::copy(&mut resp, &mut tmp).with_context(|| anyhow!("copying data"))?;
+    Ok(tmp)
+}
 
-    tmp.persist_by_rename(&download.to)
+fn persist(tmp: PersistableTempFile, to: &Path, headers: &header::HeaderMap) -> Result<(), Error> {
+    tmp.persist_by_rename(to)
         .map_err(|e| e.error)
         .with_context(|| anyhow!("persisting result"))?;
 
-    if let Some(modified) = resp.headers().get(header::LAST_MODIFIED) {
+    if let Some(modified) = headers.get(header::LAST_MODIFIED) {
         let date = DateTime::parse_from_rfc2822(modified.to_str()?)?;
         let file_time = filetime::FileTime::from_unix_time(date.timestamp(), 0);
-        filetime::set_file_times(&download.to, file_time, file_time)?;
+        filetime::set_file_times(to, file_time, file_time)?;
     }
 
-    writeln!(io::stderr(), "complete.")?;
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ptr;
+
+    use reqwest::Url;
+
+    use super::block_on;
+    use super::Client;
+    use crate::auth::Credentials;
+    use crate::proxy::ProxyConfig;
+    use crate::tls::TlsConfig;
+
+    #[test]
+    fn http_for() {
+        let tls = HashMap::from([(
+            "Deb.Corp".to_string(),
+            TlsConfig {
+                danger_accept_invalid_certs: true,
+                ..TlsConfig::default()
+            },
+        )]);
+        let client = Client::new(&ProxyConfig::default(), &tls, Credentials::default()).unwrap();
+        let http_for = |url: &str| client.http_for(&Url::parse(url).unwrap());
+
+        let corp = &client.by_host["deb.corp"];
+        assert!(ptr::eq(corp, http_for("https://deb.corp/debian/")));
+        assert!(ptr::eq(corp, http_for("https://DEB.corp:8443/debian/")));
+        assert!(ptr::eq(
+            &client.default,
+            http_for("https://deb.debian.org/")
+        ));
+        assert!(ptr::eq(&client.default, http_for("https://corp/")));
+    }
+
+    #[test]
+    fn nested_block_on() {
+        assert_eq!(1, block_on(async { Ok(1) }).unwrap());
+        let outer = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let inner = outer.block_on(async { block_on(async { Ok(2) }) });
+        assert!(inner.is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Replacing files which others may be reading at the time.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use tempfile_fast::PersistableTempFile;
+
+/// Replace `path` with `data`, so readers see either the old file or the new one, never a
+/// partial write. The new file is readable by everyone.
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> Result<(), Error> {
+    write_atomically_with(path, data, |file| set_mode(file, 0o644))
+}
+
+/// [write_atomically], keeping the permissions of the file being replaced, if there is one.
+pub(crate) fn replace_atomically(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let existing = match fs::metadata(path) {
+        Ok(meta) => Some(meta.permissions()),
+        Err(e) if io::ErrorKind::NotFound == e.kind() => None,
+        Err(e) => return Err(e).with_context(|| anyhow!("inspecting {:?}", path)),
+    };
+    write_atomically_with(path, data, |file| match existing {
+        Some(permissions) => Ok(file.set_permissions(permissions)?),
+        None => set_mode(file, 0o644),
+    })
+}
+
+/// Write `data` to a temporary file beside `path`, let `permissions` set its mode, then
+/// rename it over `path`, so it's never in place with the wrong mode.
+fn write_atomically_with<F: FnOnce(&fs::File) -> Result<(), Error>>(
+    path: &Path,
+    data: &[u8],
+    permissions: F,
+) -> Result<(), Error> {
+    let dir = path.parent().expect("files are in directories");
+    let mut temp = PersistableTempFile::new_in(dir)
+        .with_context(|| anyhow!("making temporary file in {:?}", dir))?;
+    temp.write_all(data)?;
+    // temporary files are private
+    permissions(&temp).with_context(|| anyhow!("setting the mode of {:?}", path))?;
+    temp.persist_by_rename(path)
+        .map_err(|e| e.error)
+        .with_context(|| anyhow!("writing {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(file: &fs::File, mode: u32) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(file.set_permissions(fs::Permissions::from_mode(mode))?)
+}
+
+#[cfg(not(unix))]
+fn set_mode(_file: &fs::File, _mode: u32) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::replace_atomically;
+    use super::write_atomically;
+
+    #[test]
+    fn modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status");
+        let mode = || fs::metadata(&path).unwrap().permissions().mode() & 0o7777;
+        write_atomically(&path, b"one").unwrap();
+        assert_eq!(0o644, mode());
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        write_atomically(&path, b"two").unwrap();
+        assert_eq!(0o644, mode());
+        assert_eq!(b"two", &fs::read(&path).unwrap()[..]);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        replace_atomically(&path, b"three").unwrap();
+        assert_eq!(0o755, mode());
+        replace_atomically(&dir.path().join("new"), b"four").unwrap();
+        assert_eq!(
+            0o644,
+            fs::metadata(dir.path().join("new"))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        );
+    }
+}
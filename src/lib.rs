@@ -5,13 +5,30 @@
 #[macro_use]
 extern crate nom;
 
+pub mod alternatives;
+mod archives;
+mod auth;
 mod checksum;
 pub mod commands;
+pub mod deb;
+pub mod debsums;
+mod dget;
 mod fetch;
+mod files;
 mod lists;
+pub mod mirror;
+mod openpgp;
+pub mod ownership;
 pub mod parse;
+mod patch;
+pub mod proxy;
+pub mod publish;
 mod release;
 pub mod rfc822;
-mod signing;
+pub mod selections;
+pub mod signing;
+pub mod source;
 pub mod sources_list;
+pub mod status;
 pub mod system;
+pub mod tls;
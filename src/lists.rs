@@ -11,7 +11,6 @@ use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Error;
 use flate2::bufread::GzDecoder;
-use reqwest::blocking::Client;
 use reqwest::Url;
 use tempfile_fast::PersistableTempFile;
 
@@ -64,8 +63,25 @@ pub struct Listing {
     pub name: String,
 }
 
-pub fn download_files<P: AsRef<Path>>(
-    client: &Client,
+impl Listing {
+    /// The directory holding the listing, relative to the `Release`, e.g. `main/binary-amd64`.
+    pub fn dir(&self) -> String {
+        let directory = self
+            .arch
+            .as_ref()
+            .map(|arch| format!("{}-{}", self.directory, arch))
+            .unwrap_or_else(|| self.directory.to_string());
+        format!("{}/{}", self.component, directory)
+    }
+
+    /// The uncompressed listing, relative to the `Release`, e.g. `main/binary-amd64/Packages`.
+    pub fn path(&self) -> String {
+        format!("{}/{}", self.dir(), self.name)
+    }
+}
+
+pub async fn download_files<P: AsRef<Path>>(
+    client: &fetch::Client,
     lists_dir: P,
     releases: &[Release],
 ) -> Result<(), Error> {
@@ -92,7 +108,9 @@ pub fn download_files<P: AsRef<Path>>(
         })
         .collect();
 
-    fetch::fetch(client, &downloads).with_context(|| anyhow!("downloading listed files"))?;
+    fetch::fetch(client, &downloads)
+        .await
+        .with_context(|| anyhow!("downloading listed files"))?;
 
     for list in lists {
         store_list_item(&list, &temp_dir, &lists_dir)?;
@@ -242,13 +260,8 @@ pub fn find_file(
     acquire_by_hash: bool,
     listing: &Listing,
 ) -> Result<DownloadableListing, Error> {
-    let directory = listing
-        .arch
-        .as_ref()
-        .map(|arch| format!("{}-{}", listing.directory, arch))
-        .unwrap_or_else(|| listing.directory.to_string());
-
-    let base = format!("{}/{}/{}", listing.component, directory, listing.name);
+    let directory = listing.dir();
+    let base = listing.path();
 
     let gz_name = format!("{}{}", base, Compression::Gz.suffix());
 
@@ -267,8 +280,7 @@ pub fn find_file(
 
     let url = base_url.join(&if acquire_by_hash {
         format!(
-            "{}/{}/by-hash/SHA256/{}",
-            listing.component,
+            "{}/by-hash/SHA256/{}",
             directory,
             hex::encode(gz_hashes.unwrap_or(raw_hashes).sha256)
         )
@@ -0,0 +1,1195 @@
+//! Keeping a partial copy of a repository, like `debmirror`.
+//!
+//! The configured suites, components and architectures are copied, laid out as on the
+//! mirror, so the copy can be served as-is. `InRelease` and `Release.gpg` are copied
+//! unchanged, so clients still check the copy against the original keys. This means the
+//! listings are copied unchanged too, and will mention packages which weren't selected.
+//!
+//! ```no_run
+//! # use std::collections::HashSet;
+//! # use fapt::mirror::Selection;
+//! # use fapt::system::System;
+//! let mut fapt = System::cache_only()?;
+//! fapt::commands::add_sources_entries_from_str(
+//!     &mut fapt,
+//!     "deb http://deb.debian.org/debian bookworm main",
+//! )?;
+//! fapt.set_arches(&["amd64"]);
+//! fapt::commands::add_builtin_keys(&mut fapt);
+//!
+//! let wanted: HashSet<String> = vec!["curl".to_string()].into_iter().collect();
+//! let report = fapt.mirror("/srv/mirror", &Selection::Closure(wanted))?;
+//! println!("{} new files, {} pruned", report.downloaded.len(), report.pruned.len());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use futures_util::future;
+use futures_util::stream;
+use futures_util::StreamExt;
+use futures_util::TryStreamExt;
+use reqwest::Url;
+use tempfile_fast::PersistableTempFile;
+
+use crate::archives::PoolFile;
+use crate::checksum;
+use crate::checksum::Digests;
+use crate::checksum::HashAlg;
+use crate::deb;
+use crate::fetch;
+use crate::lists;
+use crate::parse::Package;
+use crate::release;
+use crate::release::Release;
+use crate::release::RequestedRelease;
+use crate::release::RequestedReleases;
+use crate::rfc822;
+use crate::signing;
+use crate::signing::GpgClient;
+use crate::signing::Keys;
+use crate::signing::SignaturePolicy;
+use crate::sources_list::Entry;
+
+/// The compressions we'll copy a listing in, if the `Release` mentions them.
+const COMPRESSIONS: &[&str] = &["", ".gz", ".xz", ".bz2", ".zst"];
+
+/// Which packages to copy from the pool.
+#[derive(Clone, Debug)]
+pub enum Selection {
+    /// Everything the selected listings mention.
+    All,
+    /// Just these binary packages, any source packages with these names, and the
+    /// source packages the binary packages were built from.
+    Packages(HashSet<String>),
+    /// As `Packages`, but also everything these binary packages depend on.
+    ///
+    /// Every alternative is followed, and virtual packages bring in all of their providers,
+    /// as there's nothing here to choose between them. Versions are ignored.
+    Closure(HashSet<String>),
+}
+
+/// What changed in the copy.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// Files fetched from the mirror, relative to the root.
+    pub downloaded: Vec<String>,
+    /// Files removed, as nothing references them any more, relative to the root.
+    pub pruned: Vec<PathBuf>,
+}
+
+/// What a check of a mirror found.
+#[derive(Clone, Debug, Default)]
+pub struct CheckReport {
+    /// How many files were looked at, including `by-hash` aliases.
+    pub checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+/// Something wrong with a file on a mirror. Paths are relative to the mirror's root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    Missing {
+        path: String,
+    },
+    /// The server failed to say whether it has the file, e.g. it timed out, or errored.
+    Unreachable {
+        path: String,
+        reason: String,
+    },
+    /// Shorter than expected, e.g. an interrupted upload.
+    Truncated {
+        path: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// The wrong size, or the wrong contents.
+    Mismatched {
+        path: String,
+        reason: String,
+    },
+}
+
+impl Problem {
+    pub fn path(&self) -> &str {
+        match self {
+            Problem::Missing { path } => path,
+            Problem::Unreachable { path, .. } => path,
+            Problem::Truncated { path, .. } => path,
+            Problem::Mismatched { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Missing { path } => write!(f, "{}: missing", path),
+            Problem::Unreachable { path, reason } => write!(f, "{}: unreachable: {}", path, reason),
+            Problem::Truncated {
+                path,
+                expected,
+                actual,
+            } => write!(f, "{}: truncated: {} of {} bytes", path, actual, expected),
+            Problem::Mismatched { path, reason } => write!(f, "{}: {}", path, reason),
+        }
+    }
+}
+
+/// Something which should be in the copy.
+#[derive(Clone)]
+struct Wanted {
+    len: u64,
+    alg: HashAlg,
+    hash: Vec<u8>,
+    /// Pool files are usually only checked by size, as they never change, and re-reading
+    /// the whole pool would be slow.
+    size_only: bool,
+}
+
+/// Update the copy of the `requested` releases at `root`.
+///
+/// Nothing is changed until all of the indices are downloaded and verified. The new pool
+/// files go in first, then the listings, then the `Release` files, so the copy is usable
+/// throughout. Anything under `dists` or `pool` that's no longer needed is then removed,
+/// and the copy checked.
+///
+/// When a `Release` changes, the `by-hash` files the old one listed are kept until the next
+/// run, so clients which have just fetched the old one can still fetch what it lists.
+pub(crate) async fn mirror(
+    client: &fetch::Client,
+    keys: &Keys,
+    policy: &SignaturePolicy,
+    requested: &RequestedReleases,
+    root: &Path,
+    selection: &Selection,
+) -> Result<Report, Error> {
+    fs::create_dir_all(root).with_context(|| anyhow!("creating {:?}", root))?;
+    let staging = tempfile::Builder::new()
+        .prefix(".fapt-mirror")
+        .tempdir_in(root)
+        .with_context(|| anyhow!("creating temporary directory"))?;
+
+    let mut report = Report::default();
+    let mut wanted = BTreeMap::new();
+    let mut previous = HashSet::new();
+
+    // relative path -> where to get it from, in the order they should be put in place
+    let mut listings = Vec::new();
+    let mut releases = Vec::new();
+    let mut pool: BTreeMap<String, PoolFile> = BTreeMap::new();
+
+    let mut mirror_url: Option<&Url> = None;
+    for (n, (req, entries)) in requested.releases.iter().enumerate() {
+        match mirror_url {
+            Some(url) => ensure!(
+                url == &req.mirror,
+                "all sources entries must be for the same mirror: {} and {}",
+                url,
+                req.mirror
+            ),
+            None => mirror_url = Some(&req.mirror),
+        }
+
+        let dir = staging.path().join(n.to_string());
+        fs::create_dir(&dir)?;
+        let dists = format!("dists/{}", req.codename);
+
+        let (release, signed) = fetch_release(client, keys, policy, req, entries, &dir)
+            .await
+            .with_context(|| anyhow!("fetching the release for {:?}", req.codename))?;
+
+        let mut changed = false;
+        for name in signed {
+            let from = dir.join(name);
+            let digests = Digests::of(fs::File::open(&from)?)?;
+            let path = format!("{}/{}", dists, name);
+            // always fetched, but only interesting if they've changed
+            if fs::read(root.join(&path)).ok() != Some(fs::read(&from)?) {
+                report.downloaded.push(path.clone());
+                changed = true;
+            }
+            wanted.insert(path.clone(), index_entry(&digests));
+            releases.push((path, from));
+        }
+
+        if changed {
+            previous.extend(
+                previous_by_hash(root, &dists)
+                    .with_context(|| anyhow!("reading the copy's release for {:?}", dists))?,
+            );
+        }
+
+        let algs = release_algs(&verified_text(&dir)?)?;
+        let mut binaries = Vec::new();
+        let mut sources = Vec::new();
+
+        for listing in lists::selected_listings(&release) {
+            let found = fetch_listing(client, root, &release, &listing, &dists, &dir, &algs)
+                .await
+                .with_context(|| anyhow!("fetching {:?} in {:?}", listing, req.codename))?;
+
+            let mut readable = None;
+            for (path, from, downloaded, digests) in found {
+                if downloaded {
+                    report.downloaded.push(path.clone());
+                }
+                let extension = path.strip_prefix(&format!("{}/{}", dists, listing.path()));
+                if let (None, Some(extension)) = (&readable, extension) {
+                    if COMPRESSIONS.contains(&extension) {
+                        readable = Some((extension.to_string(), from.clone()));
+                    }
+                }
+                wanted.insert(path.clone(), index_entry(&digests));
+                listings.push((path, from));
+            }
+
+            let (extension, from) = readable.expect("at least one compression is present");
+            let packages = read_listing(&extension, &from)
+                .with_context(|| anyhow!("reading {:?}", listing))?;
+            if listing.arch.is_some() {
+                binaries.extend(packages);
+            } else {
+                sources.extend(packages);
+            }
+        }
+
+        for (path, file) in pool_files(&release.req, &binaries, &sources, selection)? {
+            if let Some(existing) = pool.get(&path) {
+                ensure!(
+                    existing.len == file.len
+                        && (existing.alg != file.alg || existing.hash == file.hash),
+                    "{:?} is listed with different contents",
+                    path
+                );
+            }
+            wanted.insert(
+                path.clone(),
+                Wanted {
+                    len: file.len,
+                    alg: file.alg,
+                    hash: file.hash.clone(),
+                    size_only: true,
+                },
+            );
+            pool.insert(path, file);
+        }
+    }
+
+    fetch_pool(client, root, staging.path(), &pool, &mut report).await?;
+
+    for (path, from) in listings.iter().chain(&releases) {
+        let to = root.join(path);
+        // `by-hash` names say what's in them, so are left alone once present
+        let current = &to == from || (path.contains("/by-hash/") && to.exists());
+        if !current {
+            install(from, &to).with_context(|| anyhow!("installing {:?}", path))?;
+        }
+    }
+
+    for dir in &["dists", "pool"] {
+        prune(
+            root,
+            &root.join(dir),
+            &wanted,
+            &previous,
+            &mut report.pruned,
+        )?;
+    }
+
+    check(root, &wanted)?;
+
+    Ok(report)
+}
+
+/// Fetch whichever of `InRelease`, and `Release` with `Release.gpg`, the mirror has,
+/// checking the signatures, and that they agree. As when updating, an `InRelease` which
+/// can't be verified is skipped if there's a `Release.gpg` to fall back to.
+///
+/// Returns the parsed release, and which of the files were fetched.
+async fn fetch_release(
+    client: &fetch::Client,
+    keys: &Keys,
+    policy: &SignaturePolicy,
+    req: &RequestedRelease,
+    entries: &[Entry],
+    dir: &Path,
+) -> Result<(Release, Vec<&'static str>), Error> {
+    let dists = &req.dists()?;
+    let names = ["InRelease", "Release", "Release.gpg"];
+
+    let found = future::join_all(names.iter().map(|name| async move {
+        let download = fetch::Download::from_to(dists.join(name)?, dir.join(name));
+        fetch::fetch(client, &[download]).await
+    }))
+    .await;
+
+    let mut present: Vec<&'static str> = names
+        .iter()
+        .zip(found)
+        .filter(|(_, result)| result.is_ok())
+        .map(|(name, _)| *name)
+        .collect();
+
+    let mut gpg = GpgClient::new(keys, policy);
+    let mut verified = Vec::new();
+    let mut signer = None;
+
+    if present.contains(&"InRelease") {
+        let out = dir.join("InRelease.verified");
+        match gpg.verify_clearsigned(dir.join("InRelease"), &out) {
+            Ok(found) => {
+                signer = Some(found);
+                verified.push(out);
+            }
+            Err(_) if present.contains(&"Release.gpg") => {
+                present.retain(|name| "InRelease" != *name);
+            }
+            Err(e) => return Err(e.context(anyhow!("verifying InRelease"))),
+        }
+    }
+
+    if present.contains(&"Release.gpg") {
+        ensure!(present.contains(&"Release"), "Release.gpg, but no Release");
+        let out = dir.join("Release.verified");
+        let detached = gpg
+            .verify_detached(dir.join("Release"), dir.join("Release.gpg"), &out)
+            .with_context(|| anyhow!("verifying Release.gpg"))?;
+        signer.get_or_insert(detached);
+        verified.push(out);
+    } else if present.contains(&"Release") {
+        // unsigned, but it's what clients without InRelease support would read
+        verified.push(dir.join("Release"));
+    }
+
+    ensure!(
+        present.contains(&"InRelease") || present.contains(&"Release.gpg"),
+        "no signed release found at {}",
+        dists
+    );
+
+    let texts = verified
+        .iter()
+        .map(|path| fs::read_to_string(path).with_context(|| anyhow!("reading {:?}", path)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    // clearsigning loses trailing whitespace
+    if let [first, second] = texts.as_slice() {
+        ensure!(
+            first
+                .trim_end()
+                .lines()
+                .map(str::trim_end)
+                .eq(second.trim_end().lines().map(str::trim_end)),
+            "InRelease and Release disagree"
+        );
+    }
+
+    let file = release::parse_release_file(&verified[0])?;
+
+    Ok((
+        Release {
+            req: req.clone(),
+            sources_entries: entries.to_vec(),
+            file,
+            signer,
+            unsigned: false,
+        },
+        present,
+    ))
+}
+
+/// The text of the release `fetch_release` checked.
+fn verified_text(dir: &Path) -> Result<String, Error> {
+    let path = ["InRelease.verified", "Release.verified"]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+        .expect("checked by fetch_release");
+    Ok(fs::read_to_string(&path)?)
+}
+
+/// The hashes the `Release` lists, weakest first, and hence which `by-hash` directories
+/// clients may use.
+fn release_algs(text: &str) -> Result<Vec<HashAlg>, Error> {
+    let map = rfc822::fields_in_block(text).collect_to_map()?;
+    Ok([
+        HashAlg::Md5,
+        HashAlg::Sha1,
+        HashAlg::Sha256,
+        HashAlg::Sha512,
+    ]
+    .iter()
+    .copied()
+    .filter(|alg| map.contains_key(alg.by_hash_name()))
+    .collect())
+}
+
+/// Every compression of a listing the `Release` mentions, and its `by-hash` aliases.
+///
+/// Returns `(path, where it is, whether it was downloaded, its digests)`. Copies which are
+/// already up to date aren't downloaded again.
+async fn fetch_listing(
+    client: &fetch::Client,
+    root: &Path,
+    release: &Release,
+    listing: &lists::Listing,
+    dists: &str,
+    staging: &Path,
+    algs: &[HashAlg],
+) -> Result<Vec<(String, PathBuf, bool, Digests)>, Error> {
+    // insists the uncompressed version is listed, as everything else does
+    lists::find_file_easy(release, listing)?;
+
+    let base = listing.path();
+    let contents: Vec<&release::ReleaseContent> = release
+        .file
+        .contents
+        .iter()
+        .filter(|content| {
+            content
+                .name
+                .strip_prefix(&base)
+                .is_some_and(|extension| COMPRESSIONS.contains(&extension))
+        })
+        .collect();
+
+    let mut ret = Vec::new();
+    let mut downloads = Vec::new();
+
+    for content in &contents {
+        let path = format!("{}/{}", dists, content.name);
+        let local = root.join(&path);
+        let current = fs::File::open(&local)
+            .ok()
+            .and_then(|file| Digests::of(file).ok());
+        if let Some(digests) = current {
+            if digests.len == content.len && digests.sha256 == content.hashes.sha256 {
+                ret.push((path, local, false, digests));
+                continue;
+            }
+        }
+
+        let url = release
+            .req
+            .dists()?
+            .join(&if release.file.acquire_by_hash {
+                format!(
+                    "{}/by-hash/SHA256/{}",
+                    listing.dir(),
+                    hex::encode(content.hashes.sha256)
+                )
+            } else {
+                content.name.to_string()
+            })?;
+        let staged = staging.join(content.name.replace('/', "_"));
+        downloads.push((content, path, staged.clone(), url));
+    }
+
+    // one at a time, as some of them may legitimately be missing
+    let requests: Vec<[fetch::Download; 1]> = downloads
+        .iter()
+        .map(|(_, _, staged, url)| [fetch::Download::from_to(url.clone(), staged)])
+        .collect();
+    let found =
+        future::join_all(requests.iter().map(|request| fetch::fetch(client, request))).await;
+
+    for ((content, path, staged, _), result) in downloads.into_iter().zip(found) {
+        // e.g. Debian lists uncompressed indices, but doesn't serve them
+        if result.is_err() {
+            continue;
+        }
+
+        let digests = Digests::of(fs::File::open(&staged)?)?;
+        ensure!(
+            digests.len == content.len && digests.sha256 == content.hashes.sha256,
+            "{:?} doesn't match the release: expected {} bytes with sha256 {}, got {} and {}",
+            content.name,
+            content.len,
+            hex::encode(content.hashes.sha256),
+            digests.len,
+            hex::encode(digests.sha256)
+        );
+        ret.push((path, staged, true, digests));
+    }
+
+    ensure!(!ret.is_empty(), "none of the listing could be fetched");
+
+    let mut aliases = Vec::new();
+    for (_, from, _, digests) in &ret {
+        for (alg, hex) in digests.hex().iter() {
+            if algs.contains(alg) {
+                let path = format!(
+                    "{}/{}/by-hash/{}/{}",
+                    dists,
+                    listing.dir(),
+                    alg.by_hash_name(),
+                    hex
+                );
+                aliases.push((path, from.clone(), false, *digests));
+            }
+        }
+    }
+    ret.extend(aliases);
+
+    Ok(ret)
+}
+
+/// The `by-hash` aliases for everything the copy's `Release` for `dists` lists, if any.
+///
+/// This is the copy's own `Release`, which was checked when it was fetched.
+fn previous_by_hash(root: &Path, dists: &str) -> Result<HashSet<String>, Error> {
+    let dir = root.join(dists);
+    let text = match fs::read_to_string(dir.join("Release")) {
+        Ok(text) => text,
+        Err(e) if io::ErrorKind::NotFound == e.kind() => {
+            match fs::File::open(dir.join("InRelease")) {
+                Ok(file) => signing::read_clearsigned(io::BufReader::new(file), None)?.0,
+                Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(HashSet::new()),
+                Err(e) => return Err(e).with_context(|| anyhow!("opening InRelease")),
+            }
+        }
+        Err(e) => return Err(e).with_context(|| anyhow!("reading Release")),
+    };
+
+    let mut map = rfc822::fields_in_block(&text).collect_to_map()?;
+    let mut ret = HashSet::new();
+    for alg in release_algs(&text)? {
+        let sums = release::take_checksums(&mut map, alg.by_hash_name())?
+            .expect("release_algs checked it's present");
+        for ((name, _), hash) in sums {
+            let dir = match name.rsplit_once('/') {
+                Some((dir, _)) => format!("{}/{}", dists, dir),
+                None => dists.to_string(),
+            };
+            ret.insert(format!("{}/by-hash/{}/{}", dir, alg.by_hash_name(), hash));
+        }
+    }
+    Ok(ret)
+}
+
+fn read_listing(extension: &str, from: &Path) -> Result<Vec<Package>, Error> {
+    let file = fs::File::open(from).with_context(|| anyhow!("opening {:?}", from))?;
+    rfc822::Blocks::new(
+        deb::decompress(extension, io::BufReader::new(file))?,
+        format!("{:?}", from),
+    )
+    .map(|block| Package::parse(&mut rfc822::fields_in_block(&block?).collect_to_map()?))
+    .collect()
+}
+
+/// The pool files of the selected packages, by their path relative to the mirror.
+fn pool_files(
+    req: &RequestedRelease,
+    binaries: &[Package],
+    sources: &[Package],
+    selection: &Selection,
+) -> Result<Vec<(String, PoolFile)>, Error> {
+    let names = match selection {
+        Selection::All => None,
+        Selection::Packages(names) => Some(names.clone()),
+        Selection::Closure(names) => Some(closure(binaries, names)),
+    };
+
+    let mut ret = Vec::new();
+    let mut source_names = names.clone().unwrap_or_default();
+
+    for pkg in binaries {
+        if let Some(names) = &names {
+            if !names.contains(&pkg.name) {
+                continue;
+            }
+        }
+
+        let bin = pkg.as_bin().expect("read from Packages");
+        let source = match &bin.source {
+            // e.g. `glibc (2.36-9)`, when the versions differ
+            Some(source) => source.split_whitespace().next().unwrap_or(&pkg.name),
+            None => &pkg.name,
+        };
+        source_names.insert(source.to_string());
+
+        let file = bin
+            .file
+            .as_ref()
+            .ok_or_else(|| anyhow!("{:?} has no Filename", pkg.name))?;
+        let (alg, hash) = file.strongest_hash()?;
+        ret.push((
+            safe(&file.name)?,
+            PoolFile {
+                url: req.pool_url(&file.name)?,
+                len: file.size,
+                alg,
+                hash,
+            },
+        ));
+    }
+
+    for pkg in sources {
+        if names.is_some() && !source_names.contains(&pkg.name) {
+            continue;
+        }
+
+        let src = pkg.as_src().expect("read from Sources");
+        let directory = src
+            .directory
+            .as_ref()
+            .ok_or_else(|| anyhow!("{:?} has no Directory", pkg.name))?;
+
+        for file in &src.files {
+            let path = safe(&format!("{}/{}", directory, file.name))?;
+            let (alg, hash) = file.strongest_hash();
+            ret.push((
+                path.clone(),
+                PoolFile {
+                    url: req.pool_url(&path)?,
+                    len: file.size,
+                    alg,
+                    hash,
+                },
+            ));
+        }
+    }
+
+    Ok(ret)
+}
+
+/// `roots`, and everything they `Depends` or `Pre-Depends` on, transitively.
+fn closure(binaries: &[Package], roots: &HashSet<String>) -> HashSet<String> {
+    let mut by_name = HashMap::new();
+    let mut providers = HashMap::new();
+    for pkg in binaries {
+        let bin = match pkg.as_bin() {
+            Some(bin) => bin,
+            None => continue,
+        };
+        by_name
+            .entry(pkg.name.as_str())
+            .or_insert_with(Vec::new)
+            .push(bin);
+        for provided in bin.provides.iter().flat_map(|dep| &dep.alternate) {
+            providers
+                .entry(provided.package.as_str())
+                .or_insert_with(Vec::new)
+                .push(pkg.name.as_str());
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut todo: Vec<&str> = roots.iter().map(|name| name.as_str()).collect();
+
+    while let Some(name) = todo.pop() {
+        if !seen.insert(name.to_string()) {
+            continue;
+        }
+
+        for bin in by_name.get(name).into_iter().flatten() {
+            for alternate in bin
+                .pre_depends
+                .iter()
+                .chain(&bin.depends)
+                .flat_map(|dep| &dep.alternate)
+            {
+                todo.push(&alternate.package);
+                todo.extend(
+                    providers
+                        .get(alternate.package.as_str())
+                        .into_iter()
+                        .flatten(),
+                );
+            }
+        }
+    }
+
+    seen
+}
+
+/// Paths from the indices must stay inside the copy.
+fn safe(path: &str) -> Result<String, Error> {
+    ensure!(
+        !path
+            .split('/')
+            .any(|part| part.is_empty() || "." == part || ".." == part),
+        "refusing unusual path: {:?}",
+        path
+    );
+    Ok(path.to_string())
+}
+
+fn index_entry(digests: &Digests) -> Wanted {
+    Wanted {
+        len: digests.len,
+        alg: HashAlg::Sha256,
+        hash: digests.sha256.to_vec(),
+        size_only: false,
+    }
+}
+
+/// Fetch the pool files which aren't already present, checking each.
+async fn fetch_pool(
+    client: &fetch::Client,
+    root: &Path,
+    staging: &Path,
+    pool: &BTreeMap<String, PoolFile>,
+    report: &mut Report,
+) -> Result<(), Error> {
+    let missing: Vec<(&String, &PoolFile, PathBuf)> = pool
+        .iter()
+        .filter(|(path, file)| {
+            fs::metadata(root.join(path))
+                .map(|meta| meta.len() != file.len)
+                .unwrap_or(true)
+        })
+        .enumerate()
+        .map(|(n, (path, file))| (path, file, staging.join(format!("pool-{}", n))))
+        .collect();
+
+    let downloads: Vec<fetch::Download> = missing
+        .iter()
+        .map(|(_, file, staged)| fetch::Download::from_to(file.url.clone(), staged))
+        .collect();
+
+    fetch::fetch(client, &downloads)
+        .await
+        .with_context(|| anyhow!("downloading pool files"))?;
+
+    for (path, file, staged) in missing {
+        checksum::validate_len_and_hash(fs::File::open(&staged)?, file.len, file.alg, &file.hash)
+            .with_context(|| anyhow!("validating {}", file.url))?;
+        let to = root.join(path);
+        fs::create_dir_all(to.parent().expect("inside root"))?;
+        fs::rename(&staged, &to).with_context(|| anyhow!("storing {:?}", path))?;
+        deb::set_mode(&to, 0o644)?;
+        report.downloaded.push(path.to_string());
+    }
+
+    Ok(())
+}
+
+/// Replace `to` with a copy of `from`, atomically.
+fn install(from: &Path, to: &Path) -> Result<(), Error> {
+    let parent = to.parent().expect("inside root");
+    fs::create_dir_all(parent).with_context(|| anyhow!("creating {:?}", parent))?;
+    let mut temp = PersistableTempFile::new_in(parent)?;
+    io::copy(&mut fs::File::open(from)?, &mut temp)?;
+    temp.persist_by_rename(to)
+        .map_err(|e| e.error)
+        .with_context(|| anyhow!("replacing {:?}", to))?;
+    // temporary files are private, but this is for serving
+    deb::set_mode(to, 0o644)
+}
+
+/// Remove anything under `dir` which isn't wanted or kept, then any directories left empty.
+///
+/// Returns whether `dir` is now empty.
+fn prune(
+    root: &Path,
+    dir: &Path,
+    wanted: &BTreeMap<String, Wanted>,
+    kept: &HashSet<String>,
+    pruned: &mut Vec<PathBuf>,
+) -> Result<bool, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(true),
+        Err(e) => return Err(e).with_context(|| anyhow!("listing {:?}", dir)),
+    };
+
+    let mut empty = true;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let relative = path.strip_prefix(root).expect("walking inside root");
+
+        if entry.file_type()?.is_dir() {
+            if prune(root, &path, wanted, kept, pruned)? {
+                fs::remove_dir(&path).with_context(|| anyhow!("removing {:?}", path))?;
+            } else {
+                empty = false;
+            }
+            continue;
+        }
+
+        let known = relative
+            .to_str()
+            .is_some_and(|relative| wanted.contains_key(relative) || kept.contains(relative));
+        if known {
+            empty = false;
+        } else {
+            fs::remove_file(&path).with_context(|| anyhow!("removing {:?}", path))?;
+            pruned.push(relative.to_path_buf());
+        }
+    }
+
+    Ok(empty)
+}
+
+/// Everything should now be present, and match.
+fn check(root: &Path, wanted: &BTreeMap<String, Wanted>) -> Result<(), Error> {
+    let mut problems = Vec::new();
+
+    for (path, expected) in wanted {
+        if let Some(problem) = compare_file(path, expected, &root.join(path), !expected.size_only)?
+        {
+            problems.push(problem.to_string());
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!("the copy is inconsistent:\n{}", problems.join("\n"));
+    }
+
+    Ok(())
+}
+
+/// Check a mirror's copy of a suite: its `Release`, everything the `Release` lists, the
+/// `by-hash` aliases clients will use for them, and every pool file the `Packages` and
+/// `Sources` indices mention.
+///
+/// The indices are always downloaded and checked in full. Everything else is only checked
+/// by size, using `HEAD` requests, unless `deep` is set, in which case it's all downloaded.
+///
+/// Uncompressed indices are allowed to be missing if a compressed version is listed,
+/// as Debian lists them, but doesn't serve them.
+pub(crate) async fn check_mirror(
+    client: &fetch::Client,
+    keys: &Keys,
+    policy: &SignaturePolicy,
+    mirror: Url,
+    suite: &str,
+    deep: bool,
+) -> Result<CheckReport, Error> {
+    let req = RequestedRelease {
+        mirror,
+        codename: suite.to_string(),
+        arches: Vec::new(),
+        trusted: false,
+    };
+
+    let staging = tempfile::Builder::new()
+        .prefix(".fapt-check")
+        .tempdir()
+        .with_context(|| anyhow!("creating temporary directory"))?;
+
+    let (release, _) = fetch_release(client, keys, policy, &req, &[], staging.path()).await?;
+
+    let text = verified_text(staging.path())?;
+    let alg = *release_algs(&text)?
+        .last()
+        .ok_or_else(|| anyhow!("the release lists no checksums"))?;
+    let mut map = rfc822::fields_in_block(&text).collect_to_map()?;
+    let strongest =
+        release::take_checksums(&mut map, alg.by_hash_name())?.expect("the section is present");
+
+    let dists = format!("dists/{}", suite);
+    let names: HashSet<&str> = release
+        .file
+        .contents
+        .iter()
+        .map(|content| content.name.as_str())
+        .collect();
+
+    let mut checks = Vec::new();
+    for content in &release.file.contents {
+        let hash = strongest
+            .get(&(content.name.as_str(), content.len))
+            .ok_or_else(|| anyhow!("no {} for {:?}", alg.by_hash_name(), content.name))?;
+
+        let (dir, file) = content.name.rsplit_once('/').unwrap_or(("", &content.name));
+        let index = index_name(file);
+        let wanted = Wanted {
+            len: content.len,
+            alg,
+            hash: hex::decode(hash)?,
+            size_only: !deep && index.is_none(),
+        };
+        let optional = index.is_some_and(|(_, extension)| extension.is_empty())
+            && COMPRESSIONS[1..]
+                .iter()
+                .any(|extension| names.contains(format!("{}{}", content.name, extension).as_str()));
+
+        if release.file.acquire_by_hash {
+            checks.push(Check {
+                path: format!("{}/{}/by-hash/{}/{}", dists, dir, alg.by_hash_name(), hash),
+                wanted: wanted.clone(),
+                optional,
+                index: None,
+            });
+        }
+
+        checks.push(Check {
+            path: format!("{}/{}", dists, content.name),
+            wanted,
+            optional,
+            index,
+        });
+    }
+
+    let mut report = CheckReport::default();
+    let results = check_all(client, &req, staging.path(), &checks).await?;
+
+    // one copy of each index, from whichever compression was fine
+    let mut readable = BTreeMap::new();
+    for (check, (problem, fetched)) in checks.iter().zip(results) {
+        report.checked += 1;
+        match (problem, check.index) {
+            (Some(problem), _) => report.problems.push(problem),
+            (None, Some((name, extension))) => {
+                if let Some(fetched) = fetched {
+                    let base = &check.path[..check.path.len() - extension.len()];
+                    readable
+                        .entry(base.to_string())
+                        .or_insert((name, extension, fetched));
+                }
+            }
+            (None, None) => (),
+        }
+    }
+
+    let mut binaries = Vec::new();
+    let mut sources = Vec::new();
+    for (base, (name, extension, fetched)) in readable {
+        let packages =
+            read_listing(extension, &fetched).with_context(|| anyhow!("reading {:?}", base))?;
+        if "Packages" == name {
+            binaries.extend(packages);
+        } else {
+            sources.extend(packages);
+        }
+    }
+
+    let mut pool: BTreeMap<String, Wanted> = BTreeMap::new();
+    for (path, file) in pool_files(&req, &binaries, &sources, &Selection::All)? {
+        if let Some(existing) = pool.get(&path) {
+            if existing.len != file.len || (existing.alg == file.alg && existing.hash != file.hash)
+            {
+                report.problems.push(Problem::Mismatched {
+                    path,
+                    reason: "listed with different contents".to_string(),
+                });
+            }
+            continue;
+        }
+        pool.insert(
+            path,
+            Wanted {
+                len: file.len,
+                alg: file.alg,
+                hash: file.hash,
+                size_only: !deep,
+            },
+        );
+    }
+
+    let checks: Vec<Check> = pool
+        .into_iter()
+        .map(|(path, wanted)| Check {
+            path,
+            wanted,
+            optional: false,
+            index: None,
+        })
+        .collect();
+
+    for (problem, _) in check_all(client, &req, staging.path(), &checks).await? {
+        report.checked += 1;
+        report.problems.extend(problem);
+    }
+
+    Ok(report)
+}
+
+/// `Packages.xz` -> `("Packages", ".xz")`, if it's an index we read.
+fn index_name(file: &str) -> Option<(&'static str, &'static str)> {
+    ["Packages", "Sources"].iter().find_map(|name| {
+        let extension = file.strip_prefix(name)?;
+        COMPRESSIONS
+            .iter()
+            .find(|known| **known == extension)
+            .map(|extension| (*name, *extension))
+    })
+}
+
+/// A file on a mirror, relative to its root.
+struct Check {
+    path: String,
+    wanted: Wanted,
+    /// Whether it may be missing.
+    optional: bool,
+    /// If it's an index we'll read, its name and compression.
+    index: Option<(&'static str, &'static str)>,
+}
+
+/// Check all of the files on the mirror, in parallel.
+///
+/// Returns any problem with each, and where the indices were downloaded to.
+async fn check_all(
+    client: &fetch::Client,
+    req: &RequestedRelease,
+    staging: &Path,
+    checks: &[Check],
+) -> Result<Vec<(Option<Problem>, Option<PathBuf>)>, Error> {
+    let pending: Vec<_> = checks
+        .iter()
+        .enumerate()
+        .map(|(n, check)| check_remote(client, req, staging.join(format!("check-{}", n)), check))
+        .collect();
+
+    stream::iter(pending)
+        .buffered(fetch::PARALLEL_DOWNLOADS)
+        .try_collect()
+        .await
+}
+
+async fn check_remote(
+    client: &fetch::Client,
+    req: &RequestedRelease,
+    temp: PathBuf,
+    check: &Check,
+) -> Result<(Option<Problem>, Option<PathBuf>), Error> {
+    let url = req.pool_url(&check.path)?;
+    // only the server saying it hasn't got the file means it's missing
+    let failed = |e: Error| {
+        if !fetch::is_not_found(&e) {
+            Some(Problem::Unreachable {
+                path: check.path.to_string(),
+                reason: format!("{:#}", e),
+            })
+        } else if check.optional {
+            None
+        } else {
+            Some(Problem::Missing {
+                path: check.path.to_string(),
+            })
+        }
+    };
+
+    if check.wanted.size_only {
+        let problem = match fetch::content_length(client, &url).await {
+            Ok(Some(actual)) => compare_len(&check.path, check.wanted.len, actual),
+            // can't tell, but it's there
+            Ok(None) => None,
+            Err(e) => failed(e),
+        };
+        return Ok((problem, None));
+    }
+
+    if let Err(e) = fetch::fetch(client, &[fetch::Download::from_to(url, &temp)]).await {
+        return Ok((failed(e), None));
+    }
+
+    let problem = compare_file(&check.path, &check.wanted, &temp, true)?;
+    if problem.is_some() || check.index.is_none() {
+        fs::remove_file(&temp)?;
+        return Ok((problem, None));
+    }
+
+    Ok((None, Some(temp)))
+}
+
+fn compare_len(path: &str, expected: u64, actual: u64) -> Option<Problem> {
+    if actual < expected {
+        Some(Problem::Truncated {
+            path: path.to_string(),
+            expected,
+            actual,
+        })
+    } else if actual > expected {
+        Some(Problem::Mismatched {
+            path: path.to_string(),
+            reason: format!("{} bytes, expected {}", actual, expected),
+        })
+    } else {
+        None
+    }
+}
+
+/// Compare a local file with what was expected, optionally checking its hash.
+fn compare_file(
+    path: &str,
+    expected: &Wanted,
+    file: &Path,
+    hash: bool,
+) -> Result<Option<Problem>, Error> {
+    let actual = match fs::metadata(file) {
+        Ok(meta) => meta.len(),
+        Err(e) if io::ErrorKind::NotFound == e.kind() => {
+            return Ok(Some(Problem::Missing {
+                path: path.to_string(),
+            }))
+        }
+        Err(e) => return Err(e).with_context(|| anyhow!("inspecting {:?}", file)),
+    };
+
+    if let Some(problem) = compare_len(path, expected.len, actual) {
+        return Ok(Some(problem));
+    }
+
+    if hash {
+        let found = expected
+            .alg
+            .digest(fs::File::open(file).with_context(|| anyhow!("opening {:?}", file))?)?;
+        if found != expected.hash {
+            return Ok(Some(Problem::Mismatched {
+                path: path.to_string(),
+                reason: format!(
+                    "{} mismatch: expected: {}, actual: {}",
+                    expected.alg.by_hash_name(),
+                    hex::encode(&expected.hash),
+                    hex::encode(found)
+                ),
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::closure;
+    use crate::parse::Package;
+    use crate::rfc822;
+
+    fn bin(block: &str) -> Package {
+        let text = format!(
+            "{}\nVersion: 1\nArchitecture: all\nMaintainer: a <a@example.com>\n\
+             Section: misc\nDescription: d",
+            block
+        );
+        Package::parse(&mut rfc822::fields_in_block(&text).collect_to_map().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn closure_follows_depends_and_provides() {
+        let binaries = vec![
+            bin("Package: app\nDepends: libfoo | libbar, mta"),
+            bin("Package: libfoo\nPre-Depends: libc"),
+            bin("Package: libbar"),
+            bin("Package: libc"),
+            bin("Package: postfix\nProvides: mta"),
+            bin("Package: unrelated\nRecommends: app"),
+        ];
+
+        let roots: HashSet<String> = vec!["app".to_string()].into_iter().collect();
+        let found = closure(&binaries, &roots);
+        for name in &["app", "libfoo", "libbar", "libc", "mta", "postfix"] {
+            assert!(found.contains(*name), "{}", name);
+        }
+        assert!(!found.contains("unrelated"));
+    }
+}
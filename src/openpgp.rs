@@ -0,0 +1,363 @@
+//! Just enough of OpenPGP's framing (RFC 4880) to handle keys and signatures ourselves,
+//! where `gpgrv` only verifies.
+
+use std::fmt::Write as _;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Error;
+use base64::Engine;
+use sha1::Digest;
+
+/// A packet, borrowed from its encoding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Packet<'d> {
+    pub(crate) tag: u8,
+    pub(crate) body: &'d [u8],
+}
+
+/// If `data` is armoured, remove the armour, checking its checksum. Otherwise, it's returned
+/// as it is, assuming it was binary all along.
+pub(crate) fn dearmor(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) if text.trim_start().starts_with("-----BEGIN PGP ") => text,
+        _ => return Ok(data.to_vec()),
+    };
+
+    let mut lines = text.trim_start().lines().map(str::trim_end);
+    let begin = lines.next().expect("checked above");
+
+    // headers, e.g. `Comment:`, up to a blank line
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+        ensure!(line.contains(": "), "invalid armour header: {:?}", line);
+    }
+
+    let mut encoded = String::new();
+    let mut checksum = None;
+    for line in lines.by_ref() {
+        if line.starts_with("-----END PGP ") {
+            break;
+        }
+        match line.strip_prefix('=') {
+            Some(crc) => checksum = Some(crc.to_string()),
+            None => encoded.push_str(line),
+        }
+    }
+
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| anyhow!("invalid base64 in {:?}: {}", begin, e))?;
+
+    if let Some(checksum) = checksum {
+        let expected = base64::engine::general_purpose::STANDARD
+            .decode(&checksum)
+            .map_err(|e| anyhow!("invalid armour checksum: {}", e))?;
+        ensure!(
+            expected == crc24(&decoded).to_be_bytes()[1..],
+            "armour checksum mismatch"
+        );
+    }
+
+    Ok(decoded)
+}
+
+/// Each `-----BEGIN PGP ...` to `-----END PGP ...` block in some text, e.g. a `.asc` holding
+/// more than one key, with its kind, e.g. `PUBLIC KEY BLOCK`.
+pub(crate) fn armoured_blocks(text: &str) -> Vec<(&str, &str)> {
+    let mut blocks = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("-----BEGIN PGP ") {
+        let block = &rest[start..];
+        let kind = block["-----BEGIN PGP ".len()..]
+            .split("-----")
+            .next()
+            .unwrap_or_default();
+        let end = match block.find("-----END PGP ") {
+            Some(end) => block[end..]
+                .find('\n')
+                .map_or(block.len(), |line| end + line),
+            None => block.len(),
+        };
+        blocks.push((kind, &block[..end]));
+        rest = &block[end..];
+    }
+    blocks
+}
+
+/// Armour some packets, e.g. as a `SIGNATURE`.
+pub(crate) fn armor(kind: &str, data: &[u8]) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+    let mut text = format!("-----BEGIN PGP {}-----\n\n", kind);
+    for chunk in encoded.as_bytes().chunks(64) {
+        text.push_str(std::str::from_utf8(chunk).expect("base64 is ascii"));
+        text.push('\n');
+    }
+    let crc = base64::engine::general_purpose::STANDARD.encode(&crc24(data).to_be_bytes()[1..]);
+    writeln!(text, "={}", crc).expect("writing to a string");
+    writeln!(text, "-----END PGP {}-----", kind).expect("writing to a string");
+    text
+}
+
+/// https://tools.ietf.org/html/rfc4880#section-6.1
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xb7_04ce;
+    for &byte in data {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if 0 != crc & 0x100_0000 {
+                crc ^= 0x186_4cfb;
+            }
+        }
+    }
+    crc & 0xff_ffff
+}
+
+/// Split some binary data into its packets.
+///
+/// Partial lengths are refused, as they're only used for data, which we don't handle.
+pub(crate) fn packets(data: &[u8]) -> Result<Vec<Packet<'_>>, Error> {
+    match packets_prefix(data) {
+        (packets, None) => Ok(packets),
+        (_, Some(e)) => Err(e),
+    }
+}
+
+/// Split as much of some binary data into packets as possible, and why the rest couldn't be.
+pub(crate) fn packets_prefix(mut data: &[u8]) -> (Vec<Packet<'_>>, Option<Error>) {
+    let mut packets = Vec::new();
+
+    while !data.is_empty() {
+        match take_packet(data) {
+            Ok((packet, rest)) => {
+                packets.push(packet);
+                data = rest;
+            }
+            Err(e) => return (packets, Some(e)),
+        }
+    }
+
+    (packets, None)
+}
+
+fn take_packet(data: &[u8]) -> Result<(Packet<'_>, &[u8]), Error> {
+    let header = data[0];
+    ensure!(0 != header & 0x80, "invalid packet header: {:#x}", header);
+
+    let (tag, len, header_len) = if 0 != header & 0x40 {
+        let tag = header & 0x3f;
+        let first = usize::from(*data.get(1).ok_or_else(|| anyhow!("truncated header"))?);
+        match first {
+            0..=191 => (tag, first, 2),
+            192..=223 => {
+                let second = usize::from(*data.get(2).ok_or_else(|| anyhow!("truncated header"))?);
+                (tag, ((first - 192) << 8) + second + 192, 3)
+            }
+            255 => (tag, read_be(data.get(2..6))?, 6),
+            _ => bail!("partial lengths are not supported, in packet {}", tag),
+        }
+    } else {
+        let tag = (header >> 2) & 0x0f;
+        match header & 0x03 {
+            0 => (tag, read_be(data.get(1..2))?, 2),
+            1 => (tag, read_be(data.get(1..3))?, 3),
+            2 => (tag, read_be(data.get(1..5))?, 5),
+            _ => bail!("indeterminate lengths are not supported, in packet {}", tag),
+        }
+    };
+
+    let end = header_len + len;
+    ensure!(end <= data.len(), "truncated packet {}", tag);
+    let packet = Packet {
+        tag,
+        body: &data[header_len..end],
+    };
+    Ok((packet, &data[end..]))
+}
+
+/// A big-endian `u32` from the start of `data`, e.g. a time.
+pub(crate) fn read_u32(data: &[u8]) -> Option<u32> {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(data.get(..4)?);
+    Some(u32::from_be_bytes(bytes))
+}
+
+fn read_be(bytes: Option<&[u8]>) -> Result<usize, Error> {
+    let bytes = bytes.ok_or_else(|| anyhow!("truncated header"))?;
+    Ok(bytes
+        .iter()
+        .fold(0usize, |acc, &byte| (acc << 8) | usize::from(byte)))
+}
+
+/// Encode a packet, with a new-format header.
+pub(crate) fn packet(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 6);
+    out.push(0xc0 | tag);
+    match body.len() {
+        len @ 0..=191 => out.push(len as u8),
+        len @ 192..=8383 => {
+            let len = len - 192;
+            out.push((len >> 8) as u8 + 192);
+            out.push(len as u8);
+        }
+        len => {
+            out.push(255);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+/// Take a multiprecision integer off the front of `data`, returning its big-endian bytes.
+pub(crate) fn take_mpi<'d>(data: &mut &'d [u8]) -> Result<&'d [u8], Error> {
+    ensure!(data.len() >= 2, "truncated integer");
+    let bits = usize::from(u16::from_be_bytes([data[0], data[1]]));
+    let len = bits.div_ceil(8);
+    ensure!(data.len() >= 2 + len, "truncated integer");
+    let value = &data[2..2 + len];
+    *data = &data[2 + len..];
+    Ok(value)
+}
+
+/// Encode big-endian bytes as a multiprecision integer.
+pub(crate) fn put_mpi(out: &mut Vec<u8>, value: &[u8]) {
+    let start = value
+        .iter()
+        .position(|&byte| 0 != byte)
+        .unwrap_or(value.len());
+    let value = &value[start..];
+    let bits = match value.first() {
+        Some(first) => value.len() * 8 - first.leading_zeros() as usize,
+        None => 0,
+    };
+    out.extend_from_slice(&(bits as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+/// The hashed subpackets of a version 4 signature, from the start of its body, as
+/// `(type, body)`, without the critical bit.
+///
+/// https://tools.ietf.org/html/rfc4880#section-5.2.3.1
+pub(crate) fn hashed_subpackets(signature: &[u8]) -> Result<Vec<(u8, &[u8])>, Error> {
+    ensure!(
+        Some(&4) == signature.first(),
+        "unsupported signature version: {:?}",
+        signature.first()
+    );
+    let len = read_be(signature.get(4..6))?;
+    let mut data = signature
+        .get(6..6 + len)
+        .ok_or_else(|| anyhow!("truncated subpackets"))?;
+
+    let mut subpackets = Vec::new();
+    while !data.is_empty() {
+        let first = usize::from(data[0]);
+        let (len, header_len) = match first {
+            0..=191 => (first, 1),
+            192..=254 => (((first - 192) << 8) + read_be(data.get(1..2))? + 192, 2),
+            255 => (read_be(data.get(1..5))?, 5),
+            _ => unreachable!("a byte"),
+        };
+        let end = header_len + len;
+        ensure!(0 < len && end <= data.len(), "invalid subpacket length");
+        subpackets.push((data[header_len] & 0x7f, &data[header_len + 1..end]));
+        data = &data[end..];
+    }
+    Ok(subpackets)
+}
+
+/// Is this a GnuPG keybox (e.g. `pubring.kbx`), rather than OpenPGP packets?
+pub(crate) fn is_keybox(data: &[u8]) -> bool {
+    data.get(8..12) == Some(b"KBXf")
+}
+
+/// The OpenPGP keyblocks stored in a keybox, each a sequence of packets.
+///
+/// https://github.com/gpg/gnupg/blob/master/kbx/keybox-blob.c
+pub(crate) fn keybox_keyblocks(mut data: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let mut keyblocks = Vec::new();
+    while !data.is_empty() {
+        let len = read_be(data.get(..4))?;
+        ensure!(
+            5 <= len && len <= data.len(),
+            "invalid keybox blob length: {}",
+            len
+        );
+        let blob = &data[..len];
+        data = &data[len..];
+
+        // the first blob describes the file; x509 certificates are of no use to us
+        const OPENPGP: u8 = 2;
+        if OPENPGP != blob[4] {
+            continue;
+        }
+
+        let start = read_be(blob.get(8..12))?;
+        let end = start + read_be(blob.get(12..16))?;
+        keyblocks.push(
+            blob.get(start..end)
+                .ok_or_else(|| anyhow!("keyblock outside its keybox blob"))?,
+        );
+    }
+    Ok(keyblocks)
+}
+
+/// The fingerprint of a version 4 key, from the body of its public key packet.
+pub(crate) fn v4_fingerprint(public: &[u8]) -> [u8; 20] {
+    let mut hasher = sha1::Sha1::default();
+    hasher.update([0x99]);
+    hasher.update((public.len() as u16).to_be_bytes());
+    hasher.update(public);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::armor;
+    use super::dearmor;
+    use super::packet;
+    use super::packets;
+    use super::put_mpi;
+    use super::take_mpi;
+
+    #[test]
+    fn round_trip() {
+        let long = vec![7u8; 300];
+        let mut data = packet(2, b"short");
+        data.extend(packet(13, &long));
+        let armoured = armor("SIGNATURE", &data);
+        assert!(armoured.starts_with("-----BEGIN PGP SIGNATURE-----\n\n"));
+        assert_eq!(data, dearmor(armoured.as_bytes()).unwrap());
+
+        let found = packets(&data).unwrap();
+        assert_eq!(2, found.len());
+        assert_eq!((2, &b"short"[..]), (found[0].tag, found[0].body));
+        assert_eq!((13, &long[..]), (found[1].tag, found[1].body));
+
+        let corrupt = armoured.replace("\n=", "\n=A");
+        assert!(dearmor(corrupt.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn old_format() {
+        // a user id packet, with a one-byte length
+        let found = packets(b"\xb4\x03abc").unwrap();
+        assert_eq!((13, &b"abc"[..]), (found[0].tag, found[0].body));
+        assert!(packets(b"\xb4\x05abc").is_err());
+    }
+
+    #[test]
+    fn mpis() {
+        let mut out = Vec::new();
+        put_mpi(&mut out, &[0, 0, 1, 0xff]);
+        assert_eq!(vec![0, 9, 1, 0xff], out);
+        let mut data = &out[..];
+        assert_eq!(&[1, 0xff], take_mpi(&mut data).unwrap());
+        assert!(data.is_empty());
+    }
+}
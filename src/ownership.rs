@@ -0,0 +1,321 @@
+//! Which installed package owns which files, like `dpkg --search` and `dpkg --listfiles`.
+//!
+//! `dpkg` records the paths each package installed in `info/<pkg>.list`. The paths are as
+//! they were in the package, so on a `/usr`-merged system `/bin/sh` and `/usr/bin/sh` are
+//! the same file, and are treated as such here. Diversions move a package's file elsewhere,
+//! to make room for another package's, or the admin's, copy.
+//!
+//! ```no_run
+//! # use fapt::system::System;
+//! let mut fapt = System::cache_only()?;
+//! fapt.set_dpkg_database("/var/lib/dpkg");
+//! let index = fapt.file_index("/")?;
+//! println!("{:?}", index.owners("/bin/sh"));
+//! for (package, path) in index.search("*/man1/ls.1*") {
+//!     println!("{}: {}", package, path);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Error;
+
+/// A file moved out of the way, so something else can be at `from`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diversion {
+    pub from: String,
+    /// Where the other packages' copies of `from` really are.
+    pub to: String,
+    /// The package whose copy stays at `from`; `None` for the admin's local diversions.
+    pub by: Option<String>,
+}
+
+/// The files of the installed packages, indexed both ways.
+#[derive(Clone, Debug, Default)]
+pub struct FileIndex {
+    /// package -> the paths in its `.list`
+    files: BTreeMap<String, Vec<String>>,
+    /// canonical path -> the packages listing it
+    owners: HashMap<String, Vec<String>>,
+    /// canonical `from` -> the diversion
+    diverted_from: HashMap<String, Diversion>,
+    /// canonical `to` -> the diversion
+    diverted_to: HashMap<String, Diversion>,
+    /// e.g. `/bin` -> `/usr/bin`, for `/usr`-merged roots
+    aliases: Vec<(String, String)>,
+}
+
+impl FileIndex {
+    /// The packages whose file is really at `path`, after diversions.
+    ///
+    /// Directories are usually owned by many packages.
+    pub fn owners(&self, path: &str) -> Vec<&str> {
+        let path = self.canonical(path);
+        let mut ret: Vec<&str> = Vec::new();
+        let listing = |path: &str| {
+            self.owners
+                .get(path)
+                .into_iter()
+                .flatten()
+                .map(|package| package.as_str())
+        };
+
+        match self.diverted_from.get(&path) {
+            Some(diversion) => {
+                ret.extend(listing(&path).filter(|package| diverts(diversion, package)))
+            }
+            None => ret.extend(listing(&path)),
+        }
+
+        if let Some(diversion) = self.diverted_to.get(&path) {
+            let from = self.canonical(&diversion.from);
+            ret.extend(listing(&from).filter(|package| !diverts(diversion, package)));
+        }
+
+        ret.sort_unstable();
+        ret.dedup();
+        ret
+    }
+
+    /// Where the files of `package` really are, after diversions, or `None` if it has no
+    /// files recorded, e.g. as it isn't installed.
+    ///
+    /// Packages installed for a foreign architecture, or which are `Multi-Arch: same`, may
+    /// need an architecture qualifier, e.g. `libc6:amd64`.
+    pub fn files(&self, package: &str) -> Option<Vec<String>> {
+        let listed = self.files.get(package)?;
+        Some(
+            listed
+                .iter()
+                .map(|path| self.real_path(package, path))
+                .collect(),
+        )
+    }
+
+    /// The diversion of `path`, if any.
+    pub fn diversion(&self, path: &str) -> Option<&Diversion> {
+        self.diverted_from.get(&self.canonical(path))
+    }
+
+    /// The (package, path) pairs where the path matches the glob `pattern`.
+    ///
+    /// As with `dpkg --search`, a pattern that doesn't start with `/` or `*` matches anywhere
+    /// in the path. `*` matches anything, including `/`, and `?` any one character.
+    pub fn search(&self, pattern: &str) -> Vec<(&str, String)> {
+        let pattern = if pattern.starts_with('/') || pattern.starts_with('*') {
+            pattern.to_string()
+        } else {
+            format!("*{}*", pattern)
+        };
+
+        let mut ret = Vec::new();
+        for (package, listed) in &self.files {
+            for path in listed {
+                let real = self.real_path(package, path);
+                if glob_matches(&pattern, &real) || glob_matches(&pattern, &self.canonical(&real)) {
+                    ret.push((package.as_str(), real));
+                }
+            }
+        }
+        ret
+    }
+
+    /// `path`, as listed by `package`, after any diversion.
+    fn real_path(&self, package: &str, path: &str) -> String {
+        match self.diverted_from.get(&self.canonical(path)) {
+            Some(diversion) if !diverts(diversion, package) => diversion.to.to_string(),
+            _ => path.to_string(),
+        }
+    }
+
+    /// The `/usr` form of a path, if the root is merged, e.g. `/bin/sh` -> `/usr/bin/sh`.
+    fn canonical(&self, path: &str) -> String {
+        let path = normalise(path);
+        for (alias, target) in &self.aliases {
+            if let Some(rest) = path.strip_prefix(alias.as_str()) {
+                if rest.is_empty() || rest.starts_with('/') {
+                    return format!("{}{}", target, rest);
+                }
+            }
+        }
+        path.to_string()
+    }
+}
+
+/// Does `diversion` keep `package`'s copy in place?
+pub(crate) fn diverts(diversion: &Diversion, package: &str) -> bool {
+    let package = package.split(':').next().unwrap_or(package);
+    diversion.by.as_deref() == Some(package)
+}
+
+/// Drop trailing slashes, and `dpkg`'s name for the root, `/.`.
+fn normalise(path: &str) -> &str {
+    if path == "/." {
+        return "/";
+    }
+    match path.trim_end_matches('/') {
+        "" => "/",
+        path => path,
+    }
+}
+
+/// Index the `.list` files in the `dpkg` database, for the packages installed under `root`.
+pub(crate) fn index(dpkg: &Path, root: &Path) -> Result<FileIndex, Error> {
+    let mut index = FileIndex {
+        aliases: usr_merge_aliases(root)?,
+        ..FileIndex::default()
+    };
+
+    for diversion in diversions_in(dpkg)? {
+        index
+            .diverted_to
+            .insert(index.canonical(&diversion.to), diversion.clone());
+        index
+            .diverted_from
+            .insert(index.canonical(&diversion.from), diversion);
+    }
+
+    let info = dpkg.join("info");
+    for entry in fs::read_dir(&info).with_context(|| anyhow!("listing {:?}", info))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let package = match name.to_str().and_then(|name| name.strip_suffix(".list")) {
+            Some(package) => package.to_string(),
+            None => continue,
+        };
+
+        // paths aren't necessarily utf-8, but we'd rather show them mangled than fail
+        let data = fs::read(entry.path()).with_context(|| anyhow!("reading {:?}", name))?;
+        let listed: Vec<String> = String::from_utf8_lossy(&data)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| normalise(line).to_string())
+            .collect();
+
+        for path in &listed {
+            index
+                .owners
+                .entry(index.canonical(path))
+                .or_default()
+                .push(package.to_string());
+        }
+        index.files.insert(package, listed);
+    }
+
+    Ok(index)
+}
+
+/// Top-level directories which are links into `/usr`.
+fn usr_merge_aliases(root: &Path) -> Result<Vec<(String, String)>, Error> {
+    let mut ret = Vec::new();
+    for entry in fs::read_dir(root).with_context(|| anyhow!("listing {:?}", root))? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let target = match fs::read_link(entry.path()) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        let target = target.to_string_lossy();
+        if target.trim_start_matches('/') == format!("usr/{}", name) {
+            ret.push((format!("/{}", name), format!("/usr/{}", name)));
+        }
+    }
+    Ok(ret)
+}
+
+/// Read `dpkg`'s `diversions` file; three lines per diversion: from, to, and the package,
+/// or `:` for the admin's own.
+pub fn read_diversions<R: Read>(mut from: R) -> Result<Vec<Diversion>, Error> {
+    let mut text = String::new();
+    from.read_to_string(&mut text)?;
+
+    let lines: Vec<&str> = text.lines().collect();
+    if !lines.len().is_multiple_of(3) {
+        bail!("expected three lines per diversion");
+    }
+
+    Ok(lines
+        .chunks(3)
+        .map(|chunk| Diversion {
+            from: chunk[0].to_string(),
+            to: chunk[1].to_string(),
+            by: Some(chunk[2])
+                .filter(|by| *by != ":")
+                .map(ToString::to_string),
+        })
+        .collect())
+}
+
+/// The diversions in the `dpkg` database at `dpkg`, which has none until something is diverted.
+pub(crate) fn diversions_in(dpkg: &Path) -> Result<Vec<Diversion>, Error> {
+    let path = dpkg.join("diversions");
+    match fs::File::open(&path) {
+        Ok(file) => read_diversions(file).with_context(|| anyhow!("reading {:?}", path)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(Error::from(e).context(anyhow!("opening {:?}", path))),
+    }
+}
+
+/// Shell-style matching of `*` and `?`, where `*` also matches `/`.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // where to resume after the last `*`: (pattern, text)
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+            }
+            Some(c) if *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sp, st)) => {
+                    p = sp;
+                    t = st + 1;
+                    star = Some((sp, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_matches;
+
+    #[test]
+    fn glob() {
+        assert!(glob_matches("/usr/bin/ls", "/usr/bin/ls"));
+        assert!(!glob_matches("/usr/bin/ls", "/usr/bin/lsblk"));
+        assert!(glob_matches("*/ls", "/usr/bin/ls"));
+        assert!(glob_matches("*bin*", "/usr/sbin/x"));
+        assert!(glob_matches("/usr/*/l?", "/usr/bin/ls"));
+        assert!(!glob_matches("/usr/*/l?", "/usr/bin/lsx"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("*a*b", "xaxxb"));
+        assert!(!glob_matches("*a*b", "xaxxbc"));
+    }
+}
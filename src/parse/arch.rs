@@ -1,7 +1,6 @@
 use std::collections::HashSet;
 use std::str::FromStr;
 
-use anyhow::bail;
 use anyhow::Error;
 
 /// An architecture specifier, such as `amd64`, or `linux-any`.
@@ -87,26 +86,6 @@ impl FromStr for Arch {
 
 pub type Arches = HashSet<Arch>;
 
-macro_rules! strum {
-    ($doc:literal, $name:ident, $($variant:ident($str:expr),)*) => {
-        #[doc = $doc]
-        #[derive(Copy, Clone, Debug, Hash, PartialOrd, Ord, PartialEq, Eq)]
-        pub enum $name {
-            $($variant,)*
-        }
-
-        impl FromStr for $name {
-            type Err = Error;
-            fn from_str(from: &str) -> Result<$name, Error> {
-                match from {
-                    $($str => Ok($name::$variant), )*
-                    other => bail!("no {}: {:?}", stringify!($name), other),
-                }
-            }
-        }
-    }
-}
-
 strum!(
     "A host kernel-and-ecosystem, e.g. `linux`, `musl-linux`, `kfreebsd`, ...",
     Kernel,
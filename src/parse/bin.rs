@@ -6,6 +6,7 @@ use self::rfc822::RfcMapExt;
 use super::deps::parse_dep;
 use super::deps::Dependency;
 use super::pkg;
+use super::status::Status;
 use crate::rfc822;
 
 /// Binary package specific fields.
@@ -21,7 +22,8 @@ pub struct Binary {
 
     pub description: String,
     pub source: Option<String>,
-    pub status: Option<String>,
+    /// Only present in the `dpkg` database.
+    pub status: Option<Status>,
 
     pub depends: Vec<Dependency>,
     pub recommends: Vec<Dependency>,
@@ -79,7 +81,11 @@ pub(super) fn parse_bin(it: &mut rfc822::Map) -> Result<Binary, Error> {
         installed_size,
         description: it.remove_value("Description").joined_lines_req()?,
         source: it.remove_value("Source").one_line_owned()?,
-        status: it.remove_value("Status").one_line_owned()?,
+        status: it
+            .remove_value("Status")
+            .one_line()?
+            .map(|line| line.parse())
+            .inside_out()?,
         depends: parse_dep(&it.remove("Depends").unwrap_or_else(Vec::new))?,
         recommends: parse_dep(&it.remove("Recommends").unwrap_or_else(Vec::new))?,
         suggests: parse_dep(&it.remove("Suggests").unwrap_or_else(Vec::new))?,
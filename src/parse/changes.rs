@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use chrono::DateTime;
+use chrono::Utc;
+use gpgrv::Keyring;
+use insideout::InsideOut;
+
+use super::ident;
+use super::ident::Identity;
+use super::pkg;
+use super::pkg::Priority;
+use crate::checksum;
+use crate::release::take_checksums;
+use crate::rfc822;
+use crate::rfc822::RfcMapExt;
+use crate::signing;
+use crate::signing::SignatureStatus;
+
+/// An upload, as described by a `.changes` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Changes {
+    pub format: String,
+    pub date: DateTime<Utc>,
+    pub source: String,
+    pub binaries: Vec<String>,
+    /// e.g. `source all amd64`
+    pub architecture: Vec<String>,
+    pub version: String,
+    pub distribution: Vec<String>,
+    pub urgency: String,
+
+    pub maintainer: Vec<Identity>,
+    pub changed_by: Vec<Identity>,
+
+    /// Bug numbers
+    pub closes: Vec<u64>,
+    /// The changelog entries, one line per line, with `.` lines restored to blank lines.
+    /// Indentation is not preserved.
+    pub changes: String,
+
+    pub files: Vec<ChangesFile>,
+
+    pub unparsed: HashMap<String, Vec<String>>,
+}
+
+/// An entry in the `Files` of a `.changes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangesFile {
+    pub name: String,
+    pub size: u64,
+    pub section: String,
+    /// `Unknown` for `-`, as used by `byhand` files.
+    pub priority: Priority,
+
+    pub md5: checksum::MD5,
+    pub sha1: Option<checksum::SHA1>,
+    pub sha256: Option<checksum::SHA256>,
+    pub sha512: Option<checksum::SHA512>,
+}
+
+impl Changes {
+    /// Read a `.changes`, checking its signature if a keyring is provided.
+    pub fn read<R: BufRead>(
+        from: R,
+        keyring: Option<&Keyring>,
+    ) -> Result<(Changes, SignatureStatus), Error> {
+        let (text, signature) = signing::read_clearsigned(from, keyring)?;
+        let mut map = rfc822::fields_in_block(text.trim()).collect_to_map()?;
+        Ok((Changes::parse(&mut map)?, signature))
+    }
+
+    /// Parse the fields of a `.changes`, once any signature is removed.
+    pub fn parse(map: &mut rfc822::Map) -> Result<Changes, Error> {
+        Ok(Changes {
+            format: map.remove_value("Format").one_line_req()?.to_string(),
+            date: rfc822::parse_date(map.remove_value("Date").one_line_req()?)?,
+            source: map.remove_value("Source").one_line_req()?.to_string(),
+            binaries: take_words(map, "Binary"),
+            architecture: map.remove_value("Architecture").split_whitespace()?,
+            version: map.remove_value("Version").one_line_req()?.to_string(),
+            distribution: map.remove_value("Distribution").split_whitespace()?,
+            urgency: map.remove_value("Urgency").one_line_req()?.to_string(),
+            maintainer: ident::read(map.remove_value("Maintainer").one_line_req()?)
+                .context("maintainer")?,
+            changed_by: map
+                .remove_value("Changed-By")
+                .one_line()?
+                .map(ident::read)
+                .inside_out()
+                .context("changed_by")?
+                .unwrap_or_else(Vec::new),
+            closes: take_words(map, "Closes")
+                .into_iter()
+                .map(|bug| {
+                    bug.parse()
+                        .with_context(|| anyhow!("invalid bug number: {:?}", bug))
+                })
+                .collect::<Result<_, Error>>()?,
+            changes: map
+                .remove_value("Changes")
+                .required()?
+                .iter()
+                .map(|line| if "." == *line { "" } else { line })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            files: take_files(map).context("files")?,
+            unparsed: map
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.iter().map(|v| v.to_string()).collect()))
+                .collect(),
+        })
+    }
+
+    /// Check that every file in the upload is in `dir`, with the right size and hash.
+    ///
+    /// Only the strongest hash available for each file is checked.
+    pub fn verify_files<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        let dir = dir.as_ref();
+        for file in &self.files {
+            file.verify_in(dir)
+                .with_context(|| anyhow!("checking {:?} in {:?}", file.name, dir))?;
+        }
+        Ok(())
+    }
+}
+
+impl ChangesFile {
+    fn verify_in(&self, dir: &Path) -> Result<(), Error> {
+        let (alg, hash) = checksum::strongest_decoded(
+            &self.md5,
+            self.sha1.as_ref(),
+            self.sha256.as_ref(),
+            self.sha512.as_ref(),
+        );
+        checksum::validate_in(dir, &self.name, self.size, alg, &hash)
+    }
+}
+
+/// A missing field is an empty list.
+fn take_words(map: &mut rfc822::Map, key: &str) -> Vec<String> {
+    map.remove(key)
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|line| line.split_whitespace())
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// `Files` in a `.changes` has the section and priority before the name.
+fn take_files(map: &mut rfc822::Map) -> Result<Vec<ChangesFile>, Error> {
+    let lines = map
+        .remove("Files")
+        .ok_or_else(|| anyhow!("Files required"))?;
+    let mut sha1s = take_checksums(map, "Checksums-Sha1")?.unwrap_or_else(HashMap::new);
+    let mut sha256s = take_checksums(map, "Checksums-Sha256")?.unwrap_or_else(HashMap::new);
+    let mut sha512s = take_checksums(map, "Checksums-Sha512")?.unwrap_or_else(HashMap::new);
+
+    let mut files = Vec::with_capacity(lines.len());
+
+    for line in lines {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        ensure!(5 == parts.len(), "invalid Files line: {:?}", line);
+        let name = parts[4];
+        let size = parts[1]
+            .parse()
+            .with_context(|| anyhow!("invalid size in {:?}", line))?;
+
+        files.push(ChangesFile {
+            name: name.to_string(),
+            size,
+            section: parts[2].to_string(),
+            priority: match parts[3] {
+                "-" => Priority::Unknown,
+                other => pkg::parse_priority(other)?,
+            },
+            md5: checksum::parse_md5(parts[0])?,
+            sha1: sha1s
+                .remove(&(name, size))
+                .map(checksum::parse_sha1)
+                .inside_out()?,
+            sha256: sha256s
+                .remove(&(name, size))
+                .map(checksum::parse_sha256)
+                .inside_out()?,
+            sha512: sha512s
+                .remove(&(name, size))
+                .map(checksum::parse_sha512)
+                .inside_out()?,
+        });
+    }
+
+    for (name, remaining) in &[("sha1", sha1s), ("sha256", sha256s), ("sha512", sha512s)] {
+        ensure!(
+            remaining.is_empty(),
+            "{} for a file which isn't in Files: {:?}",
+            name,
+            remaining.keys()
+        );
+    }
+
+    Ok(files)
+}
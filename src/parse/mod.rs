@@ -1,22 +1,56 @@
 //! These types are used to represent a [crate::parse::Package].
 
+/// An enum of the words used in a field, which can be parsed from, and displayed as, them.
+macro_rules! strum {
+    ($doc:literal, $name:ident, $($variant:ident($str:expr),)*) => {
+        #[doc = $doc]
+        #[derive(Copy, Clone, Debug, Hash, PartialOrd, Ord, PartialEq, Eq)]
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl ::std::str::FromStr for $name {
+            type Err = ::anyhow::Error;
+            fn from_str(from: &str) -> Result<$name, ::anyhow::Error> {
+                match from {
+                    $($str => Ok($name::$variant), )*
+                    other => ::anyhow::bail!("no {}: {:?}", stringify!($name), other),
+                }
+            }
+        }
+
+        impl ::std::fmt::Display for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.write_str(match self {
+                    $($name::$variant => $str, )*
+                })
+            }
+        }
+    }
+}
+
 mod arch;
 mod bin;
+mod changes;
 mod deps;
 mod ident;
 mod pkg;
 mod src;
+mod status;
 mod vcs;
 
 pub use self::arch::Arch;
 pub use self::arch::Cpu;
 pub use self::arch::Kernel;
 pub use self::bin::Binary;
+pub use self::changes::Changes;
+pub use self::changes::ChangesFile;
 pub use self::deps::Constraint;
 pub use self::deps::ConstraintOperator;
 pub use self::deps::Dependency;
 pub use self::deps::SingleDependency;
 pub use self::ident::Identity;
+pub use self::pkg::File;
 pub use self::pkg::Package;
 pub use self::pkg::PackageType;
 pub use self::pkg::Priority;
@@ -24,6 +58,11 @@ pub use self::src::Source;
 pub use self::src::SourceArchive;
 pub use self::src::SourceBinary;
 pub use self::src::SourceFormat;
+pub use self::status::Conffile;
+pub use self::status::Flag;
+pub use self::status::State;
+pub use self::status::Status;
+pub use self::status::Want;
 pub use self::vcs::Vcs;
 pub use self::vcs::VcsTag;
 pub use self::vcs::VcsType;
@@ -1,18 +1,24 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::io::BufRead;
 
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Error;
+use gpgrv::Keyring;
 use insideout::InsideOut;
 
 use super::arch;
 use super::bin;
 use super::ident;
 use super::src;
+use crate::checksum;
+use crate::checksum::HashAlg;
 use crate::rfc822;
 use crate::rfc822::RfcMapExt;
+use crate::signing;
+use crate::signing::SignatureStatus;
 
 /// The parsed top-level types for package
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -50,6 +56,19 @@ pub struct File {
     pub sha512: String,
 }
 
+impl File {
+    /// The strongest hash available for this file.
+    pub(crate) fn strongest_hash(&self) -> Result<(HashAlg, Vec<u8>), Error> {
+        checksum::strongest([
+            (HashAlg::Md5, self.md5.as_str()),
+            (HashAlg::Sha1, self.sha1.as_str()),
+            (HashAlg::Sha256, self.sha256.as_str()),
+            (HashAlg::Sha512, self.sha512.as_str()),
+        ])
+        .with_context(|| anyhow!("checking hashes for {:?}", self.name))
+    }
+}
+
 /// https://www.debian.org/doc/debian-policy/#priorities
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Priority {
@@ -87,7 +106,57 @@ impl Package {
             )
         };
 
-        Ok(parse_pkg(map, style).with_context(|| anyhow!("shared fields in {:?}", name))?)
+        let section = map.remove_value("Section").one_line_req()?.to_string();
+
+        Ok(parse_pkg(map, style, section)
+            .with_context(|| anyhow!("shared fields in {:?}", name))?)
+    }
+
+    /// Read a `.dsc`, checking its signature if a keyring is provided.
+    pub fn read_dsc<R: BufRead>(
+        from: R,
+        keyring: Option<&Keyring>,
+    ) -> Result<(Package, SignatureStatus), Error> {
+        let (text, signature) = signing::read_clearsigned(from, keyring)?;
+        let mut map = rfc822::fields_in_block(text.trim()).collect_to_map()?;
+        Ok((Package::parse_dsc(&mut map)?, signature))
+    }
+
+    /// Read a `.dsc`, which must be signed by one of `keys`, in a way `policy` accepts.
+    pub(crate) fn read_dsc_by<R: BufRead>(
+        from: R,
+        keys: &signing::Keys,
+        policy: &signing::SignaturePolicy,
+    ) -> Result<(Package, signing::Signer), Error> {
+        let (text, signer) = signing::read_clearsigned_by(from, keys, policy)?;
+        let mut map = rfc822::fields_in_block(text.trim()).collect_to_map()?;
+        Ok((Package::parse_dsc(&mut map)?, signer))
+    }
+
+    /// Parse the fields of a `.dsc`, which names the package in `Source`,
+    /// and has no `Directory` or `Section`.
+    pub fn parse_dsc(map: &mut rfc822::Map) -> Result<Package, Error> {
+        let name = map
+            .get_value("Source")
+            .one_line_req()
+            .with_context(|| anyhow!("no Source: {:?}", map))?
+            .to_string();
+
+        // the index calls this Package, which is what everything else expects
+        let source = map.remove("Source").expect("checked above");
+        map.insert("Package", source);
+
+        let style = PackageType::Source(
+            src::parse_src(map).with_context(|| anyhow!("source fields in {:?}", name))?,
+        );
+
+        let section = map
+            .remove_value("Section")
+            .one_line()?
+            .unwrap_or("")
+            .to_string();
+
+        parse_pkg(map, style, section).with_context(|| anyhow!("shared fields in {:?}", name))
     }
 
     pub fn as_src(&self) -> Option<&src::Source> {
@@ -105,7 +174,7 @@ impl Package {
     }
 }
 
-fn parse_pkg(map: &mut rfc822::Map, style: PackageType) -> Result<Package, Error> {
+fn parse_pkg(map: &mut rfc822::Map, style: PackageType, section: String) -> Result<Package, Error> {
     let arches = map
         .remove_value("Architecture")
         .one_line_req()?
@@ -132,7 +201,7 @@ fn parse_pkg(map: &mut rfc822::Map, style: PackageType) -> Result<Package, Error
             .inside_out()?
             .unwrap_or(Priority::Unknown),
         arches,
-        section: map.remove_value("Section").one_line_req()?.to_string(),
+        section,
         maintainer: super::ident::read(map.remove_value("Maintainer").one_line_req()?)?,
         original_maintainer,
         homepage: map.remove_value("Homepage").one_line_owned()?,
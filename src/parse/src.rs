@@ -11,6 +11,8 @@ use super::deps::Dependency;
 use super::ident::Identity;
 use super::pkg;
 use super::vcs;
+use crate::checksum;
+use crate::checksum::HashAlg;
 use crate::rfc822;
 use crate::rfc822::RfcMapExt;
 use std::collections::HashSet;
@@ -24,7 +26,8 @@ pub struct Source {
     pub files: Vec<SourceArchive>,
     pub vcs: Vec<vcs::Vcs>,
 
-    pub directory: String,
+    /// Where the files live in the archive; absent for a standalone `.dsc`.
+    pub directory: Option<String>,
     pub standards_version: String,
 
     pub build_dep: Vec<Dependency>,
@@ -35,6 +38,12 @@ pub struct Source {
     pub build_conflict_indep: Vec<Dependency>,
 
     pub uploaders: Vec<Identity>,
+
+    /// The `autopkgtest` suites, e.g. `autopkgtest-pkg-perl`.
+    pub testsuite: Vec<String>,
+    pub testsuite_triggers: Vec<String>,
+    /// The `dgit` commit this was uploaded from, and where to find it.
+    pub dgit: Option<String>,
 }
 
 /// The `Files` making up a source package
@@ -44,7 +53,21 @@ pub struct SourceArchive {
     pub name: String,
     pub size: u64,
     pub md5: crate::checksum::MD5,
+    pub sha1: Option<crate::checksum::SHA1>,
     pub sha256: Option<crate::checksum::SHA256>,
+    pub sha512: Option<crate::checksum::SHA512>,
+}
+
+impl SourceArchive {
+    /// The strongest hash available for this file.
+    pub(crate) fn strongest_hash(&self) -> (HashAlg, Vec<u8>) {
+        checksum::strongest_decoded(
+            &self.md5,
+            self.sha1.as_ref(),
+            self.sha256.as_ref(),
+            self.sha512.as_ref(),
+        )
+    }
 }
 
 /// Information on the binary packages built from a source package.
@@ -72,7 +95,7 @@ pub(super) fn parse_src(map: &mut rfc822::Map) -> Result<Source, Error> {
         format: parse_format(map.remove_value("Format").one_line_req()?)?,
         binaries: take_package_list(map).context("binaries")?,
         files: take_files(map).context("files")?,
-        directory: map.remove_value("Directory").one_line_req()?.to_string(),
+        directory: map.remove_value("Directory").one_line_owned()?,
         vcs: super::vcs::extract(map).context("vcs")?,
         // TODO: Option<> instead of empty string?
         standards_version: map
@@ -103,9 +126,24 @@ pub(super) fn parse_src(map: &mut rfc822::Map) -> Result<Source, Error> {
             .map(|line| super::ident::read(line))
             .inside_out()?
             .unwrap_or_else(Vec::new),
+        testsuite: take_comma_list(map, "Testsuite")?,
+        testsuite_triggers: take_comma_list(map, "Testsuite-Triggers")?,
+        dgit: map.remove_value("Dgit").one_line_owned()?,
     })
 }
 
+fn take_comma_list(map: &mut rfc822::Map, key: &str) -> Result<Vec<String>, Error> {
+    let value = map.remove_value(key);
+    if value.val.is_none() {
+        return Ok(Vec::new());
+    }
+    Ok(value
+        .split_comma()?
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect())
+}
+
 pub(super) fn parse_format(string: &str) -> Result<SourceFormat, Error> {
     Ok(match string {
         "3.0 (quilt)" => SourceFormat::Quilt3dot0,
@@ -175,29 +213,51 @@ pub(super) fn take_package_list(map: &mut rfc822::Map) -> Result<Vec<SourceBinar
 
 pub(super) fn take_files(map: &mut rfc822::Map) -> Result<Vec<SourceArchive>, Error> {
     use crate::checksum::parse_md5;
+    use crate::checksum::parse_sha1;
     use crate::checksum::parse_sha256;
+    use crate::checksum::parse_sha512;
     use crate::release::take_checksums;
     let file_and_size_to_md5 =
         take_checksums(map, "Files")?.ok_or_else(|| anyhow!("Files required"))?;
+    let mut file_and_size_to_sha1 =
+        take_checksums(map, "Checksums-Sha1")?.unwrap_or_else(HashMap::new);
     let mut file_and_size_to_sha256 =
         take_checksums(map, "Checksums-Sha256")?.unwrap_or_else(HashMap::new);
+    let mut file_and_size_to_sha512 =
+        take_checksums(map, "Checksums-Sha512")?.unwrap_or_else(HashMap::new);
 
     let mut archives = Vec::with_capacity(file_and_size_to_md5.len());
     for ((name, size), md5) in file_and_size_to_md5 {
+        let sha1 = file_and_size_to_sha1.remove(&(name, size));
         let sha256 = file_and_size_to_sha256.remove(&(name, size));
+        let sha512 = file_and_size_to_sha512.remove(&(name, size));
         archives.push(SourceArchive {
             name: name.to_string(),
             size,
             md5: parse_md5(md5)?,
+            sha1: sha1.map(parse_sha1).inside_out()?,
             sha256: sha256.map(|v| parse_sha256(v)).inside_out()?,
+            sha512: sha512.map(parse_sha512).inside_out()?,
         })
     }
 
+    ensure!(
+        file_and_size_to_sha1.is_empty(),
+        "sha1sum for a file which didn't exist: {:?}",
+        file_and_size_to_sha1
+    );
+
     ensure!(
         file_and_size_to_sha256.is_empty(),
         "sha256sum for a file which didn't exist: {:?}",
         file_and_size_to_sha256
     );
 
+    ensure!(
+        file_and_size_to_sha512.is_empty(),
+        "sha512sum for a file which didn't exist: {:?}",
+        file_and_size_to_sha512
+    );
+
     Ok(archives)
 }
@@ -0,0 +1,169 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::Error;
+
+/// The `Status` of a package in the `dpkg` database, e.g. `install ok installed`.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Status {
+    pub want: Want,
+    pub flag: Flag,
+    pub state: State,
+}
+
+strum!(
+    "What the admin has selected to happen to a package.",
+    Want,
+    Unknown("unknown"),
+    Install("install"),
+    Hold("hold"),
+    Deinstall("deinstall"),
+    Purge("purge"),
+);
+
+strum!(
+    "Whether a package is broken, and needs reinstalling.",
+    Flag,
+    Ok("ok"),
+    ReinstReq("reinstreq"),
+);
+
+strum!(
+    "How far a package got through being installed, or removed.",
+    State,
+    NotInstalled("not-installed"),
+    ConfigFiles("config-files"),
+    HalfInstalled("half-installed"),
+    Unpacked("unpacked"),
+    HalfConfigured("half-configured"),
+    TriggersAwaited("triggers-awaited"),
+    TriggersPending("triggers-pending"),
+    Installed("installed"),
+);
+
+impl State {
+    /// Is the package configured, and so satisfies dependencies?
+    ///
+    /// Packages awaiting or pending triggers are, as `dpkg` considers them installed.
+    pub fn is_installed(self) -> bool {
+        matches!(
+            self,
+            State::Installed | State::TriggersAwaited | State::TriggersPending
+        )
+    }
+}
+
+impl FromStr for Status {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Status, Error> {
+        match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [want, flag, state] => Ok(Status {
+                want: want.parse()?,
+                flag: flag.parse()?,
+                state: state.parse()?,
+            }),
+            _ => bail!("invalid Status: {:?}", s),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.want, self.flag, self.state)
+    }
+}
+
+/// An entry in the `Conffiles` of an installed package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conffile {
+    pub path: String,
+    /// `None` if `dpkg` hasn't recorded it, i.e. `newconffile`.
+    pub md5: Option<String>,
+    /// No longer shipped by the package, but left behind.
+    pub obsolete: bool,
+    /// To be removed on the next upgrade of the package.
+    pub remove_on_upgrade: bool,
+}
+
+impl Conffile {
+    /// Parse a line of `Conffiles`: a path, the md5, and any flags.
+    pub fn parse(line: &str) -> Result<Conffile, Error> {
+        let mut words: Vec<&str> = line.split(' ').filter(|w| !w.is_empty()).collect();
+        let mut obsolete = false;
+        let mut remove_on_upgrade = false;
+        loop {
+            match words.last() {
+                Some(&"obsolete") => obsolete = true,
+                Some(&"remove-on-upgrade") => remove_on_upgrade = true,
+                _ => break,
+            }
+            words.pop();
+        }
+
+        if words.len() < 2 {
+            bail!("invalid Conffiles line: {:?}", line);
+        }
+        let md5 = words.pop().expect("checked above");
+
+        Ok(Conffile {
+            // paths may contain spaces
+            path: words.join(" "),
+            md5: Some(md5.to_string()).filter(|md5| md5 != "newconffile"),
+            obsolete,
+            remove_on_upgrade,
+        })
+    }
+}
+
+impl fmt::Display for Conffile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.path,
+            self.md5.as_deref().unwrap_or("newconffile")
+        )?;
+        if self.obsolete {
+            f.write_str(" obsolete")?;
+        }
+        if self.remove_on_upgrade {
+            f.write_str(" remove-on-upgrade")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Conffile;
+    use super::State;
+    use super::Status;
+    use super::Want;
+
+    #[test]
+    fn status() {
+        let status: Status = "hold ok half-installed".parse().unwrap();
+        assert_eq!(Want::Hold, status.want);
+        assert_eq!(State::HalfInstalled, status.state);
+        assert!(!status.state.is_installed());
+        assert_eq!("hold ok half-installed", status.to_string());
+        assert!("install ok".parse::<Status>().is_err());
+        assert!("install ok gone".parse::<Status>().is_err());
+    }
+
+    #[test]
+    fn conffile() {
+        let line = "/etc/a file 0123abcd obsolete remove-on-upgrade";
+        let conffile = Conffile::parse(line).unwrap();
+        assert_eq!("/etc/a file", conffile.path);
+        assert_eq!(Some("0123abcd"), conffile.md5.as_deref());
+        assert!(conffile.obsolete);
+        assert!(conffile.remove_on_upgrade);
+        assert_eq!(line, conffile.to_string());
+
+        assert_eq!(None, Conffile::parse("/etc/b newconffile").unwrap().md5);
+        assert!(Conffile::parse("/etc/c").is_err());
+    }
+}
@@ -0,0 +1,378 @@
+//! Applying unified diffs, as `patch -p1` would, for source packages.
+//!
+//! Hunks must match exactly, but may have moved, as with `patch --fuzz=0`.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+
+use crate::deb;
+use crate::files;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FilePatch {
+    old: Option<String>,
+    new: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Hunk {
+    /// 1-based, as in the header; zero if the file was empty
+    old_start: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+/// Apply every file in a patch to the tree in `dir`, stripping `strip` leading components
+/// from the names in the patch.
+pub fn apply(dir: &Path, patch: &str, strip: usize) -> Result<(), Error> {
+    for file in parse(patch)? {
+        let name = file
+            .new
+            .as_ref()
+            .or(file.old.as_ref())
+            .ok_or_else(|| anyhow!("a file with no name"))?;
+        let name = strip_components(name, strip)?;
+        apply_file(dir, &name, &file).with_context(|| anyhow!("patching {:?}", name))?;
+    }
+    Ok(())
+}
+
+fn apply_file(dir: &Path, name: &str, file: &FilePatch) -> Result<(), Error> {
+    let path = deb::resolve_in(dir, &deb::absolute(name), false)?;
+    // as with `patch`; the link could point anywhere, e.g. out of the tree
+    if let Ok(meta) = fs::symlink_metadata(&path) {
+        ensure!(
+            !meta.file_type().is_symlink(),
+            "refusing to patch through a symlink"
+        );
+    }
+
+    let original = match fs::read(&path) {
+        Ok(content) => String::from_utf8(content).with_context(|| anyhow!("non-utf-8 file"))?,
+        Err(e) if io::ErrorKind::NotFound == e.kind() && file.creates() => String::new(),
+        Err(e) => return Err(e).with_context(|| anyhow!("reading {:?}", path)),
+    };
+
+    let mut lines: Vec<String> = original.split_inclusive('\n').map(str::to_string).collect();
+
+    // how far the previous hunks have moved things, and where the last one finished
+    let mut delta: isize = 0;
+    let mut floor = 0;
+
+    for (i, hunk) in file.hunks.iter().enumerate() {
+        let expected = (hunk.old_start.saturating_sub(1) as isize + delta).max(0) as usize;
+        let at = find(&lines, &hunk.old, expected, floor)
+            .ok_or_else(|| anyhow!("hunk #{} doesn't apply", i + 1))?;
+        lines.splice(at..at + hunk.old.len(), hunk.new.iter().cloned());
+        delta +=
+            hunk.new.len() as isize - hunk.old.len() as isize + (at as isize - expected as isize);
+        floor = at + hunk.new.len();
+    }
+
+    if file.new.is_none() {
+        ensure!(lines.is_empty(), "deleted file isn't empty after patching");
+        fs::remove_file(&path)?;
+        return Ok(());
+    }
+
+    files::replace_atomically(&path, lines.concat().as_bytes())
+}
+
+/// The position of `want` in `lines`, closest to `expected`, but not before `floor`.
+fn find(lines: &[String], want: &[String], expected: usize, floor: usize) -> Option<usize> {
+    if want.len() > lines.len() {
+        return None;
+    }
+    let last = lines.len() - want.len();
+    let matches = |at: usize| at >= floor && at <= last && lines[at..at + want.len()] == *want;
+
+    (0..=lines.len()).find_map(|distance| {
+        let after = expected + distance;
+        if matches(after) {
+            return Some(after);
+        }
+        expected
+            .checked_sub(distance)
+            .filter(|&before| matches(before))
+    })
+}
+
+impl FilePatch {
+    fn creates(&self) -> bool {
+        self.old.is_none() || self.hunks.iter().all(|hunk| hunk.old.is_empty())
+    }
+}
+
+fn strip_components(name: &str, strip: usize) -> Result<String, Error> {
+    let parts: Vec<&str> = name.split('/').filter(|p| !p.is_empty()).collect();
+    ensure!(
+        !parts.contains(&".."),
+        "refusing path containing '..': {:?}",
+        name
+    );
+    ensure!(
+        parts.len() > strip,
+        "can't strip {} components from {:?}",
+        strip,
+        name
+    );
+    Ok(parts[strip..].join("/"))
+}
+
+/// `--- a/foo.c\t2019-01-01 00:00:00` -> `Some("a/foo.c")`; `/dev/null` -> `None`
+fn header_name(line: &str) -> Option<String> {
+    let name = line[4..].split('\t').next().unwrap_or("").trim_end();
+    if "/dev/null" == name {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+fn parse(patch: &str) -> Result<Vec<FilePatch>, Error> {
+    let mut lines = patch.split_inclusive('\n').peekable();
+    let mut files = Vec::new();
+
+    while let Some(line) = lines.next() {
+        if line.starts_with("GIT binary patch") {
+            bail!("binary patches are not supported");
+        }
+
+        if !line.starts_with("--- ") || !lines.peek().is_some_and(|l| l.starts_with("+++ ")) {
+            // commentary, `diff` command lines, `Index:`, etc.
+            continue;
+        }
+
+        let old = header_name(line.trim_end_matches('\n'));
+        let new = header_name(lines.next().expect("peeked").trim_end_matches('\n'));
+
+        let mut hunks = Vec::new();
+        while lines.peek().is_some_and(|l| l.starts_with("@@ ")) {
+            let header = lines.next().expect("peeked");
+            hunks.push(
+                parse_hunk(header, &mut lines)
+                    .with_context(|| anyhow!("parsing hunk {:?}", header.trim_end()))?,
+            );
+        }
+
+        ensure!(!hunks.is_empty(), "no hunks for {:?}", new.or(old));
+
+        files.push(FilePatch { old, new, hunks });
+    }
+
+    Ok(files)
+}
+
+fn parse_hunk<'p, I: Iterator<Item = &'p str>>(
+    header: &str,
+    lines: &mut std::iter::Peekable<I>,
+) -> Result<Hunk, Error> {
+    let ranges: Vec<&str> = header
+        .trim_start_matches("@@ ")
+        .split(" @@")
+        .next()
+        .unwrap_or("")
+        .split_whitespace()
+        .collect();
+
+    ensure!(2 == ranges.len(), "invalid hunk header");
+
+    let (old_start, mut old_len) = parse_range(ranges[0].trim_start_matches('-'))?;
+    let (_, mut new_len) = parse_range(ranges[1].trim_start_matches('+'))?;
+
+    let mut hunk = Hunk {
+        old_start,
+        old: Vec::with_capacity(old_len),
+        new: Vec::with_capacity(new_len),
+    };
+
+    let mut last_kind = None;
+
+    while old_len > 0 || new_len > 0 {
+        let line = lines
+            .next()
+            .ok_or_else(|| anyhow!("patch ended in the middle of a hunk"))?;
+
+        // some tools strip the trailing space from empty context lines
+        let (kind, content) = match line {
+            "\n" => (' ', "\n"),
+            line => match line.as_bytes().first() {
+                // so the rest starts on a character boundary
+                Some(&kind) if kind.is_ascii() => (char::from(kind), &line[1..]),
+                _ => bail!("unexpected line in hunk: {:?}", line),
+            },
+        };
+
+        let content = content.to_string();
+
+        match kind {
+            ' ' => {
+                ensure!(old_len > 0 && new_len > 0, "too many context lines");
+                hunk.old.push(content.clone());
+                hunk.new.push(content);
+                old_len -= 1;
+                new_len -= 1;
+            }
+            '-' => {
+                ensure!(old_len > 0, "too many removed lines");
+                hunk.old.push(content);
+                old_len -= 1;
+            }
+            '+' => {
+                ensure!(new_len > 0, "too many added lines");
+                hunk.new.push(content);
+                new_len -= 1;
+            }
+            '\\' => {
+                no_newline(&mut hunk, last_kind)?;
+                continue;
+            }
+            other => bail!("unexpected line in hunk: {:?}", other),
+        }
+
+        last_kind = Some(kind);
+    }
+
+    // the marker usually follows the last line of the hunk
+    if lines.peek().is_some_and(|l| l.starts_with('\\')) {
+        lines.next();
+        no_newline(&mut hunk, last_kind)?;
+    }
+
+    Ok(hunk)
+}
+
+/// `\ No newline at end of file` applies to the line before it.
+fn no_newline(hunk: &mut Hunk, last_kind: Option<char>) -> Result<(), Error> {
+    let sides = match last_kind {
+        Some(' ') => vec![&mut hunk.old, &mut hunk.new],
+        Some('-') => vec![&mut hunk.old],
+        Some('+') => vec![&mut hunk.new],
+        _ => bail!("no-newline marker with nothing before it"),
+    };
+
+    for side in sides {
+        if let Some(last) = side.last_mut() {
+            if last.ends_with('\n') {
+                last.pop();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_range(range: &str) -> Result<(usize, usize), Error> {
+    Ok(match range.split_once(',') {
+        Some((start, len)) => (start.parse()?, len.parse()?),
+        None => (range.parse()?, 1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::apply;
+
+    #[test]
+    fn modify_create_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        fs::write(
+            dir.join("hello.c"),
+            "extra\nline\n#include <stdio.h>\nint main() {\n  printf(\"hi\");\n}\n",
+        )
+        .unwrap();
+        fs::write(dir.join("gone"), "bye\n").unwrap();
+
+        apply(
+            dir,
+            r#"Description: say hello properly
+Author: Someone
+
+--- a/hello.c
++++ b/hello.c
+@@ -1,4 +1,4 @@
+ #include <stdio.h>
+ int main() {
+-  printf("hi");
++  printf("hello\n");
+ }
+--- /dev/null
++++ b/new/file
+@@ -0,0 +1,2 @@
++one
++two
+\ No newline at end of file
+--- a/gone
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+"#,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "extra\nline\n#include <stdio.h>\nint main() {\n  printf(\"hello\\n\");\n}\n",
+            fs::read_to_string(dir.join("hello.c")).unwrap()
+        );
+        assert_eq!(
+            "one\ntwo",
+            fs::read_to_string(dir.join("new/file")).unwrap()
+        );
+        assert!(!dir.join("gone").exists());
+    }
+
+    #[test]
+    fn mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "one\ntwo\n").unwrap();
+        assert!(apply(
+            dir.path(),
+            "--- a/a\n+++ b/a\n@@ -1 +1 @@\n-three\n+four\n",
+            1
+        )
+        .is_err());
+        assert!(apply(
+            dir.path(),
+            "--- a/../a\n+++ b/../a\n@@ -1 +1 @@\n-one\n+1\n",
+            1
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "one\n").unwrap();
+        for hunk in &["éone\n", "*one\n", "-one"] {
+            let patch = format!("--- a/a\n+++ b/a\n@@ -1,2 +1 @@\n{}", hunk);
+            assert!(apply(dir.path(), &patch, 1).is_err(), "{:?}", hunk);
+        }
+        assert_eq!("one\n", fs::read_to_string(dir.path().join("a")).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_out_of_tree() {
+        let outside = tempfile::tempdir().unwrap();
+        let target = outside.path().join("passwd");
+        fs::write(&target, "root\n").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(&target, dir.path().join("foo")).unwrap();
+
+        let patch = "--- a/foo\n+++ b/foo\n@@ -1 +1 @@\n-root\n+evil\n";
+        assert!(apply(dir.path(), patch, 1).is_err());
+        assert_eq!("root\n", fs::read_to_string(&target).unwrap());
+    }
+}
@@ -0,0 +1,603 @@
+//! Writing repositories from a pool of `.deb`s and `.dsc`s, like `apt-ftparchive`.
+//!
+//! The pool is expected at `pool/<component>/` under the repository's root, as on Debian's
+//! mirrors, and the indices are written to `dists/<suite>/`.
+//!
+//! ```no_run
+//! # use std::fs;
+//! # use fapt::publish::Suite;
+//! # use fapt::signing::SigningKey;
+//! let mut suite = Suite::new("internal");
+//! suite.arches = vec!["amd64".to_string()];
+//! suite.by_hash = true;
+//! let release = fapt::publish::publish("/srv/repo", &suite)?;
+//!
+//! let key = SigningKey::read(fs::File::open("repo-key.asc")?, None)?;
+//! fapt::publish::sign(&release, &key)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use chrono::DateTime;
+use chrono::Utc;
+
+use crate::checksum::Digests;
+use crate::checksum::HashAlg;
+use crate::deb::Control;
+use crate::files::write_atomically;
+use crate::parse::Package;
+use crate::rfc822;
+use crate::rfc822::RfcMapExt;
+use crate::signing;
+use crate::signing::SigningKey;
+
+/// Fields we compute for `Packages`, which shouldn't be copied from a `control` file.
+const GENERATED_FIELDS: &[&str] = &["Filename", "Size", "MD5sum", "SHA1", "SHA256", "SHA512"];
+
+/// What `xz` writes for an empty input.
+const EMPTY_XZ: &[u8] = &[
+    0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46, 0x00, 0x00, 0x00, 0x00,
+    0x1c, 0xdf, 0x44, 0x21, 0x1f, 0xb6, 0xf3, 0x7d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5a,
+];
+
+/// How to describe a suite in its `Release`, and what to put in it.
+#[derive(Clone, Debug)]
+pub struct Suite {
+    /// The directory under `dists`, e.g. `stable`.
+    pub suite: String,
+    pub codename: Option<String>,
+    pub origin: String,
+    pub label: String,
+    pub description: Option<String>,
+    pub date: DateTime<Utc>,
+
+    /// Empty means every component found in the pool.
+    pub components: Vec<String>,
+    /// Empty means every architecture found in the pool. `all` packages are listed
+    /// under every architecture.
+    pub arches: Vec<String>,
+
+    /// Also write `by-hash` copies of the indices, and set `Acquire-By-Hash`.
+    pub by_hash: bool,
+}
+
+impl Suite {
+    /// A suite named `suite`, dated now, with everything in the pool.
+    pub fn new<S: ToString>(suite: S) -> Suite {
+        let suite = suite.to_string();
+        Suite {
+            codename: None,
+            origin: suite.to_string(),
+            label: suite.to_string(),
+            description: None,
+            date: Utc::now(),
+            components: Vec::new(),
+            arches: Vec::new(),
+            by_hash: false,
+            suite,
+        }
+    }
+}
+
+/// `(name, version, arch)`, which must be unique in a suite; `arch` is `source` for sources.
+type Key = (String, String, String);
+
+/// A `Packages` entry, before we know which architectures it belongs in.
+struct BinaryEntry {
+    component: String,
+    arch: String,
+    stanza: String,
+}
+
+/// Scan `root/pool` and write `Packages`, `Sources`, their `.gz` and `.xz` variants, and
+/// an unsigned `Release`, for `suite`. Returns the path of the `Release`.
+///
+/// Each index is replaced atomically, and the `Release` is written last. Existing
+/// `by-hash` files are left in place, for clients still using an older `Release`.
+pub fn publish<P: AsRef<Path>>(root: P, suite: &Suite) -> Result<PathBuf, Error> {
+    let root = root.as_ref();
+    let pool = root.join("pool");
+
+    let mut found = Vec::new();
+    if pool.exists() {
+        walk(&pool, &mut found).with_context(|| anyhow!("scanning {:?}", pool))?;
+    }
+    found.sort();
+
+    let mut binaries = Vec::new();
+    let mut sources = Vec::new();
+
+    // where we saw each first
+    let mut seen: HashMap<Key, String> = HashMap::new();
+
+    for path in found {
+        let relative = relative_name(root, &path)?;
+        let extension = path.extension().and_then(|e| e.to_str());
+        if !matches!(extension, Some("deb") | Some("dsc")) {
+            continue;
+        }
+
+        let component = match relative.split('/').collect::<Vec<_>>().as_slice() {
+            ["pool", component, _, ..] => component.to_string(),
+            _ => bail!("expected pool/<component>/...: {:?}", relative),
+        };
+
+        if !suite.components.is_empty() && !suite.components.contains(&component) {
+            continue;
+        }
+
+        let key = if Some("deb") == extension {
+            let (key, arch, stanza) =
+                deb_stanza(&path, &relative).with_context(|| anyhow!("indexing {:?}", relative))?;
+            binaries.push(BinaryEntry {
+                component,
+                arch,
+                stanza,
+            });
+            key
+        } else {
+            let (key, stanza) =
+                dsc_stanza(&path, &relative).with_context(|| anyhow!("indexing {:?}", relative))?;
+            sources.push((component, stanza));
+            key
+        };
+
+        if let Some(previous) = seen.insert(key.clone(), relative.to_string()) {
+            bail!(
+                "{:?} and {:?} both contain {} {} ({})",
+                previous,
+                relative,
+                key.0,
+                key.1,
+                key.2
+            );
+        }
+    }
+
+    let components: BTreeSet<String> = if suite.components.is_empty() {
+        binaries
+            .iter()
+            .map(|binary| binary.component.to_string())
+            .chain(sources.iter().map(|(component, _)| component.to_string()))
+            .collect()
+    } else {
+        suite.components.iter().cloned().collect()
+    };
+
+    let arches: BTreeSet<String> = if suite.arches.is_empty() {
+        binaries
+            .iter()
+            .map(|binary| binary.arch.to_string())
+            .filter(|arch| "all" != arch)
+            .collect()
+    } else {
+        suite.arches.iter().cloned().collect()
+    };
+
+    ensure!(!components.is_empty(), "no components; the pool is empty?");
+    ensure!(
+        !arches.is_empty(),
+        "no architectures found in the pool; please list some"
+    );
+
+    let dists = root.join("dists").join(&suite.suite);
+    let mut contents = Vec::new();
+
+    for component in &components {
+        for arch in &arches {
+            let stanzas: Vec<&str> = binaries
+                .iter()
+                .filter(|binary| {
+                    &binary.component == component && (&binary.arch == arch || "all" == binary.arch)
+                })
+                .map(|binary| binary.stanza.as_str())
+                .collect();
+            write_index(
+                &dists,
+                &format!("{}/binary-{}", component, arch),
+                "Packages",
+                &stanzas,
+                suite.by_hash,
+                &mut contents,
+            )?;
+        }
+
+        let stanzas: Vec<&str> = sources
+            .iter()
+            .filter(|(source_component, _)| source_component == component)
+            .map(|(_, stanza)| stanza.as_str())
+            .collect();
+        write_index(
+            &dists,
+            &format!("{}/source", component),
+            "Sources",
+            &stanzas,
+            suite.by_hash,
+            &mut contents,
+        )?;
+    }
+
+    let release = dists.join("Release");
+    write_atomically(
+        &release,
+        release_text(suite, &components, &arches, &contents)?.as_bytes(),
+    )?;
+    Ok(release)
+}
+
+/// Sign a `Release`, writing a clearsigned `InRelease` and a detached `Release.gpg` beside it.
+pub fn sign<P: AsRef<Path>>(release: P, key: &SigningKey) -> Result<(), Error> {
+    let release = release.as_ref();
+    let text = fs::read_to_string(release).with_context(|| anyhow!("reading {:?}", release))?;
+    let dir = release
+        .parent()
+        .ok_or_else(|| anyhow!("full path please"))?;
+
+    write_atomically(&dir.join("InRelease"), key.clearsign(&text)?.as_bytes())?;
+    write_atomically(
+        &dir.join("Release.gpg"),
+        key.sign_detached(text.as_bytes())?.as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// The `Packages` stanza for a `.deb`, and its architecture.
+fn deb_stanza(path: &Path, relative: &str) -> Result<(Key, String, String), Error> {
+    let control = Control::read(io::BufReader::new(fs::File::open(path)?))?;
+    let arch = rfc822::fields_in_block(control.text.trim())
+        .collect_to_map()?
+        .get_value("Architecture")
+        .one_line_req()?
+        .to_string();
+
+    let mut stanza = String::new();
+    for (key, raw) in raw_fields(&control.text)? {
+        if !GENERATED_FIELDS.contains(&key) {
+            stanza.push_str(&raw);
+        }
+    }
+
+    let digests = Digests::of(fs::File::open(path)?)?;
+    writeln!(stanza, "Filename: {}", relative)?;
+    writeln!(stanza, "Size: {}", digests.len)?;
+    for (alg, hash) in &digests.hex() {
+        let key = match alg {
+            HashAlg::Md5 => "MD5sum",
+            other => other.by_hash_name(),
+        };
+        writeln!(stanza, "{}: {}", key, hash)?;
+    }
+
+    let pkg = control.package;
+    Ok(((pkg.name, pkg.version, arch.to_string()), arch, stanza))
+}
+
+/// The `Sources` stanza for a `.dsc`.
+///
+/// A `.dsc` has no `Section` or `Priority`, so they're taken from the first binary in its
+/// `Package-List`, or are `misc` and `optional` if it doesn't have one.
+fn dsc_stanza(path: &Path, relative: &str) -> Result<(Key, String), Error> {
+    let (text, _) = signing::read_clearsigned(io::BufReader::new(fs::File::open(path)?), None)?;
+    let text = text.trim();
+
+    let mut map = rfc822::fields_in_block(text).collect_to_map()?;
+    let (section, priority) = match map.get("Package-List").and_then(|list| list.first()) {
+        Some(first) => match first.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [_, _, section, priority, ..] => (section.to_string(), priority.to_string()),
+            _ => bail!("invalid Package-List line: {:?}", first),
+        },
+        None => ("misc".to_string(), "optional".to_string()),
+    };
+    let pkg = Package::parse_dsc(&mut map)?;
+
+    let digests = Digests::of(fs::File::open(path)?)?;
+    let (directory, name) = relative.rsplit_once('/').expect("inside the pool");
+    let hex: HashMap<&str, String> = digests
+        .hex()
+        .iter()
+        .map(|(alg, hash)| {
+            let key = match alg {
+                HashAlg::Md5 => "Files",
+                HashAlg::Sha1 => "Checksums-Sha1",
+                HashAlg::Sha256 => "Checksums-Sha256",
+                HashAlg::Sha512 => "Checksums-Sha512",
+            };
+            (key, hash.to_string())
+        })
+        .collect();
+
+    let mut stanza = format!("Package: {}\n", pkg.name);
+    let mut has_section = false;
+    let mut has_priority = false;
+
+    for (key, raw) in raw_fields(text)? {
+        match key {
+            "Source" | "Directory" => continue,
+            "Section" => has_section = true,
+            "Priority" => has_priority = true,
+            _ => (),
+        }
+        stanza.push_str(&raw);
+
+        // the index lists the .dsc itself, too
+        if let Some(hash) = hex.get(key) {
+            writeln!(stanza, " {} {} {}", hash, digests.len, name)?;
+        }
+    }
+
+    writeln!(stanza, "Directory: {}", directory)?;
+    if !has_section {
+        writeln!(stanza, "Section: {}", section)?;
+    }
+    if !has_priority {
+        writeln!(stanza, "Priority: {}", priority)?;
+    }
+
+    Ok(((pkg.name, pkg.version, "source".to_string()), stanza))
+}
+
+/// Split a block into its fields, keeping each exactly as written, including the newlines.
+fn raw_fields(block: &str) -> Result<Vec<(&str, String)>, Error> {
+    let mut fields: Vec<(&str, String)> = Vec::new();
+    for line in block.trim().lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            let (_, raw) = fields
+                .last_mut()
+                .ok_or_else(|| anyhow!("continuation with no field: {:?}", line))?;
+            raw.push_str(line);
+            raw.push('\n');
+            continue;
+        }
+
+        let colon = line
+            .find(':')
+            .ok_or_else(|| anyhow!("expected a key: in {:?}", line))?;
+        fields.push((&line[..colon], format!("{}\n", line)));
+    }
+    Ok(fields)
+}
+
+/// Write an index in every compression we offer, recording what we wrote in `contents`.
+fn write_index(
+    dists: &Path,
+    dir: &str,
+    name: &str,
+    stanzas: &[&str],
+    by_hash: bool,
+    contents: &mut Vec<(String, Digests)>,
+) -> Result<(), Error> {
+    let target = dists.join(dir);
+    fs::create_dir_all(&target).with_context(|| anyhow!("creating {:?}", target))?;
+
+    let plain = stanzas.join("\n").into_bytes();
+
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    gz.write_all(&plain)?;
+    let gz = gz.finish()?;
+
+    // the encoder describes a block it never wrote when given nothing, so `xz` can't read it
+    let xz = if plain.is_empty() {
+        EMPTY_XZ.to_vec()
+    } else {
+        let mut xz = lzma_rust2::XzWriter::new(Vec::new(), lzma_rust2::XzOptions::with_preset(6))?;
+        xz.write_all(&plain)?;
+        xz.finish()?
+    };
+
+    for (suffix, data) in [("", &plain), (".gz", &gz), (".xz", &xz)] {
+        let digests = Digests::of(data.as_slice())?;
+        let file_name = format!("{}{}", name, suffix);
+        write_atomically(&target.join(&file_name), data)?;
+
+        if by_hash {
+            for (alg, hash) in &digests.hex() {
+                let by_hash = target.join("by-hash").join(alg.by_hash_name());
+                fs::create_dir_all(&by_hash)?;
+                write_atomically(&by_hash.join(hash), data)?;
+            }
+        }
+
+        contents.push((format!("{}/{}", dir, file_name), digests));
+    }
+
+    Ok(())
+}
+
+fn release_text(
+    suite: &Suite,
+    components: &BTreeSet<String>,
+    arches: &BTreeSet<String>,
+    contents: &[(String, Digests)],
+) -> Result<String, Error> {
+    let mut text = String::new();
+    writeln!(text, "Origin: {}", suite.origin)?;
+    writeln!(text, "Label: {}", suite.label)?;
+    writeln!(text, "Suite: {}", suite.suite)?;
+    if let Some(codename) = &suite.codename {
+        writeln!(text, "Codename: {}", codename)?;
+    }
+    writeln!(
+        text,
+        "Date: {}",
+        suite.date.format("%a, %d %b %Y %H:%M:%S UTC")
+    )?;
+    if suite.by_hash {
+        writeln!(text, "Acquire-By-Hash: yes")?;
+    }
+    writeln!(
+        text,
+        "Architectures: {}",
+        arches.iter().cloned().collect::<Vec<_>>().join(" ")
+    )?;
+    writeln!(
+        text,
+        "Components: {}",
+        components.iter().cloned().collect::<Vec<_>>().join(" ")
+    )?;
+    if let Some(description) = &suite.description {
+        writeln!(text, "Description: {}", description)?;
+    }
+
+    for alg in [
+        HashAlg::Md5,
+        HashAlg::Sha1,
+        HashAlg::Sha256,
+        HashAlg::Sha512,
+    ] {
+        writeln!(text, "{}:", alg.by_hash_name())?;
+        for (name, digests) in contents {
+            writeln!(
+                text,
+                " {} {:>16} {}",
+                digests.hex_of(alg),
+                digests.len,
+                name
+            )?;
+        }
+    }
+
+    Ok(text)
+}
+
+/// Every file under `dir`, not following symlinks to directories.
+fn walk(dir: &Path, found: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), found)?;
+        } else {
+            found.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// `/srv/repo/pool/main/h/hello.deb` -> `pool/main/h/hello.deb`
+fn relative_name(root: &Path, path: &Path) -> Result<String, Error> {
+    let relative = path.strip_prefix(root)?;
+    let parts = relative
+        .components()
+        .map(|part| {
+            part.as_os_str()
+                .to_str()
+                .ok_or_else(|| anyhow!("non-utf-8 path: {:?}", path))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::Utc;
+    use reqwest::Url;
+
+    use super::publish;
+    use super::sign;
+    use super::Suite;
+    use crate::checksum::HashAlg;
+    use crate::lists;
+    use crate::release;
+    use crate::signing::GpgClient;
+    use crate::signing::Keys;
+    use crate::signing::SignaturePolicy;
+    use crate::signing::Signer;
+    use crate::signing::SigningKey;
+
+    #[test]
+    fn readable_by_lists() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        fs::create_dir_all(root.join("pool/main/f")).unwrap();
+        fs::write(
+            root.join("pool/main/f/fixture.deb"),
+            &include_bytes!("../tests/debs/fapt-fixture_gzip.deb")[..],
+        )
+        .unwrap();
+
+        let mut suite = Suite::new("sid");
+        suite.arches = vec!["amd64".to_string()];
+        suite.by_hash = true;
+        let release = release::parse_release_file(publish(root, &suite).unwrap()).unwrap();
+        assert!(release.acquire_by_hash);
+        assert_eq!(vec!["amd64"], release.arches);
+
+        let base = Url::parse("http://example.com/debian/dists/sid/").unwrap();
+        let listing = lists::Listing {
+            component: "main".to_string(),
+            arch: Some("amd64".to_string()),
+            directory: "binary".to_string(),
+            name: "Packages".to_string(),
+        };
+        let found = lists::find_file(&base, &release.contents, true, &listing).unwrap();
+
+        let path = found.url.path().trim_start_matches("/debian/dists/sid/");
+        assert!(path.starts_with("main/binary-amd64/by-hash/SHA256/"));
+        assert!(root.join("dists/sid").join(path).is_file());
+    }
+
+    #[test]
+    fn signed_release_verifies() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+
+        let mut suite = Suite::new("sid");
+        suite.components = vec!["main".to_string()];
+        suite.arches = vec!["amd64".to_string()];
+        let release = publish(root, &suite).unwrap();
+
+        let key = SigningKey::read(
+            &include_bytes!("../tests/keys/test-repo.secret.asc")[..],
+            None,
+        )
+        .unwrap();
+        sign(&release, &key).unwrap();
+
+        let mut keys = Keys::new();
+        keys.append_from(&include_bytes!("../tests/keys/test-repo.asc")[..])
+            .unwrap();
+        let policy = SignaturePolicy::default();
+        let mut gpg = GpgClient::new(&keys, &policy);
+
+        let dists = root.join("dists/sid");
+        let verified = dists.join("verified");
+        let signer = gpg
+            .verify_clearsigned(dists.join("InRelease"), &verified)
+            .unwrap();
+        assert_eq!(key.fingerprint(), signer.fingerprint);
+        assert_eq!(signer.fingerprint, signer.primary_fingerprint);
+        assert_eq!(key.key_id(), signer.key_id);
+        assert_eq!(HashAlg::Sha512, signer.hash);
+        assert!((Utc::now() - signer.created).num_minutes() < 5);
+        assert_eq!(signer, Signer::from_stanza(&signer.to_stanza()).unwrap());
+        assert_eq!(
+            fs::read_to_string(&release).unwrap().trim_end(),
+            fs::read_to_string(&verified).unwrap().trim_end()
+        );
+
+        let detached = gpg
+            .verify_detached(&release, dists.join("Release.gpg"), &verified)
+            .unwrap();
+        assert_eq!(signer.fingerprint, detached.fingerprint);
+
+        // and the signature doesn't cover a different release
+        fs::write(&release, "Origin: evil\n").unwrap();
+        assert!(gpg
+            .verify_detached(&release, dists.join("Release.gpg"), &verified)
+            .is_err());
+    }
+}
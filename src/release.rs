@@ -8,35 +8,44 @@ use std::path::Path;
 use std::path::PathBuf;
 
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::ensure;
 use anyhow::Context;
 use anyhow::Error;
 use chrono::DateTime;
 use chrono::Utc;
-use gpgrv::Keyring;
+use futures_util::future;
 use insideout::InsideOut;
 use reqwest::Url;
 
+use crate::auth;
 use crate::checksum::Hashes;
+use crate::fetch;
 use crate::fetch::fetch;
 use crate::fetch::Download;
 use crate::rfc822;
 use crate::rfc822::RfcMapExt;
 use crate::signing::GpgClient;
+use crate::signing::Keys;
+use crate::signing::SignaturePolicy;
+use crate::signing::Signer;
 use crate::sources_list::Entry;
 
 pub struct RequestedReleases {
-    releases: Vec<(RequestedRelease, Vec<Entry>)>,
+    pub(crate) releases: Vec<(RequestedRelease, Vec<Entry>)>,
 }
 
 #[derive(Clone, PartialOrd, Ord, Hash, PartialEq, Eq, Debug)]
 pub struct RequestedRelease {
-    mirror: Url,
+    pub(crate) mirror: Url,
     /// This can also be called "suite" in some places,
     /// e.g. "unstable" (suite) == "sid" (codename)
     pub codename: String,
 
     pub arches: Vec<String>,
+
+    /// The sources entries say `trusted=yes`, so an unsigned release will do.
+    pub trusted: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -67,6 +76,10 @@ pub struct Release {
     pub req: RequestedRelease,
     pub sources_entries: Vec<Entry>,
     pub file: ReleaseFile,
+    /// Who signed the release, when it was last downloaded; missing from older caches.
+    pub signer: Option<Signer>,
+    /// Accepted without a signature, as its sources entries say `trusted=yes`.
+    pub unsigned: bool,
 }
 
 impl fmt::Debug for ReleaseContent {
@@ -87,6 +100,11 @@ impl RequestedRelease {
             .join(&format!("{}/", self.codename))?)
     }
 
+    /// The location of a file from the pool, e.g. a `Filename` from a `Packages` file.
+    pub fn pool_url(&self, name: &str) -> Result<Url, Error> {
+        Ok(self.mirror.join(name)?)
+    }
+
     pub fn filesystem_safe(&self) -> String {
         let u = &self.mirror;
         let underscore_path = u
@@ -115,6 +133,12 @@ impl RequestedRelease {
             .as_ref()
             .join(format!("{}_Verified", self.filesystem_safe()))
     }
+
+    pub fn signer_path<P: AsRef<Path>>(&self, lists_dir: P) -> PathBuf {
+        lists_dir
+            .as_ref()
+            .join(format!("{}_Signer", self.filesystem_safe()))
+    }
 }
 
 impl RequestedReleases {
@@ -134,10 +158,13 @@ impl RequestedReleases {
                 "urls must end with a '/': {:?}",
                 entry.url
             );
+            let mut mirror = Url::parse(&entry.url)?;
+            auth::strip(&mut mirror);
             match ret.entry(RequestedRelease {
-                mirror: Url::parse(&entry.url)?,
+                mirror,
                 codename: entry.suite_codename.to_string(),
                 arches: arches.to_vec(),
+                trusted: false,
             }) {
                 hash_map::Entry::Vacant(vacancy) => {
                     vacancy.insert(vec![entry.clone()]);
@@ -146,54 +173,31 @@ impl RequestedReleases {
             }
         }
 
-        Ok(RequestedReleases {
-            releases: ret.into_iter().collect(),
-        })
+        let mut releases = Vec::with_capacity(ret.len());
+        for (mut release, entries) in ret {
+            release.trusted =
+                trusted(&entries).with_context(|| anyhow!("sources entries for {:?}", release))?;
+            releases.push((release, entries));
+        }
+
+        Ok(RequestedReleases { releases })
     }
 
-    pub fn download<P: AsRef<Path>>(
+    pub async fn download<P: AsRef<Path>>(
         &self,
         lists_dir: P,
-        keyring: &Keyring,
-        client: &reqwest::blocking::Client,
+        keys: &Keys,
+        policy: &SignaturePolicy,
+        client: &fetch::Client,
     ) -> Result<(), Error> {
         let lists_dir = lists_dir.as_ref();
 
-        let mut gpg = GpgClient::new(keyring);
-
-        for &(ref release, _) in &self.releases {
-            let dest: PathBuf = release.download_path(lists_dir);
-            let verified = release.verified_path(lists_dir);
-
-            match fetch(
-                client,
-                &[Download::from_to(
-                    release.dists()?.join("InRelease")?,
-                    &dest,
-                )],
-            ) {
-                Ok(_) => gpg.verify_clearsigned(&dest, &verified),
-                Err(_) => {
-                    let mut detatched_signature = dest.as_os_str().to_os_string();
-                    detatched_signature.push(".gpg");
-
-                    fetch(
-                        client,
-                        &[Download::from_to(release.dists()?.join("Release")?, &dest)],
-                    )?;
-
-                    fetch(
-                        client,
-                        &[Download::from_to(
-                            release.dists()?.join("Release.gpg")?,
-                            &detatched_signature,
-                        )],
-                    )?;
-                    gpg.verify_detached(&dest, detatched_signature, verified)
-                }
-            }
-            .with_context(|| anyhow!("verifying {:?} at {:?}", release, dest))?;
-        }
+        future::try_join_all(
+            self.releases
+                .iter()
+                .map(|(release, _)| download_release(release, lists_dir, keys, policy, client)),
+        )
+        .await?;
 
         Ok(())
     }
@@ -202,16 +206,114 @@ impl RequestedReleases {
         self.releases
             .into_iter()
             .map(|(req, sources_entries)| {
-                parse_release_file(req.verified_path(&lists_dir)).map(|file| Release {
+                let file = parse_release_file(req.verified_path(&lists_dir))?;
+                let (signer, unsigned) = match fs::read_to_string(req.signer_path(&lists_dir)) {
+                    Ok(text) if UNSIGNED == text => (None, true),
+                    Ok(text) => (
+                        Some(
+                            Signer::from_stanza(&text)
+                                .with_context(|| anyhow!("reading signer of {:?}", req))?,
+                        ),
+                        false,
+                    ),
+                    Err(e) if io::ErrorKind::NotFound == e.kind() => (None, false),
+                    Err(e) => return Err(e.into()),
+                };
+                ensure!(
+                    !unsigned || req.trusted,
+                    "{:?} was downloaded unsigned, but is no longer trusted=yes; update again",
+                    req
+                );
+                Ok(Release {
                     req,
                     file,
                     sources_entries,
+                    signer,
+                    unsigned,
                 })
             })
             .collect::<Result<Vec<Release>, Error>>()
     }
 }
 
+async fn download_release(
+    release: &RequestedRelease,
+    lists_dir: &Path,
+    keys: &Keys,
+    policy: &SignaturePolicy,
+    client: &fetch::Client,
+) -> Result<(), Error> {
+    let mut gpg = GpgClient::new(keys, policy);
+
+    let dest: PathBuf = release.download_path(lists_dir);
+    let verified = release.verified_path(lists_dir);
+
+    let signer = match fetch(
+        client,
+        &[Download::from_to(
+            release.dists()?.join("InRelease")?,
+            &dest,
+        )],
+    )
+    .await
+    {
+        Ok(_) => gpg.verify_clearsigned(&dest, &verified),
+        Err(_) => {
+            let mut detatched_signature = dest.as_os_str().to_os_string();
+            detatched_signature.push(".gpg");
+
+            fetch(
+                client,
+                &[
+                    Download::from_to(release.dists()?.join("Release")?, &dest),
+                    Download::from_to(release.dists()?.join("Release.gpg")?, &detatched_signature),
+                ],
+            )
+            .await
+            .and_then(|()| gpg.verify_detached(&dest, detatched_signature, &verified))
+        }
+    }
+    .with_context(|| anyhow!("verifying {:?} at {:?}", release, dest));
+
+    let record = match signer {
+        Ok(signer) => signer.to_stanza(),
+        Err(_) if release.trusted => {
+            fetch(
+                client,
+                &[Download::from_to(release.dists()?.join("Release")?, &dest)],
+            )
+            .await
+            .with_context(|| anyhow!("fetching unsigned Release for trusted {:?}", release))?;
+            fs::copy(&dest, &verified)?;
+            UNSIGNED.to_string()
+        }
+        Err(e) => return Err(e),
+    };
+
+    fs::write(release.signer_path(lists_dir), record)
+        .with_context(|| anyhow!("recording signer of {:?}", release))
+}
+
+/// Recorded instead of a signer, for a release accepted as `trusted=yes`.
+const UNSIGNED: &str = "Unsigned: yes\n";
+
+/// Whether some sources entries for the same release say it's `trusted=yes`;
+/// like `apt`, they mustn't disagree.
+fn trusted(entries: &[Entry]) -> Result<bool, Error> {
+    let mut values = entries.iter().map(|entry| {
+        Ok(match entry.options.get("trusted").map(String::as_str) {
+            None | Some("no") => false,
+            Some("yes") => true,
+            Some(other) => bail!("invalid value for trusted: {:?}", other),
+        })
+    });
+    let first = values.next().unwrap_or(Ok(false))?;
+    for value in values {
+        ensure!(first == value?, "conflicting values for trusted");
+    }
+    Ok(first)
+}
+
 pub fn parse_release_file<P: AsRef<Path>>(path: P) -> Result<ReleaseFile, Error> {
     let mut file = String::with_capacity(100 * 1024);
     io::BufReader::new(
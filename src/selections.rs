@@ -0,0 +1,152 @@
+//! What the admin asked for: which packages apt installed only as dependencies, and what
+//! `dpkg` has been told to do with each package, including holding it at its version.
+//!
+//! ```no_run
+//! # use fapt::system::System;
+//! let mut fapt = System::cache_only()?;
+//! fapt.set_dpkg_database("/var/lib/dpkg");
+//! fapt.set_extended_states("/var/lib/apt/extended_states");
+//! for mark in fapt.marks()? {
+//!     if !mark.auto_installed {
+//!         println!("{}", mark.package);
+//!     }
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::io::Read;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Error;
+
+use crate::parse::Want;
+use crate::rfc822;
+use crate::rfc822::RfcMapExt;
+use crate::status;
+
+/// An entry in apt's `extended_states`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedState {
+    pub package: String,
+    /// Architecture-independent packages are recorded as the native architecture. Missing
+    /// in files written before multiarch.
+    pub arch: Option<String>,
+    /// Only installed to satisfy a dependency, so can be removed when nothing needs it.
+    pub auto_installed: bool,
+}
+
+/// A package's selection, e.g. a line of `dpkg --get-selections`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub package: String,
+    /// Only given if it's needed to tell packages apart, e.g. `libc6:i386`.
+    pub arch: Option<String>,
+    pub want: Want,
+}
+
+/// How an installed package was marked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mark {
+    pub package: String,
+    pub arch: Option<String>,
+    pub auto_installed: bool,
+    /// `dpkg` and apt won't upgrade or remove it.
+    pub held: bool,
+}
+
+/// Read apt's `extended_states` file.
+pub fn read_extended_states<R: Read>(from: R) -> Result<Vec<ExtendedState>, Error> {
+    let mut ret = Vec::new();
+    for block in rfc822::Blocks::new(from, "extended_states".to_string()) {
+        let block = block?;
+        let map = rfc822::fields_in_block(&block).collect_to_map()?;
+        let package = map.get_value("Package").one_line_req()?;
+        let auto_installed = match map.get_value("Auto-Installed").one_line()? {
+            None | Some("0") => false,
+            Some("1") => true,
+            Some(other) => bail!("{:?}: invalid Auto-Installed: {:?}", package, other),
+        };
+        ret.push(ExtendedState {
+            package: package.to_string(),
+            arch: map.get_value("Architecture").one_line_owned()?,
+            auto_installed,
+        });
+    }
+    Ok(ret)
+}
+
+/// Read the output of `dpkg --get-selections`, as accepted by `dpkg --set-selections`.
+pub fn read_selections<R: BufRead>(from: R) -> Result<Vec<Selection>, Error> {
+    let mut ret = Vec::new();
+    for line in from.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, want) = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [name, want] => (*name, *want),
+            _ => bail!("expected a package and a selection: {:?}", line),
+        };
+        let (package, arch) = match name.split_once(':') {
+            Some((package, arch)) => (package, Some(arch.to_string())),
+            None => (name, None),
+        };
+        ret.push(Selection {
+            package: package.to_string(),
+            arch,
+            want: want
+                .parse()
+                .with_context(|| anyhow!("selection for {:?}", name))?,
+        });
+    }
+    Ok(ret)
+}
+
+/// The marks of the installed packages in the `status` database, according to `extended`,
+/// which was written on a system whose native architecture is `native`, if we know it.
+pub(crate) fn marks(
+    status: &status::Database,
+    extended: &[ExtendedState],
+    native: Option<&str>,
+) -> Vec<Mark> {
+    // package -> arch -> auto
+    let mut auto: HashMap<&str, HashMap<Option<&str>, bool>> = HashMap::new();
+    for state in extended {
+        auto.entry(&state.package)
+            .or_default()
+            .insert(state.arch.as_deref(), state.auto_installed);
+    }
+
+    status
+        .installed()
+        .map(|entry| {
+            let recorded = auto.get(entry.package.as_str());
+            let auto_installed = recorded
+                .and_then(|by_arch| {
+                    by_arch
+                        .get(&entry.arch.as_deref())
+                        .or_else(|| by_arch.get(&None))
+                        // recorded under the native architecture, which, if we don't know it,
+                        // can only be the one recorded
+                        .or_else(|| match (entry.arch.as_deref(), native) {
+                            (Some("all"), Some(native)) => by_arch.get(&Some(native)),
+                            (Some("all"), None) if 1 == by_arch.len() => by_arch.values().next(),
+                            _ => None,
+                        })
+                })
+                .copied()
+                .unwrap_or(false);
+            Mark {
+                package: entry.package.to_string(),
+                arch: entry.arch.clone(),
+                auto_installed,
+                held: Want::Hold == entry.status.want,
+            }
+        })
+        .collect()
+}
@@ -1,27 +1,740 @@
+//! Checking signatures on documents, e.g. `Release` files, or `.dsc`s, and making them.
+
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
 use std::path::Path;
+use std::slice;
 
 use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
 use anyhow::Context;
 use anyhow::Error;
+use cfb_mode::cipher::AsyncStreamCipher;
+use cfb_mode::cipher::KeyIvInit;
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::Utc;
 use gpgrv::Keyring;
+use rsa::BigUint;
+use rsa::Pkcs1v15Sign;
+use rsa::RsaPrivateKey;
+use sha1::Digest;
+use sha2::Sha512;
 use tempfile_fast::PersistableTempFile;
 
-pub struct GpgClient<'k> {
-    keyring: &'k Keyring,
+use crate::checksum::HashAlg;
+use crate::openpgp;
+use crate::rfc822;
+use crate::rfc822::RfcMapExt;
+
+const BEGIN_SIGNED_MESSAGE: &[u8] = b"-----BEGIN PGP SIGNED MESSAGE-----";
+
+/// What we know about the signature on a document.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// The document was not signed at all.
+    Unsigned,
+    /// The document was signed, but we had no keys to check it with.
+    Unchecked,
+    /// The document was signed by a key in the provided keyring.
+    Verified,
+}
+
+/// Read a possibly clearsigned document (e.g. a `.dsc`), returning the signed content.
+///
+/// If a keyring is provided, the document must be signed by a key in it.
+pub fn read_clearsigned<R: BufRead>(
+    mut from: R,
+    keyring: Option<&Keyring>,
+) -> Result<(String, SignatureStatus), Error> {
+    let mut content = Vec::with_capacity(4 * 1024);
+
+    let signed = from.fill_buf()?.starts_with(BEGIN_SIGNED_MESSAGE);
+
+    let status = match (signed, keyring) {
+        (false, Some(_)) => bail!("document is not signed, but a keyring was provided"),
+        (false, None) => {
+            from.read_to_end(&mut content)?;
+            SignatureStatus::Unsigned
+        }
+        (true, None) => {
+            gpgrv::read_doc(from, &mut content).with_context(|| anyhow!("stripping signature"))?;
+            SignatureStatus::Unchecked
+        }
+        (true, Some(keyring)) => {
+            let doc = gpgrv::read_doc(from, &mut content)
+                .with_context(|| anyhow!("reading signed document"))?;
+            let body = doc
+                .body
+                .ok_or_else(|| anyhow!("document wasn't a message (i.e. there was no body)"))?;
+            let signatures: Vec<_> = doc
+                .signatures
+                .into_iter()
+                .filter(|signature| body.sig_type == signature.sig_type)
+                .collect();
+            ensure!(
+                !signatures.is_empty(),
+                "no signatures are of the correct type"
+            );
+            any_signature_valid(keyring, &signatures, &body.digest).map_err(|errors| {
+                anyhow!("verifying signature: no valid signatures: {}", errors)
+            })?;
+            SignatureStatus::Verified
+        }
+    };
+
+    Ok((String::from_utf8(content)?, status))
+}
+
+/// Read a clearsigned document (e.g. a `.dsc`), which must be signed by one of `keys`, in a
+/// way `policy` accepts, returning the signed content, and who signed it.
+pub(crate) fn read_clearsigned_by<R: BufRead>(
+    mut from: R,
+    keys: &Keys,
+    policy: &SignaturePolicy,
+) -> Result<(String, Signer), Error> {
+    ensure!(
+        from.fill_buf()?.starts_with(BEGIN_SIGNED_MESSAGE),
+        "document is not signed, but keys were provided"
+    );
+    let mut content = Vec::with_capacity(4 * 1024);
+    let signer = read_signed(from, &mut content, keys, policy)?;
+    Ok((String::from_utf8(content)?, signer))
+}
+
+/// Check a clearsigned document, writing its content to `to`.
+fn read_signed<R: BufRead, W: Write>(
+    from: R,
+    to: W,
+    keys: &Keys,
+    policy: &SignaturePolicy,
+) -> Result<Signer, Error> {
+    let doc = gpgrv::read_doc(from, to).with_context(|| anyhow!("reading signed document"))?;
+    let body = doc
+        .body
+        .ok_or_else(|| anyhow!("document wasn't a message (i.e. there was no body)"))?;
+    let signatures: Vec<_> = doc
+        .signatures
+        .into_iter()
+        .filter(|signature| body.sig_type == signature.sig_type)
+        .collect();
+    ensure!(
+        !signatures.is_empty(),
+        "no signatures are of the correct type"
+    );
+    keys.signer(&signatures, &body.digest, policy)
+}
+
+/// Keys to check signatures with: a `gpgrv` keyring, and what we learnt about each key while
+/// loading it, which `gpgrv` doesn't keep, so we can say who made a signature.
+#[derive(Clone, Default)]
+pub struct Keys {
+    keyring: Keyring,
+    details: HashMap<u64, Vec<KeyDetails>>,
+}
+
+/// A key (or subkey) we've loaded, by fingerprint.
+#[derive(Clone, Debug)]
+struct KeyDetails {
+    fingerprint: [u8; 20],
+    primary: [u8; 20],
+    user_id: Option<String>,
+    expires: Option<DateTime<Utc>>,
+    revoked: bool,
+}
+
+/// Which good signatures to accept. By default, like `apt`, signatures by expired or revoked
+/// keys, or over weak hashes (i.e. MD5 or SHA-1), are refused.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SignaturePolicy {
+    /// Accept signatures from keys which have expired (by the time of the signature, or by
+    /// now), and signatures which have themselves expired.
+    pub allow_expired: bool,
+    /// Accept signatures from keys which the keyring says have been revoked.
+    pub allow_revoked: bool,
+    /// Accept signatures over MD5 or SHA-1 digests.
+    pub allow_weak_hashes: bool,
+}
+
+/// Who made a signature we checked, and how.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signer {
+    /// The last eight bytes of `fingerprint`, as the signature names its key.
+    pub key_id: u64,
+    /// The key which made the signature, e.g. a signing subkey; forty upper-case hex digits.
+    pub fingerprint: String,
+    /// The primary key `fingerprint` belongs to, which is the same if it signed directly.
+    pub primary_fingerprint: String,
+    /// The primary key's first user id, e.g. `Debian Stable Release Key (12/bookworm) <...>`.
+    pub user_id: Option<String>,
+    /// When the signature claims it was made.
+    pub created: DateTime<Utc>,
+    /// The hash the signature was made over.
+    pub hash: HashAlg,
+}
+
+impl Keys {
+    pub fn new() -> Keys {
+        Keys::default()
+    }
+
+    /// The keys, for checking signatures with, e.g. [read_clearsigned].
+    pub fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    /// Add the public keys from a key file: armoured (`.asc`), binary (`.gpg`),
+    /// or a GnuPG keybox (`.kbx`), which are told apart by their content.
+    ///
+    /// Like `apt`, keys (or subkeys) which can't be used, e.g. as their algorithm isn't
+    /// supported, are skipped with a warning, rather than rejecting the whole file.
+    /// Returns how many keys were added.
+    pub fn append_from<R: Read>(&mut self, mut from: R) -> Result<usize, Error> {
+        let mut data = Vec::new();
+        from.read_to_end(&mut data)?;
+
+        let mut keyblocks = Vec::new();
+        let text = std::str::from_utf8(&data)
+            .ok()
+            .filter(|text| text.trim_start().starts_with("-----BEGIN PGP "));
+        if openpgp::is_keybox(&data) {
+            for keyblock in
+                openpgp::keybox_keyblocks(&data).with_context(|| anyhow!("reading keybox"))?
+            {
+                keyblocks.push(keyblock.to_vec());
+            }
+        } else if let Some(text) = text {
+            for (kind, block) in openpgp::armoured_blocks(text) {
+                if "PUBLIC KEY BLOCK" != kind {
+                    warn(format_args!("ignoring armoured {}", kind))?;
+                    continue;
+                }
+                match openpgp::dearmor(block.as_bytes()) {
+                    Ok(keyblock) => keyblocks.push(keyblock),
+                    Err(e) => warn(format_args!("ignoring armoured key: {}", e))?,
+                }
+            }
+        } else {
+            keyblocks.push(data);
+        }
+
+        let mut added = 0;
+        let mut found = false;
+        for keyblock in &keyblocks {
+            let (packets, rest) = openpgp::packets_prefix(keyblock);
+            if let Some(e) = rest {
+                warn(format_args!("ignoring the rest of a keyring: {}", e))?;
+            }
+
+            // a transferable public key is a primary key, and everything up to the next
+            let mut starts: Vec<usize> = packets
+                .iter()
+                .enumerate()
+                .filter(|(_, packet)| 6 == packet.tag)
+                .map(|(i, _)| i)
+                .collect();
+            found |= !starts.is_empty();
+            starts.push(packets.len());
+            for window in starts.windows(2) {
+                if self.append_key(&packets[window[0]..window[1]])? {
+                    added += 1;
+                }
+            }
+        }
+
+        ensure!(found, "no public keys found");
+        Ok(added)
+    }
+
+    /// Add a primary key and its subkeys, as `gpgrv` parses them, without the signatures and
+    /// user ids it'd ignore anyway, so a problem with one key doesn't lose the rest.
+    fn append_key(&mut self, packets: &[openpgp::Packet]) -> Result<bool, Error> {
+        if let Err(e) = self.add(packets, 0) {
+            warn(format_args!("skipping key {}: {}", describe(packets), e))?;
+            return Ok(false);
+        }
+
+        for (at, _) in packets
+            .iter()
+            .enumerate()
+            .filter(|(_, packet)| 14 == packet.tag)
+        {
+            if let Err(e) = self.add(packets, at) {
+                warn(format_args!(
+                    "skipping a subkey of {}: {}",
+                    describe(packets),
+                    e
+                ))?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Add the key at `at` in a transferable public key, `packets`, whose first is the primary key.
+    fn add(&mut self, packets: &[openpgp::Packet], at: usize) -> Result<(), Error> {
+        let key = packets[at];
+        for packet in &[packets[0], key] {
+            ensure!(
+                Some(&4) == packet.body.first(),
+                "unsupported key version: {:?}",
+                packet.body.first()
+            );
+        }
+        self.keyring
+            .append_keys_from(io::Cursor::new(openpgp::packet(key.tag, key.body)))?;
+
+        // a subkey is only as good as its primary key
+        let (mut expires, mut revoked) = self.self_signatures(packets, at);
+        if 0 != at {
+            let (primary_expires, primary_revoked) = self.self_signatures(packets, 0);
+            expires = match (expires, primary_expires) {
+                (Some(ours), Some(primary)) => Some(ours.min(primary)),
+                (ours, primary) => ours.or(primary),
+            };
+            revoked |= primary_revoked;
+        }
+
+        let fingerprint = openpgp::v4_fingerprint(key.body);
+        let user_id = packets
+            .iter()
+            .find(|packet| 13 == packet.tag)
+            .map(|user| String::from_utf8_lossy(user.body).to_string());
+        self.details
+            .entry(key_id(&fingerprint))
+            .or_default()
+            .push(KeyDetails {
+                fingerprint,
+                primary: openpgp::v4_fingerprint(packets[0].body),
+                user_id,
+                expires,
+                revoked,
+            });
+        Ok(())
+    }
+
+    /// When the key at `at` expires, and whether it has been revoked, according to the
+    /// signatures its primary key made over it. Signatures we can't check are ignored.
+    ///
+    /// https://tools.ietf.org/html/rfc4880#section-5.2.4
+    fn self_signatures(
+        &self,
+        packets: &[openpgp::Packet],
+        at: usize,
+    ) -> (Option<DateTime<Utc>>, bool) {
+        let primary = packets[0].body;
+        let key = packets[at].body;
+
+        // the latest self-signature's creation time, and its key expiration time
+        let mut latest: Option<(u32, Option<u32>)> = None;
+        let mut revoked = false;
+        let mut user_id = None;
+
+        for packet in packets[at + 1..]
+            .iter()
+            .take_while(|packet| 14 != packet.tag)
+        {
+            match packet.tag {
+                2 => (),
+                13 => {
+                    user_id = Some(packet.body);
+                    continue;
+                }
+                // user attributes, e.g. photos, whose signatures don't interest us
+                17 => {
+                    user_id = None;
+                    continue;
+                }
+                _ => continue,
+            }
+
+            let signature = packet.body;
+            let sig_type = match signature.get(..2) {
+                Some(&[4, sig_type]) => sig_type,
+                _ => continue,
+            };
+
+            let mut content = vec![key_material(primary)];
+            let revocation = match (sig_type, user_id) {
+                // certifications of a user id, and signatures directly on the primary key
+                (0x10..=0x13, Some(user_id)) if 0 == at => {
+                    let mut material = vec![0xb4];
+                    material.extend_from_slice(&(user_id.len() as u32).to_be_bytes());
+                    material.extend_from_slice(user_id);
+                    content.push(material);
+                    false
+                }
+                (0x1f, _) if 0 == at => false,
+                (0x20, _) if 0 == at => true,
+                // subkey binding, and revocation
+                (0x18, _) if 0 != at => {
+                    content.push(key_material(key));
+                    false
+                }
+                (0x28, _) if 0 != at => {
+                    content.push(key_material(key));
+                    true
+                }
+                _ => continue,
+            };
+
+            if !self.self_signed(primary, signature, &content) {
+                continue;
+            }
+
+            if revocation {
+                revoked = true;
+                continue;
+            }
+
+            let subpackets = match openpgp::hashed_subpackets(signature) {
+                Ok(subpackets) => subpackets,
+                Err(_) => continue,
+            };
+            let created = time_subpacket(&subpackets, 2).unwrap_or(0);
+            if latest.is_none_or(|(latest, _)| created >= latest) {
+                latest = Some((created, time_subpacket(&subpackets, 9)));
+            }
+        }
+
+        let key_created = openpgp::read_u32(&key[1..]).unwrap_or(0);
+        let expires = latest
+            .and_then(|(_, expiry)| expiry)
+            .filter(|&expiry| 0 != expiry)
+            .and_then(|expiry| timestamp(u64::from(key_created) + u64::from(expiry)));
+        (expires, revoked)
+    }
+
+    /// Is `signature` (the body of a packet) a good signature by `primary`, over `content`?
+    fn self_signed(&self, primary: &[u8], signature: &[u8], content: &[Vec<u8>]) -> bool {
+        // `gpgrv` doesn't know key revocations, but they're made over the same content as
+        // signatures directly on a key, so it can check one disguised as the other
+        let sig_type = signature[1];
+        let mut disguised = signature.to_vec();
+        if 0x20 == sig_type {
+            disguised[1] = 0x1f;
+        }
+        let doc = match gpgrv::read_doc(io::Cursor::new(openpgp::packet(2, &disguised)), io::sink())
+        {
+            Ok(doc) => doc,
+            Err(_) => return false,
+        };
+        let mut signature = match doc.signatures.into_iter().next() {
+            Some(signature) => signature,
+            None => return false,
+        };
+        signature.authenticated_data[1] = sig_type;
+
+        let issuer = key_id(&openpgp::v4_fingerprint(primary)).to_be_bytes();
+        if Some(issuer) != signature.issuer {
+            return false;
+        }
+
+        let mut digest = match digestable(signature.hash_alg) {
+            Some(digest) => digest,
+            None => return false,
+        };
+        for part in content {
+            digest.process(part);
+        }
+        any_signature_valid(&self.keyring, &[signature], &digest).is_ok()
+    }
+
+    /// Check some signatures over a digest, returning who made the first which is good, and
+    /// acceptable to the `policy`.
+    fn signer(
+        &self,
+        signatures: &[gpgrv::Signature],
+        digest: &gpgrv::Digestable,
+        policy: &SignaturePolicy,
+    ) -> Result<Signer, Error> {
+        let mut errors = Vec::new();
+        for signature in signatures {
+            if let Err(e) = any_signature_valid(&self.keyring, slice::from_ref(signature), digest) {
+                errors.push(e);
+                continue;
+            }
+            let (signer, key, expires) = self.describe(signature)?;
+            match policy.check(&signer, key, expires) {
+                Ok(()) => return Ok(signer),
+                Err(e) => errors.push(format!("signature by {}: {}", signer.fingerprint, e)),
+            }
+        }
+        bail!("no acceptable signatures: {}", errors.join("; "))
+    }
+
+    /// What we know about a (good) signature, the key which made it, and when the signature
+    /// itself expires, if ever.
+    fn describe(
+        &self,
+        signature: &gpgrv::Signature,
+    ) -> Result<(Signer, &KeyDetails, Option<DateTime<Utc>>), Error> {
+        let subpackets = openpgp::hashed_subpackets(&signature.authenticated_data)?;
+        let created = time_subpacket(&subpackets, 2)
+            .and_then(|created| timestamp(u64::from(created)))
+            .ok_or_else(|| anyhow!("signature has no creation time"))?;
+        let expires = time_subpacket(&subpackets, 3)
+            .filter(|&expiry| 0 != expiry)
+            .and_then(|expiry| timestamp(created.timestamp() as u64 + u64::from(expiry)));
+        let issuer_fingerprint = subpackets
+            .iter()
+            .find(|(kind, body)| 33 == *kind && 21 == body.len() && 4 == body[0])
+            .map(|(_, body)| &body[1..]);
+
+        let key_id = u64::from_be_bytes(
+            signature
+                .issuer
+                .ok_or_else(|| anyhow!("signature has no issuer"))?,
+        );
+
+        let details = signed_by(
+            self.details.get(&key_id).map(Vec::as_slice).unwrap_or(&[]),
+            key_id,
+            issuer_fingerprint,
+        )?;
+
+        let hash = match signature.hash_alg {
+            gpgrv::HashAlg::Sha1 => HashAlg::Sha1,
+            gpgrv::HashAlg::Sha256 => HashAlg::Sha256,
+            gpgrv::HashAlg::Sha512 => HashAlg::Sha512,
+            other => bail!("unexpected hash in a good signature: {:?}", other),
+        };
+
+        let signer = Signer {
+            key_id,
+            fingerprint: hex::encode_upper(details.fingerprint),
+            primary_fingerprint: hex::encode_upper(details.primary),
+            user_id: details.user_id.clone(),
+            created,
+            hash,
+        };
+        Ok((signer, details, expires))
+    }
+}
+
+/// Which of the keys with the signature's `key_id` made it. Ids can collide, but the
+/// signature may also name the whole fingerprint; if it doesn't, we can't tell them apart.
+fn signed_by<'k>(
+    keys: &'k [KeyDetails],
+    key_id: u64,
+    issuer_fingerprint: Option<&[u8]>,
+) -> Result<&'k KeyDetails, Error> {
+    match (keys, issuer_fingerprint) {
+        ([], _) => bail!("no details for key {:016X}", key_id),
+        (keys, Some(fingerprint)) => keys
+            .iter()
+            .find(|key| fingerprint == &key.fingerprint[..])
+            .ok_or_else(|| {
+                anyhow!(
+                    "no details for key {}, signing as {:016X}",
+                    hex::encode_upper(fingerprint),
+                    key_id
+                )
+            }),
+        ([only], None) => Ok(only),
+        (keys, None) => bail!(
+            "ambiguous signer: {} keys have the id {:016X}, and the signature doesn't say which",
+            keys.len(),
+            key_id
+        ),
+    }
+}
+
+impl SignaturePolicy {
+    /// Refuse a good signature, saying which rule it breaks, if it breaks any.
+    fn check(
+        &self,
+        signer: &Signer,
+        key: &KeyDetails,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        ensure!(
+            self.allow_weak_hashes || signer.hash > HashAlg::Sha1,
+            "{} is too weak a hash (see allow_weak_hashes)",
+            signer.hash.by_hash_name()
+        );
+        ensure!(
+            self.allow_revoked || !key.revoked,
+            "the key has been revoked (see allow_revoked)"
+        );
+        if self.allow_expired {
+            return Ok(());
+        }
+        let now = Utc::now();
+        if let Some(expiry) = key.expires {
+            ensure!(
+                signer.created <= expiry,
+                "the key had expired, at {}, when it was made (see allow_expired)",
+                expiry
+            );
+            ensure!(
+                now <= expiry,
+                "the key expired at {} (see allow_expired)",
+                expiry
+            );
+        }
+        if let Some(expiry) = expires {
+            ensure!(
+                now <= expiry,
+                "the signature expired at {} (see allow_expired)",
+                expiry
+            );
+        }
+        Ok(())
+    }
+}
+
+fn key_id(fingerprint: &[u8; 20]) -> u64 {
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&fingerprint[12..]);
+    u64::from_be_bytes(id)
+}
+
+/// A key, as it's hashed when signatures are made over it.
+fn key_material(body: &[u8]) -> Vec<u8> {
+    let mut material = vec![0x99];
+    material.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    material.extend_from_slice(body);
+    material
+}
+
+/// A (four byte) time, or period, subpacket, e.g. `2`, the creation time.
+fn time_subpacket(subpackets: &[(u8, &[u8])], kind: u8) -> Option<u32> {
+    subpackets
+        .iter()
+        .find(|(found, _)| kind == *found)
+        .and_then(|(_, body)| openpgp::read_u32(body))
+}
+
+fn timestamp(seconds: u64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(i64::try_from(seconds).ok()?, 0).single()
+}
+
+/// [gpgrv::any_signature_valid], but also accepting RSA signatures which are shorter than
+/// the key. They're integers, so lose any leading zero bytes, which one in 256 has; `gpgrv`
+/// rejects those, so they're padded back out to the length of each key which could have
+/// made them.
+fn any_signature_valid(
+    keyring: &Keyring,
+    signatures: &[gpgrv::Signature],
+    digest: &gpgrv::Digestable,
+) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for signature in signatures {
+        match gpgrv::any_signature_valid(keyring, Some(signature), digest) {
+            Ok(()) => return Ok(()),
+            Err(e) => errors.push(format!("{:?}", e)),
+        }
+
+        let (sig, issuer) = match (&signature.sig, signature.issuer) {
+            (gpgrv::PublicKeySig::Rsa(sig), Some(issuer)) => (sig, issuer),
+            _ => continue,
+        };
+        for key in keyring.keys_with_id(u64::from_be_bytes(issuer)) {
+            let len = match key {
+                gpgrv::PubKey::Rsa { n, .. } if n.len() > sig.len() => n.len(),
+                _ => continue,
+            };
+            let mut padded = signature.clone();
+            let mut value = vec![0; len - sig.len()];
+            value.extend_from_slice(sig);
+            padded.sig = gpgrv::PublicKeySig::Rsa(value);
+            if gpgrv::any_signature_valid(keyring, Some(&padded), digest).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+    Err(errors.join(", "))
+}
+
+fn digestable(alg: gpgrv::HashAlg) -> Option<gpgrv::Digestable> {
+    Some(match alg {
+        gpgrv::HashAlg::Sha1 => gpgrv::Digestable::sha1(),
+        gpgrv::HashAlg::Sha256 => gpgrv::Digestable::sha256(),
+        gpgrv::HashAlg::Sha512 => gpgrv::Digestable::sha512(),
+        _ => return None,
+    })
+}
+
+/// e.g. `7657...E20E (Test Repository <repo@example.com>)`, for humans.
+fn describe(packets: &[openpgp::Packet]) -> String {
+    let primary = packets[0].body;
+    let id = match primary.first() {
+        Some(4) => hex::encode_upper(openpgp::v4_fingerprint(primary)),
+        Some(version) => format!("with version {}", version),
+        None => "with no content".to_string(),
+    };
+    match packets.iter().find(|packet| 13 == packet.tag) {
+        Some(user) => format!("{} ({})", id, String::from_utf8_lossy(user.body)),
+        None => id,
+    }
+}
+
+fn warn(message: fmt::Arguments) -> Result<(), Error> {
+    writeln!(io::stderr(), "warning: {}", message)?;
+    Ok(())
+}
+
+impl Signer {
+    /// Record the signer as a stanza, to be read back by [Signer::from_stanza].
+    pub(crate) fn to_stanza(&self) -> String {
+        let mut text = format!(
+            "Key-Id: {:016X}\nFingerprint: {}\nPrimary-Fingerprint: {}\n",
+            self.key_id, self.fingerprint, self.primary_fingerprint
+        );
+        if let Some(user_id) = &self.user_id {
+            text.push_str(&format!("User-Id: {}\n", user_id));
+        }
+        text.push_str(&format!(
+            "Created: {}\nHash: {}\n",
+            self.created.format("%a, %d %b %Y %H:%M:%S UTC"),
+            self.hash.by_hash_name()
+        ));
+        text
+    }
+
+    pub(crate) fn from_stanza(text: &str) -> Result<Signer, Error> {
+        let mut data = rfc822::fields_in_block(text).collect_to_map()?;
+        let hash = data.remove_value("Hash").one_line_req()?.to_string();
+        Ok(Signer {
+            key_id: u64::from_str_radix(data.remove_value("Key-Id").one_line_req()?, 16)?,
+            fingerprint: data.remove_value("Fingerprint").one_line_req()?.to_string(),
+            primary_fingerprint: data
+                .remove_value("Primary-Fingerprint")
+                .one_line_req()?
+                .to_string(),
+            user_id: data.remove_value("User-Id").one_line_owned()?,
+            created: rfc822::parse_date(data.remove_value("Created").one_line_req()?)?,
+            hash: [HashAlg::Sha1, HashAlg::Sha256, HashAlg::Sha512]
+                .into_iter()
+                .find(|alg| hash == alg.by_hash_name())
+                .ok_or_else(|| anyhow!("unrecognised hash: {:?}", hash))?,
+        })
+    }
+}
+
+pub(crate) struct GpgClient<'k> {
+    keys: &'k Keys,
+    policy: &'k SignaturePolicy,
 }
 
 impl<'k> GpgClient<'k> {
-    pub fn new(keyring: &Keyring) -> GpgClient {
-        GpgClient { keyring }
+    pub fn new(keys: &'k Keys, policy: &'k SignaturePolicy) -> GpgClient<'k> {
+        GpgClient { keys, policy }
     }
 
+    /// Check a clearsigned file, writing its content to `dest`.
     pub fn verify_clearsigned<P: AsRef<Path>, Q: AsRef<Path>>(
         &self,
         file: P,
         dest: Q,
-    ) -> Result<(), Error> {
+    ) -> Result<Signer, Error> {
         let from = fs::File::open(file).with_context(|| anyhow!("opening input file"))?;
         let to = PersistableTempFile::new_in(
             dest.as_ref()
@@ -30,29 +743,529 @@ impl<'k> GpgClient<'k> {
         )
         .with_context(|| anyhow!("creating temporary file"))?;
 
-        gpgrv::verify_message(io::BufReader::new(from), &to, &self.keyring)?;
+        let signer = read_signed(
+            io::BufReader::new(from),
+            io::BufWriter::new(&to),
+            self.keys,
+            self.policy,
+        )?;
 
         to.persist_by_rename(dest)
             .map_err(|e| e.error)
             .with_context(|| anyhow!("persisting output file"))?;
 
-        Ok(())
+        Ok(signer)
     }
 
+    /// Check a detached signature over `file`, copying it to `dest`.
     pub fn verify_detached<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
         &mut self,
         file: P,
         signature: Q,
         dest: R,
-    ) -> Result<(), Error> {
-        gpgrv::verify_detached(
+    ) -> Result<Signer, Error> {
+        let doc = gpgrv::read_doc(
             io::BufReader::new(
                 fs::File::open(signature).with_context(|| anyhow!("opening signature file"))?,
             ),
-            fs::File::open(file.as_ref()).with_context(|| anyhow!("opening input file"))?,
-            &self.keyring,
+            io::sink(),
+        )?;
+        ensure!(doc.body.is_none(), "detached signature was a message");
+        ensure!(
+            !doc.signatures.is_empty(),
+            "no signatures in signature file"
+        );
+
+        let mut errors = Vec::new();
+        for signature in &doc.signatures {
+            // of a binary document, i.e. type zero
+            if Some(&0) != signature.authenticated_data.get(1) {
+                errors.push(anyhow!("unsupported: detached signature of another type"));
+                continue;
+            }
+            let mut digest = match digestable(signature.hash_alg) {
+                Some(digest) => digest,
+                None => {
+                    errors.push(anyhow!("unsupported: hash type {:?}", signature.hash_alg));
+                    continue;
+                }
+            };
+            let mut message =
+                fs::File::open(file.as_ref()).with_context(|| anyhow!("opening input file"))?;
+            let mut buf = [0u8; 8 * 1024];
+            loop {
+                let read = message.read(&mut buf)?;
+                if 0 == read {
+                    break;
+                }
+                digest.process(&buf[..read]);
+            }
+            match self
+                .keys
+                .signer(std::slice::from_ref(signature), &digest, self.policy)
+            {
+                Ok(signer) => {
+                    fs::copy(file, dest)?;
+                    return Ok(signer);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        bail!("no valid signatures: {:?}", errors)
+    }
+}
+
+/// An RSA secret key, for signing documents, e.g. `Release` files, without `gpg`.
+///
+/// Only RSA is supported, as it's all we can verify.
+pub struct SigningKey {
+    fingerprint: [u8; 20],
+    key: RsaPrivateKey,
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SigningKey {{ {} }}", self.fingerprint())
+    }
+}
+
+impl SigningKey {
+    /// Load the primary key from a transferable secret key, as exported by
+    /// `gpg --export-secret-keys`, armoured or not.
+    ///
+    /// The `passphrase` is only needed if the key is protected by one.
+    pub fn read<R: Read>(mut from: R, passphrase: Option<&str>) -> Result<SigningKey, Error> {
+        let mut data = Vec::new();
+        from.read_to_end(&mut data)?;
+        let data = openpgp::dearmor(&data)?;
+
+        let packet = openpgp::packets(&data)?
+            .into_iter()
+            .find(|packet| 5 == packet.tag)
+            .ok_or_else(|| anyhow!("no secret key found"))?;
+
+        parse_secret_key(packet.body, passphrase)
+    }
+
+    /// e.g. `A1B2...`, forty upper-case hex digits.
+    pub fn fingerprint(&self) -> String {
+        hex::encode_upper(self.fingerprint)
+    }
+
+    /// The last eight bytes of the fingerprint, as used to look up keys.
+    pub fn key_id(&self) -> u64 {
+        key_id(&self.fingerprint)
+    }
+
+    /// Produce a clearsigned document, like `gpg --clearsign`, e.g. an `InRelease`.
+    pub fn clearsign(&self, text: &str) -> Result<String, Error> {
+        let body = text.strip_suffix('\n').unwrap_or(text);
+        let lines: Vec<&str> = body.split('\n').collect();
+
+        let signature = self.signature(0x01, |hasher| {
+            for (i, line) in lines.iter().enumerate() {
+                if 0 != i {
+                    hasher.update(b"\r\n");
+                }
+                hasher.update(line.trim_end_matches(&[' ', '\t', '\r'][..]).as_bytes());
+            }
+        })?;
+
+        let mut out = String::with_capacity(text.len() + 1024);
+        out.push_str("-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA512\n\n");
+        for line in lines {
+            if line.starts_with('-') {
+                out.push_str("- ");
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str(&openpgp::armor("SIGNATURE", &signature));
+        Ok(out)
+    }
+
+    /// Produce an armoured detached signature, like `gpg --armor --detach-sign`,
+    /// e.g. a `Release.gpg`.
+    pub fn sign_detached(&self, data: &[u8]) -> Result<String, Error> {
+        let signature = self.signature(0x00, |hasher| hasher.update(data))?;
+        Ok(openpgp::armor("SIGNATURE", &signature))
+    }
+
+    /// A version 4 signature packet, of type `sig_type`, over whatever `content` hashes.
+    fn signature<F: FnOnce(&mut Sha512)>(
+        &self,
+        sig_type: u8,
+        content: F,
+    ) -> Result<Vec<u8>, Error> {
+        self.signature_at(chrono::Utc::now().timestamp() as u32, sig_type, content)
+    }
+
+    /// [SigningKey::signature], as if made at `created`.
+    fn signature_at<F: FnOnce(&mut Sha512)>(
+        &self,
+        created: u32,
+        sig_type: u8,
+        content: F,
+    ) -> Result<Vec<u8>, Error> {
+        let mut hashed = Vec::with_capacity(32);
+        // creation time
+        hashed.extend_from_slice(&[5, 2]);
+        hashed.extend_from_slice(&created.to_be_bytes());
+        // issuer fingerprint
+        hashed.extend_from_slice(&[22, 33, 4]);
+        hashed.extend_from_slice(&self.fingerprint);
+
+        // RSA, SHA512
+        let mut body = vec![4, sig_type, 1, 10];
+        body.extend_from_slice(&(hashed.len() as u16).to_be_bytes());
+        body.extend_from_slice(&hashed);
+
+        let mut hasher = Sha512::default();
+        content(&mut hasher);
+        hasher.update(&body);
+        hasher.update([4, 0xff]);
+        hasher.update((body.len() as u32).to_be_bytes());
+        let digest = hasher.finalize();
+
+        let signed = self
+            .key
+            .sign_with_rng(
+                &mut rand_core::OsRng,
+                Pkcs1v15Sign::new::<Sha512>(),
+                &digest,
+            )
+            .with_context(|| anyhow!("signing"))?;
+
+        // issuer key id, unhashed, which is where verifiers look for it
+        body.extend_from_slice(&10u16.to_be_bytes());
+        body.extend_from_slice(&[9, 16]);
+        body.extend_from_slice(&self.key_id().to_be_bytes());
+
+        body.extend_from_slice(&digest[..2]);
+        openpgp::put_mpi(&mut body, &signed);
+
+        Ok(openpgp::packet(2, &body))
+    }
+}
+
+/// https://tools.ietf.org/html/rfc4880#section-5.5.3
+fn parse_secret_key(body: &[u8], passphrase: Option<&str>) -> Result<SigningKey, Error> {
+    let mut data = body;
+    ensure!(
+        data.len() > 6 && 4 == data[0],
+        "only version 4 keys are supported"
+    );
+    let algorithm = data[5];
+    ensure!(
+        1 == algorithm || 3 == algorithm,
+        "only RSA keys are supported, not algorithm {}",
+        algorithm
+    );
+    data = &data[6..];
+    let n = openpgp::take_mpi(&mut data)?;
+    let e = openpgp::take_mpi(&mut data)?;
+    let public = &body[..body.len() - data.len()];
+
+    let usage = *data
+        .first()
+        .ok_or_else(|| anyhow!("truncated secret key"))?;
+    data = &data[1..];
+
+    let secret = match usage {
+        0 => {
+            ensure!(data.len() >= 2, "truncated secret key");
+            let (secret, checksum) = data.split_at(data.len() - 2);
+            ensure!(
+                checksum == sum16(secret).to_be_bytes(),
+                "secret key checksum mismatch"
+            );
+            secret.to_vec()
+        }
+        254 | 255 => {
+            let passphrase =
+                passphrase.ok_or_else(|| anyhow!("the secret key is protected by a passphrase"))?;
+            decrypt_secret(usage, data, passphrase)?
+        }
+        other => bail!("unsupported secret key protection: {}", other),
+    };
+
+    let mut secret = secret.as_slice();
+    let d = openpgp::take_mpi(&mut secret)?;
+    let p = openpgp::take_mpi(&mut secret)?;
+    let q = openpgp::take_mpi(&mut secret)?;
+
+    let key = RsaPrivateKey::from_components(
+        BigUint::from_bytes_be(n),
+        BigUint::from_bytes_be(e),
+        BigUint::from_bytes_be(d),
+        vec![BigUint::from_bytes_be(p), BigUint::from_bytes_be(q)],
+    )?;
+    key.validate()
+        .with_context(|| anyhow!("invalid secret key"))?;
+
+    Ok(SigningKey {
+        fingerprint: openpgp::v4_fingerprint(public),
+        key,
+    })
+}
+
+/// Decrypt the secret part of a key, which must be followed by a checksum (255),
+/// or a SHA-1 hash (254).
+fn decrypt_secret(usage: u8, mut data: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    ensure!(data.len() > 2, "truncated secret key");
+    let cipher = data[0];
+    let s2k = data[1];
+    data = &data[2..];
+
+    let (key_len, block_len) = match cipher {
+        3 => (16, 8),
+        7 => (16, 16),
+        8 => (24, 16),
+        9 => (32, 16),
+        other => bail!("unsupported cipher for the secret key: {}", other),
+    };
+
+    let (hash, salt, count) = match s2k {
+        0 | 1 | 3 => {
+            let hash = *data.first().ok_or_else(|| anyhow!("truncated s2k"))?;
+            data = &data[1..];
+            let mut salt: &[u8] = &[];
+            let mut count = 0;
+            if 0 != s2k {
+                ensure!(data.len() >= 8, "truncated s2k");
+                salt = &data[..8];
+                data = &data[8..];
+            }
+            if 3 == s2k {
+                let coded = usize::from(*data.first().ok_or_else(|| anyhow!("truncated s2k"))?);
+                count = (16 + (coded & 15)) << ((coded >> 4) + 6);
+                data = &data[1..];
+            }
+            (hash, salt, count)
+        }
+        101 => bail!("the secret key isn't present; is it on a smartcard?"),
+        other => bail!("unsupported s2k: {}", other),
+    };
+
+    ensure!(data.len() > block_len, "truncated secret key");
+    let (iv, encrypted) = data.split_at(block_len);
+
+    let key = s2k_key(hash, salt, count, passphrase.as_bytes(), key_len)?;
+    let mut plain = encrypted.to_vec();
+    match cipher {
+        3 => cfb_mode::Decryptor::<cast5::Cast5>::new_from_slices(&key, iv)
+            .map_err(|e| anyhow!("{}", e))?
+            .decrypt(&mut plain),
+        7 => cfb_mode::Decryptor::<aes::Aes128>::new_from_slices(&key, iv)
+            .map_err(|e| anyhow!("{}", e))?
+            .decrypt(&mut plain),
+        8 => cfb_mode::Decryptor::<aes::Aes192>::new_from_slices(&key, iv)
+            .map_err(|e| anyhow!("{}", e))?
+            .decrypt(&mut plain),
+        9 => cfb_mode::Decryptor::<aes::Aes256>::new_from_slices(&key, iv)
+            .map_err(|e| anyhow!("{}", e))?
+            .decrypt(&mut plain),
+        _ => unreachable!("checked above"),
+    }
+
+    let valid = if 254 == usage {
+        plain.len() > 20 && {
+            let (secret, hash) = plain.split_at(plain.len() - 20);
+            sha1::Sha1::digest(secret).as_slice() == hash
+        }
+    } else {
+        plain.len() > 2 && {
+            let (secret, checksum) = plain.split_at(plain.len() - 2);
+            checksum == sum16(secret).to_be_bytes()
+        }
+    };
+    ensure!(valid, "couldn't decrypt the secret key; wrong passphrase?");
+
+    let trailer = if 254 == usage { 20 } else { 2 };
+    plain.truncate(plain.len() - trailer);
+    Ok(plain)
+}
+
+/// Turn a passphrase into a key, as in https://tools.ietf.org/html/rfc4880#section-3.7.1
+fn s2k_key(
+    hash: u8,
+    salt: &[u8],
+    count: usize,
+    passphrase: &[u8],
+    key_len: usize,
+) -> Result<Vec<u8>, Error> {
+    let input = [salt, passphrase].concat();
+    // the count is a number of bytes to hash, but it's always at least the input once
+    let count = count.max(input.len());
+
+    let mut key = Vec::with_capacity(key_len + 64);
+    let mut preload = 0;
+    while key.len() < key_len {
+        let mut hasher: Box<dyn digest::DynDigest> = match hash {
+            2 => Box::new(sha1::Sha1::default()),
+            8 => Box::new(sha2::Sha256::default()),
+            9 => Box::new(sha2::Sha384::default()),
+            10 => Box::new(sha2::Sha512::default()),
+            11 => Box::new(sha2::Sha224::default()),
+            other => bail!("unsupported s2k hash: {}", other),
+        };
+        hasher.update(&vec![0u8; preload]);
+
+        let mut remaining = count;
+        while remaining > 0 {
+            let take = remaining.min(input.len());
+            hasher.update(&input[..take]);
+            remaining -= take;
+        }
+
+        key.extend_from_slice(&hasher.finalize());
+        preload += 1;
+    }
+
+    key.truncate(key_len);
+    Ok(key)
+}
+
+fn sum16(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(u16::from(byte)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+
+    use anyhow::Error;
+    use sha1::Digest;
+
+    use super::signed_by;
+    use super::GpgClient;
+    use super::KeyDetails;
+    use super::Keys;
+    use super::SignaturePolicy;
+    use super::Signer;
+    use super::SigningKey;
+    use crate::openpgp;
+
+    fn verify(key: &[u8], signed: &[u8], policy: &SignaturePolicy) -> Result<Signer, Error> {
+        let mut keys = Keys::new();
+        keys.append_from(key)?;
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("InRelease");
+        fs::write(&file, signed)?;
+        GpgClient::new(&keys, policy).verify_clearsigned(&file, dir.path().join("Verified"))
+    }
+
+    #[test]
+    fn expired() {
+        let key = include_bytes!("../tests/keys/expired.asc");
+        let signed = include_bytes!("../tests/keys/expired.signed");
+        let err = verify(key, signed, &SignaturePolicy::default()).unwrap_err();
+        assert!(
+            err.to_string().contains("the key expired at 2020-12-31"),
+            "{}",
+            err
+        );
+
+        let policy = SignaturePolicy {
+            allow_expired: true,
+            ..SignaturePolicy::default()
+        };
+        let signer = verify(key, signed, &policy).unwrap();
+        assert_eq!("2020-06-01", signer.created.format("%Y-%m-%d").to_string());
+    }
+
+    #[test]
+    fn revoked() {
+        let key = include_bytes!("../tests/keys/revoked.asc");
+        let signed = include_bytes!("../tests/keys/revoked.signed");
+        let err = verify(key, signed, &SignaturePolicy::default()).unwrap_err();
+        assert!(
+            err.to_string().contains("the key has been revoked"),
+            "{}",
+            err
+        );
+
+        let policy = SignaturePolicy {
+            allow_revoked: true,
+            ..SignaturePolicy::default()
+        };
+        verify(key, signed, &policy).unwrap();
+    }
+
+    #[test]
+    fn weak_hashes() {
+        let key = include_bytes!("../tests/keys/test-repo.asc");
+        let signed = include_bytes!("../tests/keys/sha1.signed");
+        let err = verify(key, signed, &SignaturePolicy::default()).unwrap_err();
+        assert!(err.to_string().contains("SHA1 is too weak"), "{}", err);
+
+        let policy = SignaturePolicy {
+            allow_weak_hashes: true,
+            ..SignaturePolicy::default()
+        };
+        verify(key, signed, &policy).unwrap();
+    }
+
+    #[test]
+    fn colliding_ids() {
+        let key = |byte: u8| KeyDetails {
+            fingerprint: [byte; 20],
+            primary: [byte; 20],
+            user_id: None,
+            expires: None,
+            revoked: false,
+        };
+        let keys = [key(1), key(2)];
+
+        let named = signed_by(&keys, 0x0202, Some(&[2; 20])).unwrap();
+        assert_eq!([2; 20], named.fingerprint);
+        assert!(signed_by(&keys, 0x0303, Some(&[3; 20])).is_err());
+
+        let err = signed_by(&keys, 0x0101, None).unwrap_err();
+        assert!(err.to_string().contains("ambiguous signer"), "{}", err);
+        assert_eq!(
+            [1; 20],
+            signed_by(&keys[..1], 0x0101, None).unwrap().fingerprint
+        );
+        assert!(signed_by(&[], 0x0101, None).is_err());
+    }
+
+    #[test]
+    fn detached() -> Result<(), Error> {
+        let key = SigningKey::read(
+            &include_bytes!("../tests/keys/test-repo.secret.asc")[..],
+            None,
         )?;
-        fs::copy(file, dest)?;
+        let mut keys = Keys::new();
+        keys.append_from(&include_bytes!("../tests/keys/test-repo.asc")[..])?;
+        let dir = tempfile::tempdir()?;
+        let check = |data: &[u8], signature: &str| -> Result<Signer, Error> {
+            fs::write(dir.path().join("Release"), data)?;
+            fs::write(dir.path().join("Release.gpg"), signature)?;
+            GpgClient::new(&keys, &SignaturePolicy::default()).verify_detached(
+                dir.path().join("Release"),
+                dir.path().join("Release.gpg"),
+                dir.path().join("Verified"),
+            )
+        };
+
+        let data = b"Origin: test\nLabel: test\n";
+        let signature = key.sign_detached(data)?;
+        check(data, &signature)?;
+        assert!(check(b"Origin: evil\n", &signature).is_err());
+
+        // one signature in 256 starts with a zero byte, which isn't encoded; this is one
+        let data = b"Origin: test107\n";
+        let packet = key.signature_at(1_600_000_000, 0x00, |hasher| hasher.update(data))?;
+        let doc = gpgrv::read_doc(io::Cursor::new(&packet), io::sink())?;
+        assert!(matches!(
+            &doc.signatures[0].sig,
+            gpgrv::PublicKeySig::Rsa(sig) if 255 == sig.len()
+        ));
+        check(data, &openpgp::armor("SIGNATURE", &packet))?;
         Ok(())
     }
 }
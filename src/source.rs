@@ -0,0 +1,329 @@
+//! Extracting source packages, like `dpkg-source -x`, without needing `dpkg-dev`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+
+use crate::checksum;
+use crate::deb;
+use crate::parse::Package;
+use crate::parse::SourceArchive;
+use crate::parse::SourceFormat;
+use crate::patch;
+
+/// Extract the source package described by the `.dsc` at `dsc` into `dest`, which must not
+/// exist. The other files are expected next to the `.dsc`, and are checked before use.
+///
+/// The tree is built beside `dest` and moved into place once it's complete, so nothing is
+/// left behind on failure. Unlike `dpkg-source`, no `.pc` directory is recorded for the
+/// applied quilt patches. The `.dsc`'s signature is not checked.
+pub fn unpack<P: AsRef<Path>, Q: AsRef<Path>>(dsc: P, dest: Q) -> Result<Package, Error> {
+    let dsc = dsc.as_ref();
+    let dest = dest.as_ref();
+
+    let (pkg, _) = Package::read_dsc(
+        io::BufReader::new(fs::File::open(dsc).with_context(|| anyhow!("opening {:?}", dsc))?),
+        None,
+    )
+    .with_context(|| anyhow!("reading {:?}", dsc))?;
+
+    let src = pkg
+        .as_src()
+        .ok_or_else(|| anyhow!("{:?} is not a source package", pkg.name))?;
+
+    ensure!(
+        fs::symlink_metadata(dest).is_err(),
+        "destination already exists: {:?}",
+        dest
+    );
+
+    let dir = parent(dsc);
+    for file in &src.files {
+        // only the strongest hash available is checked
+        let (alg, hash) = file.strongest_hash();
+        checksum::validate_in(dir, &file.name, file.size, alg, &hash)
+            .with_context(|| anyhow!("checking {:?} in {:?}", file.name, dir))?;
+    }
+
+    let tarballs = Tarballs::classify(&src.files)?;
+
+    let parent = parent(dest);
+    fs::create_dir_all(parent).with_context(|| anyhow!("creating {:?}", parent))?;
+    let temp = tempfile::Builder::new()
+        .prefix(".fapt-source")
+        .tempdir_in(parent)?;
+    let work = temp.path().join("tree");
+
+    match src.format {
+        SourceFormat::Native3dot0 => {
+            ensure!(
+                tarballs.orig.is_none() && tarballs.debian.is_none() && tarballs.diff.is_none(),
+                "native packages have only a single tarball"
+            );
+            let tarball = tarballs.native.ok_or_else(|| anyhow!("no tarball"))?;
+            extract_stripped(&dir.join(tarball), &work)?;
+        }
+        SourceFormat::Quilt3dot0 => {
+            let orig = tarballs.orig.ok_or_else(|| anyhow!("no orig tarball"))?;
+            let debian = tarballs
+                .debian
+                .ok_or_else(|| anyhow!("no debian tarball"))?;
+            ensure!(
+                tarballs.native.is_none() && tarballs.diff.is_none(),
+                "unexpected files for a quilt package"
+            );
+
+            extract_stripped(&dir.join(orig), &work)?;
+
+            for (component, tarball) in &tarballs.components {
+                let into = work.join(component);
+                if into.exists() {
+                    fs::remove_dir_all(&into)?;
+                }
+                extract_stripped(&dir.join(tarball), &into)
+                    .with_context(|| anyhow!("component {:?}", component))?;
+            }
+
+            // the packaging entirely replaces anything upstream shipped
+            let debian_dir = work.join("debian");
+            if fs::symlink_metadata(&debian_dir).is_ok() {
+                fs::remove_dir_all(&debian_dir)?;
+            }
+            extract_tar(&dir.join(debian), &work)?;
+
+            apply_series(&work)?;
+        }
+        SourceFormat::Original => match (tarballs.orig, tarballs.native) {
+            (Some(orig), None) => {
+                let diff = tarballs.diff.ok_or_else(|| anyhow!("no diff"))?;
+                extract_stripped(&dir.join(orig), &work)?;
+
+                let mut text = String::new();
+                flate2::read::GzDecoder::new(fs::File::open(dir.join(diff))?)
+                    .read_to_string(&mut text)
+                    .with_context(|| anyhow!("reading {:?}", diff))?;
+                patch::apply(&work, &text, 1).with_context(|| anyhow!("applying {:?}", diff))?;
+
+                // diffs can't carry permissions
+                let rules = work.join("debian/rules");
+                if rules.is_file() {
+                    deb::set_mode(&rules, 0o755)?;
+                }
+            }
+            (None, Some(native)) => {
+                ensure!(tarballs.diff.is_none(), "native packages have no diff");
+                extract_stripped(&dir.join(native), &work)?;
+            }
+            _ => bail!("expected exactly one of an orig tarball or a native tarball"),
+        },
+        SourceFormat::Git3dot0 => bail!("3.0 (git) packages are not supported"),
+    }
+
+    fs::rename(&work, dest).with_context(|| anyhow!("moving into {:?}", dest))?;
+
+    Ok(pkg)
+}
+
+/// The files of a source package, by what they're for.
+#[derive(Default)]
+struct Tarballs<'s> {
+    orig: Option<&'s str>,
+    /// `foo_1.0.orig-bar.tar.gz` -> `("bar", ..)`
+    components: Vec<(&'s str, &'s str)>,
+    debian: Option<&'s str>,
+    native: Option<&'s str>,
+    diff: Option<&'s str>,
+}
+
+impl<'s> Tarballs<'s> {
+    fn classify(files: &'s [SourceArchive]) -> Result<Tarballs<'s>, Error> {
+        let mut tarballs = Tarballs::default();
+
+        for name in files.iter().map(|file| file.name.as_str()) {
+            // upstream signatures, and the .dsc itself
+            if name.ends_with(".asc") || name.ends_with(".dsc") {
+                continue;
+            }
+
+            if name.ends_with(".diff.gz") {
+                replace(&mut tarballs.diff, name)?;
+                continue;
+            }
+
+            let stem = match name.rfind(".tar") {
+                Some(pos) => &name[..pos],
+                None => bail!("unrecognised file in source package: {:?}", name),
+            };
+
+            if stem.ends_with(".orig") {
+                replace(&mut tarballs.orig, name)?;
+            } else if stem.ends_with(".debian") {
+                replace(&mut tarballs.debian, name)?;
+            } else if let Some(pos) = stem.rfind(".orig-") {
+                let component = &stem[pos + ".orig-".len()..];
+                ensure!(
+                    !component.is_empty()
+                        && component
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || '-' == c),
+                    "invalid component name in {:?}",
+                    name
+                );
+                tarballs.components.push((component, name));
+            } else {
+                replace(&mut tarballs.native, name)?;
+            }
+        }
+
+        Ok(tarballs)
+    }
+}
+
+fn replace<'s>(slot: &mut Option<&'s str>, name: &'s str) -> Result<(), Error> {
+    if let Some(existing) = slot.replace(name) {
+        bail!("both {:?} and {:?} are present", existing, name);
+    }
+    Ok(())
+}
+
+/// Apply `debian/patches/series`, if there is one.
+fn apply_series(work: &Path) -> Result<(), Error> {
+    let patches = work.join("debian/patches");
+    let series = match fs::read_to_string(patches.join("series")) {
+        Ok(series) => series,
+        Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(()),
+        Err(e) => return Err(e).with_context(|| anyhow!("reading series")),
+    };
+
+    for line in series.lines() {
+        let line = line
+            .split(" #")
+            .next()
+            .expect("split is never empty")
+            .trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let name = words.next().expect("non-empty");
+        let strip = match words.next() {
+            Some(option) => option
+                .strip_prefix("-p")
+                .and_then(|level| level.parse().ok())
+                .ok_or_else(|| anyhow!("unsupported option in series: {:?}", line))?,
+            None => 1,
+        };
+
+        // an empty first part is an absolute path
+        ensure!(
+            !name
+                .split('/')
+                .any(|part| part.is_empty() || "." == part || ".." == part),
+            "refusing patch outside of debian/patches: {:?}",
+            name
+        );
+
+        let text = fs::read_to_string(patches.join(name))
+            .with_context(|| anyhow!("reading patch {:?}", name))?;
+        patch::apply(work, &text, strip).with_context(|| anyhow!("applying {:?}", name))?;
+    }
+
+    Ok(())
+}
+
+/// Extract a tarball into `dest`, dropping the single top-level directory it probably has.
+fn extract_stripped(tarball: &Path, dest: &Path) -> Result<(), Error> {
+    let temp = tempfile::Builder::new()
+        .prefix(".fapt-source")
+        .tempdir_in(parent(dest))?;
+
+    extract_tar(tarball, temp.path())?;
+
+    let top = fs::read_dir(temp.path())?.collect::<Result<Vec<_>, io::Error>>()?;
+    let from = match top.as_slice() {
+        [only] if only.file_type()?.is_dir() => only.path(),
+        _ => temp.keep(),
+    };
+
+    fs::rename(&from, dest).with_context(|| anyhow!("moving {:?} into {:?}", from, dest))?;
+    Ok(())
+}
+
+fn extract_tar(tarball: &Path, into: &Path) -> Result<(), Error> {
+    let name = tarball
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("invalid tarball name: {:?}", tarball))?;
+    let extension = &name[name
+        .rfind(".tar")
+        .ok_or_else(|| anyhow!("not a tarball: {:?}", name))?
+        + ".tar".len()..];
+
+    let file = fs::File::open(tarball).with_context(|| anyhow!("opening {:?}", tarball))?;
+    let mut archive = tar::Archive::new(deb::decompress(extension, io::BufReader::new(file))?);
+
+    // tarball path -> where we put it, for hardlinks
+    let mut written = HashMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        // e.g. the commit id `git archive` records
+        if let tar::EntryType::XGlobalHeader = entry.header().entry_type() {
+            continue;
+        }
+
+        let data = deb::DataEntry::from_tar(&entry)?;
+        let dest = deb::unpack_entry(into, &data, &data.path, &mut entry, &written)
+            .with_context(|| anyhow!("extracting {:?} from {:?}", data.path, name))?;
+
+        if let Some(dest) = dest {
+            written.insert(data.path, dest);
+        }
+    }
+
+    Ok(())
+}
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::apply_series;
+
+    #[test]
+    fn series_stays_in_patches() {
+        let work = tempfile::tempdir().unwrap();
+        let work = work.path();
+        let patches = work.join("debian/patches");
+        fs::create_dir_all(&patches).unwrap();
+        fs::write(patches.join("ok.diff"), "").unwrap();
+        fs::write(work.join("outside.diff"), "").unwrap();
+
+        fs::write(patches.join("series"), "# comment\nok.diff -p1\n").unwrap();
+        apply_series(work).unwrap();
+
+        let outside = work.join("outside.diff");
+        let outside = outside.to_str().unwrap();
+        for name in &[outside, "../../outside.diff", "./ok.diff", "sub//ok.diff"] {
+            fs::write(patches.join("series"), format!("{}\n", name)).unwrap();
+            assert!(apply_series(work).is_err(), "{:?}", name);
+        }
+    }
+}
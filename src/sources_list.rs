@@ -1,5 +1,6 @@
 //! Load `Entry` objects from from a _classic_ sources list. (e.g. `/etc/*apt/sources.list`).
 
+use std::collections::HashMap;
 use std::io::BufRead;
 
 use anyhow::anyhow;
@@ -15,6 +16,9 @@ pub struct Entry {
     pub suite_codename: String,
     pub components: Vec<String>,
     pub arch: Option<String>,
+    /// Any other `[key=value]` options, e.g. `ca-info=/etc/ssl/private-ca.pem`, or
+    /// `trusted=yes`, to accept the release without a signature.
+    pub options: HashMap<String, String>,
 }
 
 fn read_single_line(line: &str) -> Result<Vec<Entry>, Error> {
@@ -33,15 +37,14 @@ fn read_single_line(line: &str) -> Result<Vec<Entry>, Error> {
     let src = parts
         .next()
         .ok_or_else(|| anyhow!("deb{{,s,-src}} section required"))?;
-    let arch = match parts.peek() {
-        Some(&val) if val.starts_with("[") => {
-            parts.next();
-            Some(val)
-        }
-        Some(_) => None,
-        None => bail!("unexpected end of line looking for arch or url"),
+    let mut options = match parts.peek() {
+        Some(&val) if val.starts_with('[') => read_options(&mut parts)?,
+        Some(_) => HashMap::new(),
+        None => bail!("unexpected end of line looking for options or url"),
     };
 
+    let arch = options.remove("arch");
+
     let url = parts
         .next()
         .ok_or_else(|| anyhow!("url section required"))?;
@@ -70,13 +73,42 @@ fn read_single_line(line: &str) -> Result<Vec<Entry>, Error> {
             },
             suite_codename: suite.to_string(),
             components: components.iter().map(|x| x.to_string()).collect(),
-            arch: arch.map(|arch| arch.to_string()),
+            arch: arch.clone(),
+            options: options.clone(),
         });
     }
 
     Ok(ret)
 }
 
+fn read_options<'a, I: Iterator<Item = &'a str>>(
+    parts: &mut I,
+) -> Result<HashMap<String, String>, Error> {
+    let mut words = Vec::new();
+    for part in parts.by_ref() {
+        words.push(part);
+        if part.ends_with(']') {
+            break;
+        }
+    }
+
+    let joined = words.join(" ");
+    let inner = joined
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .ok_or_else(|| anyhow!("unterminated options: {:?}", joined))?;
+
+    let mut ret = HashMap::new();
+    for option in inner.split_whitespace() {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| anyhow!("options must be key=value: {:?}", option))?;
+        ret.insert(key.to_string(), value.to_string());
+    }
+
+    Ok(ret)
+}
+
 fn read_single_line_number(line: &str, no: usize) -> Result<Vec<Entry>, Error> {
     Ok(read_single_line(line).with_context(|| anyhow!("parsing line {}", no + 1))?)
 }
@@ -98,6 +130,7 @@ pub fn read<R: BufRead>(from: R) -> Result<Vec<Entry>, Error> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::io;
 
     use super::read;
@@ -113,6 +146,7 @@ mod tests {
                     url: "http://foo/".to_string(),
                     suite_codename: "bar".to_string(),
                     components: vec!["baz".to_string(), "quux".to_string()],
+                    options: HashMap::new(),
                 },
                 Entry {
                     src: true,
@@ -120,6 +154,7 @@ mod tests {
                     url: "http://foo/".to_string(),
                     suite_codename: "bar".to_string(),
                     components: vec!["baz".to_string(), "quux".to_string()],
+                    options: HashMap::new(),
                 },
             ],
             read(io::Cursor::new(
@@ -131,4 +166,19 @@ deb-src http://foo  bar  baz quux
             .unwrap()
         );
     }
+
+    #[test]
+    fn options() {
+        let entries = read(io::Cursor::new(
+            "deb [ arch=amd64 ca-info=/etc/ca.pem ] https://foo bar baz\n",
+        ))
+        .unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!(Some("amd64"), entries[0].arch.as_deref());
+        assert_eq!(
+            Some("/etc/ca.pem"),
+            entries[0].options.get("ca-info").map(|s| s.as_str())
+        );
+        assert_eq!("https://foo/", entries[0].url);
+    }
 }
@@ -0,0 +1,545 @@
+//! The `dpkg` database of what is installed, usually `/var/lib/dpkg/status`.
+//!
+//! Entries are kept even for packages which aren't installed, as `dpkg` remembers what was
+//! selected, and which packages left their conffiles behind.
+//!
+//! Packages unpacked without `dpkg` can be recorded with [record], which also writes the
+//! package's files in `info/`, so `dpkg` can later upgrade or remove them.
+//!
+//! ```no_run
+//! # use fapt::system::System;
+//! let mut fapt = System::cache_only()?;
+//! fapt.set_dpkg_database("/var/lib/dpkg");
+//! let status = fapt.status_database()?;
+//! for entry in status.installed() {
+//!     println!("{} {}", entry.package, entry.version.as_deref().unwrap_or("?"));
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+
+use crate::checksum;
+use crate::checksum::HashAlg;
+use crate::deb;
+use crate::files::write_atomically;
+use crate::parse::Conffile;
+use crate::parse::Package;
+use crate::parse::State;
+use crate::parse::Status;
+use crate::rfc822;
+use crate::rfc822::RfcMapExt;
+use crate::selections::Selection;
+
+/// The entries of a `status` file, in order.
+#[derive(Clone, Debug, Default)]
+pub struct Database {
+    entries: Vec<Entry>,
+}
+
+/// A package `dpkg` knows about, which may not be installed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub package: String,
+    /// Missing for some packages which were only ever selected.
+    pub arch: Option<String>,
+    pub multi_arch: Option<String>,
+    pub version: Option<String>,
+    pub status: Status,
+    pub conffiles: Vec<Conffile>,
+    /// The version last successfully configured, e.g. while an upgrade is half-done.
+    pub config_version: Option<String>,
+    /// Names of triggers to run for this package.
+    pub triggers_pending: Vec<String>,
+    /// Packages whose triggers this package is waiting on.
+    pub triggers_awaited: Vec<String>,
+    block: String,
+}
+
+/// What `dpkg` keeps in `info/` about an installed package, other than its scripts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Info {
+    /// Every path in the package, in order, e.g. `/usr/bin/hello`, or `/` for the root.
+    pub list: Vec<String>,
+    /// Absolute paths, e.g. `/usr/bin/hello`, and their expected md5.
+    pub md5sums: Vec<(String, checksum::MD5)>,
+    pub conffiles: Vec<deb::Conffile>,
+}
+
+/// The order `dpkg` writes the fields it knows about in; any others follow, as they were.
+const FIELD_ORDER: &[&str] = &[
+    "Package",
+    "Essential",
+    "Protected",
+    "Status",
+    "Priority",
+    "Section",
+    "Installed-Size",
+    "Origin",
+    "Maintainer",
+    "Bugs",
+    "Architecture",
+    "Multi-Arch",
+    "Source",
+    "Version",
+    "Config-Version",
+    "Replaces",
+    "Provides",
+    "Depends",
+    "Pre-Depends",
+    "Recommends",
+    "Suggests",
+    "Breaks",
+    "Conflicts",
+    "Enhances",
+    "Conffiles",
+    "Description",
+    "Triggers-Pending",
+    "Triggers-Awaited",
+];
+
+impl Database {
+    pub fn read<R: Read>(from: R) -> Result<Database, Error> {
+        let mut entries = Vec::new();
+        for block in rfc822::Blocks::new(from, "status".to_string()) {
+            entries.push(Entry::parse(block?)?);
+        }
+        Ok(Database { entries })
+    }
+
+    /// Every entry, in the order of the file.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The entries which are installed, and configured; see [crate::parse::State::is_installed].
+    pub fn installed(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(|entry| entry.is_installed())
+    }
+
+    /// All of the entries for `package`, one per architecture.
+    pub fn by_name(&self, package: &str) -> Vec<&Entry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.package == package)
+            .collect()
+    }
+
+    /// The entry for `package` on `arch`. As with `dpkg`, architecture-independent packages
+    /// are only found as `all`.
+    pub fn get(&self, package: &str, arch: &str) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.package == package && entry.arch.as_deref() == Some(arch))
+    }
+
+    /// The selections, as `dpkg --get-selections` lists them: not those of packages which
+    /// aren't installed at all, whatever they want. A package is named with its architecture
+    /// if that's neither `native` nor `all`, or if it's `Multi-Arch: same`.
+    pub fn selections(&self, native: &str) -> Vec<Selection> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.status.state != State::NotInstalled)
+            .map(|entry| Selection {
+                package: entry.package.to_string(),
+                arch: match (entry.arch.as_deref(), entry.multi_arch.as_deref()) {
+                    (_, Some("same")) => entry.arch.clone(),
+                    (Some(arch), _) if arch != native && arch != "all" => entry.arch.clone(),
+                    _ => None,
+                },
+                want: entry.status.want,
+            })
+            .collect()
+    }
+
+    /// Add `entry`, or replace the existing entry for the same package and architecture.
+    pub fn set(&mut self, entry: Entry) {
+        if let Some(existing) = self
+            .entries
+            .iter_mut()
+            .find(|existing| existing.package == entry.package && existing.arch == entry.arch)
+        {
+            *existing = entry;
+            return;
+        }
+
+        // dpkg keeps the file sorted, which makes it much easier to diff
+        let pos = self
+            .entries
+            .iter()
+            .position(|existing| {
+                (&existing.package, &existing.arch) > (&entry.package, &entry.arch)
+            })
+            .unwrap_or(self.entries.len());
+        self.entries.insert(pos, entry);
+    }
+
+    /// Remove the entry for `package` on `arch`, returning it.
+    pub fn remove(&mut self, package: &str, arch: &str) -> Option<Entry> {
+        let pos = self
+            .entries
+            .iter()
+            .position(|entry| entry.package == package && entry.arch.as_deref() == Some(arch))?;
+        Some(self.entries.remove(pos))
+    }
+
+    /// Write the database in the format of the `status` file.
+    pub fn write<W: Write>(&self, mut to: W) -> Result<(), Error> {
+        // dpkg ends every entry with a blank line, even the last
+        for entry in &self.entries {
+            writeln!(to, "{}", entry)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the `status` file in the `dpkg` database at `dpkg`, keeping the previous
+    /// version as `status-old`, as `dpkg` does.
+    pub fn save<P: AsRef<Path>>(&self, dpkg: P) -> Result<(), Error> {
+        let dpkg = dpkg.as_ref();
+        let path = dpkg.join("status");
+        backup(&path, &dpkg.join("status-old"))?;
+
+        let mut data = Vec::with_capacity(self.entries.len() * 1024);
+        self.write(&mut data)?;
+        write_atomically(&path, &data)
+    }
+}
+
+impl Entry {
+    /// A new entry, from a package's `control` file.
+    pub fn new(control: &str, status: Status) -> Result<Entry, Error> {
+        let block = format!("{}\nStatus: {}\n", control.trim_end(), status);
+        let mut fields = raw_fields(&block);
+        // stable, so the unknown fields stay in order
+        fields.sort_by_key(|(key, _)| field_rank(key));
+        Entry::parse(fields.into_iter().map(|(_, raw)| raw).collect())
+    }
+
+    /// A new entry for a package [deb::unpack]ed into `root`. `dpkg` records the hashes of
+    /// the conffiles as they were installed.
+    pub fn unpacked(root: &Path, unpacked: &deb::Unpacked, status: Status) -> Result<Entry, Error> {
+        let mut entry = Entry::new(&unpacked.control.text, status)?;
+        for conffile in &unpacked.control.conffiles {
+            let written_to = unpacked
+                .manifest
+                .iter()
+                .find(|manifest| manifest.entry.path == conffile.path)
+                .and_then(|manifest| manifest.written_to.as_ref());
+            let md5 = match written_to {
+                Some(path) => {
+                    let path = root.join(path.trim_start_matches('/'));
+                    let file =
+                        fs::File::open(&path).with_context(|| anyhow!("opening {:?}", path))?;
+                    Some(hex::encode(HashAlg::Md5.digest(file)?))
+                }
+                None => None,
+            };
+            entry.conffiles.push(Conffile {
+                path: conffile.path.to_string(),
+                md5,
+                obsolete: false,
+                remove_on_upgrade: conffile.remove_on_upgrade,
+            });
+        }
+        Ok(entry)
+    }
+
+    fn parse(block: String) -> Result<Entry, Error> {
+        let map = rfc822::fields_in_block(&block).collect_to_map()?;
+        let package = map.get_value("Package").one_line_req()?.to_string();
+        let mut entry =
+            parse_fields(&map, &package).with_context(|| anyhow!("status of {:?}", package))?;
+        entry.block = block;
+        Ok(entry)
+    }
+
+    /// Is the package installed, and configured? Half-installed packages, and those with
+    /// only their conffiles left, aren't.
+    pub fn is_installed(&self) -> bool {
+        self.status.state.is_installed()
+    }
+
+    /// The name of the package's files in `info/`, which is qualified with the architecture
+    /// if many architectures can be installed at once.
+    pub fn info_name(&self) -> String {
+        match (&self.arch, self.multi_arch.as_deref()) {
+            (Some(arch), Some("same")) => format!("{}:{}", self.package, arch),
+            _ => self.package.to_string(),
+        }
+    }
+
+    pub fn as_map(&self) -> Result<rfc822::Map<'_>, Error> {
+        rfc822::fields_in_block(&self.block).collect_to_map()
+    }
+
+    /// The whole package. This fails for entries without the fields every package has,
+    /// e.g. those which were only ever selected.
+    pub fn as_pkg(&self) -> Result<Package, Error> {
+        Package::parse(&mut self.as_map()?)
+    }
+}
+
+impl fmt::Display for Entry {
+    /// The entry as a block of the `status` file, with any changes to the modelled fields.
+    /// The fields stay in the order they were read in; any new ones go where `dpkg` puts them.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let field = |key: &'static str, value: Option<String>| {
+            (key, value.map(|value| format!("{}: {}\n", key, value)))
+        };
+        let words = |words: &[String]| Some(words.join(" ")).filter(|v| !v.is_empty());
+        let conffiles = Some(&self.conffiles)
+            .filter(|conffiles| !conffiles.is_empty())
+            .map(|conffiles| {
+                let mut raw = "Conffiles:\n".to_string();
+                for conffile in conffiles {
+                    raw.push_str(&format!(" {}\n", conffile));
+                }
+                raw
+            });
+        let mut modelled = vec![
+            field("Package", Some(self.package.to_string())),
+            field("Status", Some(self.status.to_string())),
+            field("Architecture", self.arch.clone()),
+            field("Multi-Arch", self.multi_arch.clone()),
+            field("Version", self.version.clone()),
+            field("Config-Version", self.config_version.clone()),
+            ("Conffiles", conffiles),
+            field("Triggers-Pending", words(&self.triggers_pending)),
+            field("Triggers-Awaited", words(&self.triggers_awaited)),
+        ];
+
+        let mut fields: Vec<(&str, String)> = Vec::new();
+        for (key, raw) in raw_fields(&self.block) {
+            match modelled
+                .iter_mut()
+                .find(|(known, _)| known.eq_ignore_ascii_case(key))
+            {
+                Some((known, value)) => fields.extend(value.take().map(|value| (*known, value))),
+                None => fields.push((key, raw.to_string())),
+            }
+        }
+        for (key, value) in modelled {
+            if let Some(value) = value {
+                let pos = fields
+                    .iter()
+                    .position(|(existing, _)| field_rank(existing) > field_rank(key))
+                    .unwrap_or(fields.len());
+                fields.insert(pos, (key, value));
+            }
+        }
+
+        for (_, raw) in fields {
+            f.write_str(&raw)?;
+            if !raw.ends_with('\n') {
+                f.write_str("\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where `dpkg` writes `key`, in [FIELD_ORDER]; after all of those if it's unknown.
+fn field_rank(key: &str) -> usize {
+    FIELD_ORDER
+        .iter()
+        .position(|known| known.eq_ignore_ascii_case(key))
+        .unwrap_or(FIELD_ORDER.len())
+}
+
+/// The fields of a block, exactly as written, including their continuation lines.
+fn raw_fields(block: &str) -> Vec<(&str, &str)> {
+    let mut ret: Vec<(&str, &str)> = Vec::new();
+    let mut start = 0;
+    for (pos, _) in block.match_indices('\n') {
+        let next = pos + 1;
+        let continues = block[next..].starts_with(' ') || block[next..].starts_with('\t');
+        if !continues {
+            let raw = &block[start..next];
+            let key = raw.split(':').next().unwrap_or(raw);
+            ret.push((key, raw));
+            start = next;
+        }
+    }
+    if start < block.len() {
+        ret.push((
+            block[start..].split(':').next().unwrap_or(""),
+            &block[start..],
+        ));
+    }
+    ret
+}
+
+fn parse_fields(map: &rfc822::Map, package: &str) -> Result<Entry, Error> {
+    let words = |key: &str| -> Result<Vec<String>, Error> {
+        Ok(map
+            .get_value(key)
+            .one_line()?
+            .map(|line| line.split_whitespace().map(ToString::to_string).collect())
+            .unwrap_or_default())
+    };
+
+    Ok(Entry {
+        package: package.to_string(),
+        arch: map.get_value("Architecture").one_line_owned()?,
+        multi_arch: map.get_value("Multi-Arch").one_line_owned()?,
+        version: map.get_value("Version").one_line_owned()?,
+        status: map.get_value("Status").one_line_req()?.parse()?,
+        conffiles: map
+            .get_value("Conffiles")
+            .val
+            .unwrap_or_default()
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Conffile::parse(line))
+            .collect::<Result<_, _>>()?,
+        config_version: map.get_value("Config-Version").one_line_owned()?,
+        triggers_pending: words("Triggers-Pending")?,
+        triggers_awaited: words("Triggers-Awaited")?,
+        block: String::new(),
+    })
+}
+
+impl Info {
+    /// The files which `dpkg` would have recorded for the package.
+    pub fn from_unpacked(unpacked: &deb::Unpacked) -> Info {
+        Info {
+            list: unpacked
+                .manifest
+                .iter()
+                .map(|entry| entry.entry.path.to_string())
+                .collect(),
+            md5sums: unpacked.control.md5sums.clone(),
+            conffiles: unpacked.control.conffiles.clone(),
+        }
+    }
+}
+
+/// Read the `status` file of the `dpkg` database at `dpkg`, which may not exist yet.
+fn read_or_empty(dpkg: &Path) -> Result<Database, Error> {
+    let path = dpkg.join("status");
+    match fs::File::open(&path) {
+        Ok(file) => Database::read(file).with_context(|| anyhow!("reading {:?}", path)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Database::default()),
+        Err(e) => Err(Error::from(e).context(anyhow!("opening {:?}", path))),
+    }
+}
+
+/// The files in `info/` which belong to a package, other than those left by `dpkg`'s
+/// triggers and hooks.
+const INFO_FILES: &[&str] = &[
+    "list",
+    "md5sums",
+    "conffiles",
+    "preinst",
+    "postinst",
+    "prerm",
+    "postrm",
+    "config",
+    "templates",
+    "triggers",
+    "shlibs",
+    "symbols",
+];
+
+/// Record `entry` in the `dpkg` database at `dpkg`, with its files in `info/`, replacing any
+/// existing entry for the package.
+///
+/// The `info/` files are written first, then the `status` file, each atomically.
+pub fn record<P: AsRef<Path>>(dpkg: P, entry: Entry, info: &Info) -> Result<(), Error> {
+    let dpkg = dpkg.as_ref();
+    let mut database = read_or_empty(dpkg)?;
+    let dir = dpkg.join("info");
+    fs::create_dir_all(&dir).with_context(|| anyhow!("creating {:?}", dir))?;
+    let name = entry.info_name();
+
+    let mut list = String::new();
+    for path in &info.list {
+        // dpkg's name for the root
+        let path = if "/" == path { "/." } else { path.as_str() };
+        list.push_str(&format!("{}\n", path));
+    }
+    write_atomically(&dir.join(format!("{}.list", name)), list.as_bytes())?;
+
+    let mut md5sums = String::new();
+    for (path, md5) in &info.md5sums {
+        let path = path.trim_start_matches('/');
+        md5sums.push_str(&format!("{}  {}\n", hex::encode(md5), path));
+    }
+    write_optional(&dir.join(format!("{}.md5sums", name)), &md5sums)?;
+
+    let mut conffiles = String::new();
+    for conffile in &info.conffiles {
+        if conffile.remove_on_upgrade {
+            conffiles.push_str("remove-on-upgrade ");
+        }
+        conffiles.push_str(&format!("{}\n", conffile.path));
+    }
+    write_optional(&dir.join(format!("{}.conffiles", name)), &conffiles)?;
+
+    database.set(entry);
+    database.save(dpkg)
+}
+
+/// Remove the entry for `package` on `arch` from the `dpkg` database at `dpkg`, and its
+/// files in `info/`, returning the entry, if there was one.
+pub fn forget<P: AsRef<Path>>(dpkg: P, package: &str, arch: &str) -> Result<Option<Entry>, Error> {
+    let dpkg = dpkg.as_ref();
+    let mut database = read_or_empty(dpkg)?;
+    let entry = match database.remove(package, arch) {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+
+    database.save(dpkg)?;
+    for ext in INFO_FILES {
+        let path = dpkg
+            .join("info")
+            .join(format!("{}.{}", entry.info_name(), ext));
+        match fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(Error::from(e).context(anyhow!("removing {:?}", path))),
+        }
+    }
+    Ok(Some(entry))
+}
+
+/// Make `old` another name for `path`, if it exists, replacing any previous `old` in one go.
+/// The link is made at a temporary name first, as `link` won't replace a file.
+fn backup(path: &Path, old: &Path) -> Result<(), Error> {
+    let mut temp = old.as_os_str().to_owned();
+    temp.push(".dpkg-new");
+    let temp = Path::new(&temp);
+    match fs::remove_file(temp) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(Error::from(e).context(anyhow!("removing {:?}", temp))),
+    }
+    match fs::hard_link(path, temp) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Error::from(e).context(anyhow!("linking {:?} to {:?}", temp, path))),
+    }
+    fs::rename(temp, old).with_context(|| anyhow!("backing up to {:?}", old))
+}
+
+/// Write `text` to `path`, or, as `dpkg` only has the files packages have, remove it if empty.
+fn write_optional(path: &Path, text: &str) -> Result<(), Error> {
+    if !text.is_empty() {
+        return write_atomically(path, text.as_bytes());
+    }
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::from(e).context(anyhow!("removing {:?}", path))),
+    }
+}
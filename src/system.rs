@@ -10,8 +10,9 @@
 //! # }
 //! ```
 
-use std::env;
+use std::collections::HashMap;
 use std::fs;
+use std::io;
 use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
@@ -19,22 +20,47 @@ use std::path::PathBuf;
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Error;
-use gpgrv::Keyring;
-
+use reqwest::Url;
+
+use crate::alternatives;
+use crate::archives;
+use crate::auth::Credentials;
+use crate::deb;
+use crate::debsums;
+use crate::dget;
+use crate::fetch;
 use crate::lists;
+use crate::mirror;
+use crate::ownership;
 use crate::parse::Package;
+use crate::parse::Status;
+use crate::proxy::ProxyConfig;
 use crate::release;
 use crate::rfc822;
+use crate::selections;
+use crate::signing;
+use crate::signing::SignatureStatus;
+use crate::source;
 use crate::sources_list::Entry;
+use crate::status;
+use crate::tls::TlsConfig;
 
 /// The core object, tying together configuration, caching, and listing.
+///
+/// Methods which download start a runtime of their own, so mustn't be called from inside
+/// an async runtime; use their `_async` versions there instead.
 pub struct System {
     pub(crate) lists_dir: PathBuf,
+    archives_dir: PathBuf,
     dpkg_database: Option<PathBuf>,
+    extended_states: Option<PathBuf>,
     sources_entries: Vec<Entry>,
     arches: Vec<String>,
-    keyring: Keyring,
-    client: reqwest::blocking::Client,
+    keys: signing::Keys,
+    policy: signing::SignaturePolicy,
+    proxies: ProxyConfig,
+    credentials: Credentials,
+    tls: HashMap<String, TlsConfig>,
 }
 
 /// A _Listing_ that has been downloaded, and the _Release_ it came from.
@@ -44,6 +70,24 @@ pub struct DownloadedList {
     pub listing: lists::Listing,
 }
 
+/// A source package which has been downloaded and checked.
+#[derive(Clone, Debug)]
+pub struct DownloadedSource {
+    /// The directory containing the `.dsc` and all of its files.
+    pub dir: PathBuf,
+    /// The `.dsc` itself.
+    pub dsc: PathBuf,
+    /// What we learnt about the `.dsc`'s signature.
+    pub signature: SignatureStatus,
+}
+
+impl DownloadedSource {
+    /// Extract the package into `dest`, like `dpkg-source -x`. See [source::unpack].
+    pub fn unpack<P: AsRef<Path>>(&self, dest: P) -> Result<Package, Error> {
+        source::unpack(&self.dsc, dest)
+    }
+}
+
 impl System {
     /// Produce a `System` with no configuration, using the user's cache directory.
     pub fn cache_only() -> Result<Self, Error> {
@@ -59,21 +103,18 @@ impl System {
     pub fn cache_only_in<P: AsRef<Path>>(lists_dir: P) -> Result<Self, Error> {
         fs::create_dir_all(lists_dir.as_ref())?;
 
-        let client = if let Ok(proxy) = env::var("http_proxy") {
-            reqwest::blocking::Client::builder()
-                .proxy(reqwest::Proxy::http(&proxy)?)
-                .build()?
-        } else {
-            reqwest::blocking::Client::new()
-        };
-
         Ok(System {
             lists_dir: lists_dir.as_ref().to_path_buf(),
+            archives_dir: lists_dir.as_ref().join("archives"),
             dpkg_database: None,
+            extended_states: None,
             sources_entries: Vec::new(),
             arches: Vec::new(),
-            keyring: Keyring::new(),
-            client,
+            keys: signing::Keys::new(),
+            policy: signing::SignaturePolicy::default(),
+            proxies: ProxyConfig::from_env(),
+            credentials: Credentials::default(),
+            tls: HashMap::new(),
         })
     }
 
@@ -81,8 +122,24 @@ impl System {
     ///
     /// These can be acquired from [crate::sources_list]. It is not recommended that you
     /// build them by hand.
-    pub fn add_sources_entries<I: IntoIterator<Item = Entry>>(&mut self, entries: I) {
-        self.sources_entries.extend(entries);
+    ///
+    /// Any credentials in the urls are moved to the credentials store,
+    /// so they don't end up in file names or messages.
+    pub fn add_sources_entries<I: IntoIterator<Item = Entry>>(
+        &mut self,
+        entries: I,
+    ) -> Result<(), Error> {
+        for mut entry in entries {
+            if let Ok(mut url) = Url::parse(&entry.url) {
+                // not the url itself, which holds the password
+                self.credentials
+                    .take_from_url(&mut url)
+                    .with_context(|| anyhow!("credentials for {:?}", url.host_str()))?;
+                entry.url = url.to_string();
+            }
+            self.sources_entries.push(entry);
+        }
+        Ok(())
     }
 
     /// Configure the architectures this system is using.
@@ -92,6 +149,13 @@ impl System {
         self.arches = arches.into_iter().map(|x| x.to_string()).collect();
     }
 
+    /// Configure where downloaded `.deb`s, and other pool files, are kept.
+    ///
+    /// By default, this is the `archives` directory inside the lists directory.
+    pub fn set_archives_dir<P: AsRef<Path>>(&mut self, archives: P) {
+        self.archives_dir = archives.as_ref().to_path_buf();
+    }
+
     /// Configure the location of the `dpkg` database.
     ///
     /// This can be used to view `status` information, i.e. information on
@@ -100,34 +164,188 @@ impl System {
         self.dpkg_database = Some(dpkg.as_ref().to_path_buf());
     }
 
-    /// Load GPG keys from an old-style keyring (i.e. not a keybox file).
+    /// Where apt records which packages were installed automatically, usually
+    /// `/var/lib/apt/extended_states`.
+    pub fn set_extended_states<P: AsRef<Path>>(&mut self, path: P) {
+        self.extended_states = Some(path.as_ref().to_path_buf());
+    }
+
+    /// Configure how to reach mirrors, replacing the configuration read from the environment.
+    pub fn set_proxies(&mut self, proxies: ProxyConfig) {
+        self.proxies = proxies;
+    }
+
+    /// Configure TLS for a specific host, e.g. to trust a private CA.
     ///
-    /// Note that this will reject invalid keyring files, unlike other `*apt` implementations.
+    /// This overrides any TLS options given on sources entries for the same host.
+    pub fn set_tls_config<S: ToString>(&mut self, host: S, config: TlsConfig) {
+        self.tls.insert(host.to_string(), config);
+    }
+
+    /// Load repository credentials from an apt `auth.conf` (or `auth.conf.d/*.conf`) file.
+    ///
+    /// Entries are matched against urls by longest prefix. As with apt, entries with no
+    /// scheme (e.g. `machine example.com/debian`) are only used for `https`.
+    pub fn add_credentials_from<R: Read>(&mut self, source: R) -> Result<(), Error> {
+        self.credentials.append_from(source)
+    }
+
+    /// Load GPG keys from a keyring file: armoured (`.asc`), binary (`.gpg`), or a keybox
+    /// (`.kbx`).
+    ///
+    /// Keys which can't be used are skipped with a warning, as `apt` does, so a
+    /// `trusted.gpg.d` with an odd key in it still loads; see [`signing::Keys::append_from`].
     pub fn add_keys_from<R: Read>(&mut self, source: R) -> Result<(), Error> {
-        self.keyring.append_keys_from(source)?;
+        self.keys.append_from(source)?;
         Ok(())
     }
 
+    /// Decide which signatures to accept on _Releases_; by default, the same as `apt`.
+    pub fn set_signature_policy(&mut self, policy: signing::SignaturePolicy) {
+        self.policy = policy;
+    }
+
     /// Download any necessary _Listings_ for the configured _Sources Entries_.
     pub fn update(&self) -> Result<(), Error> {
+        fetch::block_on(self.update_inner())
+    }
+
+    /// Download any necessary _Listings_ for the configured _Sources Entries_,
+    /// without blocking the calling thread.
+    pub async fn update_async(&self) -> Result<(), Error> {
+        self.update_inner().await
+    }
+
+    async fn update_inner(&self) -> Result<(), Error> {
+        let client = self.client()?;
+
         let requested =
             release::RequestedReleases::from_sources_lists(&self.sources_entries, &self.arches)
                 .with_context(|| anyhow!("parsing sources entries"))?;
 
         requested
-            .download(&self.lists_dir, &self.keyring, &self.client)
+            .download(&self.lists_dir, &self.keys, &self.policy, &client)
+            .await
             .with_context(|| anyhow!("downloading releases"))?;
 
         let releases = requested
             .parse(&self.lists_dir)
             .with_context(|| anyhow!("parsing releases"))?;
 
-        lists::download_files(&self.client, &self.lists_dir, &releases)
+        lists::download_files(&client, &self.lists_dir, &releases)
+            .await
             .with_context(|| anyhow!("downloading release content"))?;
 
         Ok(())
     }
 
+    /// Copy the configured _Releases_ into `root`, laid out as on the mirror, with only the
+    /// selected packages in the pool. See [crate::mirror].
+    ///
+    /// All of the _Sources Entries_ must be for the same mirror.
+    pub fn mirror<P: AsRef<Path>>(
+        &self,
+        root: P,
+        selection: &mirror::Selection,
+    ) -> Result<mirror::Report, Error> {
+        fetch::block_on(self.mirror_inner(root.as_ref(), selection))
+    }
+
+    /// Update a copy of the configured _Releases_, without blocking the calling thread.
+    pub async fn mirror_async<P: AsRef<Path>>(
+        &self,
+        root: P,
+        selection: &mirror::Selection,
+    ) -> Result<mirror::Report, Error> {
+        self.mirror_inner(root.as_ref(), selection).await
+    }
+
+    async fn mirror_inner(
+        &self,
+        root: &Path,
+        selection: &mirror::Selection,
+    ) -> Result<mirror::Report, Error> {
+        let requested =
+            release::RequestedReleases::from_sources_lists(&self.sources_entries, &self.arches)
+                .with_context(|| anyhow!("parsing sources entries"))?;
+
+        mirror::mirror(
+            &self.client()?,
+            &self.keys,
+            &self.policy,
+            &requested,
+            root,
+            selection,
+        )
+        .await
+        .with_context(|| anyhow!("mirroring into {:?}", root))
+    }
+
+    /// Check a mirror's copy of a suite is complete and correct, e.g. when it's misbehaving.
+    ///
+    /// `mirror` is the root of the mirror, e.g. `http://deb.debian.org/debian/`. The `Release`
+    /// must be signed by one of the configured keys. See [crate::mirror].
+    pub fn check_mirror(
+        &self,
+        mirror: &str,
+        suite: &str,
+        deep: bool,
+    ) -> Result<mirror::CheckReport, Error> {
+        fetch::block_on(self.check_mirror_inner(mirror, suite, deep))
+    }
+
+    /// Check a mirror, without blocking the calling thread.
+    pub async fn check_mirror_async(
+        &self,
+        mirror: &str,
+        suite: &str,
+        deep: bool,
+    ) -> Result<mirror::CheckReport, Error> {
+        self.check_mirror_inner(mirror, suite, deep).await
+    }
+
+    async fn check_mirror_inner(
+        &self,
+        mirror: &str,
+        suite: &str,
+        deep: bool,
+    ) -> Result<mirror::CheckReport, Error> {
+        let mut url = Url::parse(mirror).with_context(|| anyhow!("parsing {:?}", mirror))?;
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+
+        mirror::check_mirror(&self.client()?, &self.keys, &self.policy, url, suite, deep)
+            .await
+            .with_context(|| anyhow!("checking {:?} on {}", suite, mirror))
+    }
+
+    fn dpkg_database(&self) -> Result<&Path, Error> {
+        self.dpkg_database
+            .as_deref()
+            .ok_or_else(|| anyhow!("dpkg database not set"))
+    }
+
+    fn client(&self) -> Result<fetch::Client, Error> {
+        let mut tls = HashMap::new();
+        for entry in &self.sources_entries {
+            let config = TlsConfig::from_options(&entry.options)
+                .with_context(|| anyhow!("tls options for {:?}", entry.url))?;
+            if let Some(config) = config {
+                let url = Url::parse(&entry.url)?;
+                let host = url
+                    .host_str()
+                    .ok_or_else(|| anyhow!("tls options need a host: {:?}", entry.url))?;
+                tls.insert(host.to_string(), config);
+            }
+        }
+
+        tls.extend(self.tls.clone());
+
+        fetch::Client::new(&self.proxies, &tls, self.credentials.clone())
+    }
+
     /// Explain the configured _Listings_.
     pub fn listings(&self) -> Result<Vec<DownloadedList>, Error> {
         let releases =
@@ -150,6 +368,89 @@ impl System {
         Ok(ret)
     }
 
+    /// Download the `.deb` for a binary package into the archive cache, returning its path.
+    pub fn download_deb(&self, pkg: &Package, list: &DownloadedList) -> Result<PathBuf, Error> {
+        Ok(self
+            .download_debs(&[(pkg, list)])?
+            .pop()
+            .expect("one in, one out"))
+    }
+
+    /// Download the `.deb`s for some binary packages into the archive cache, in parallel.
+    ///
+    /// Each is verified against its size, and the strongest hash available. Files already
+    /// in the cache are not downloaded again. The paths are returned in the same order.
+    pub fn download_debs(
+        &self,
+        packages: &[(&Package, &DownloadedList)],
+    ) -> Result<Vec<PathBuf>, Error> {
+        fetch::block_on(self.download_debs_inner(packages))
+    }
+
+    /// Download the `.deb`s for some binary packages, without blocking the calling thread.
+    pub async fn download_debs_async(
+        &self,
+        packages: &[(&Package, &DownloadedList)],
+    ) -> Result<Vec<PathBuf>, Error> {
+        self.download_debs_inner(packages).await
+    }
+
+    async fn download_debs_inner(
+        &self,
+        packages: &[(&Package, &DownloadedList)],
+    ) -> Result<Vec<PathBuf>, Error> {
+        let files = packages
+            .iter()
+            .map(|(pkg, list)| deb_pool_file(pkg, list))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        archives::download(&self.client()?, &self.archives_dir, &files).await
+    }
+
+    /// Download a source package (its `.dsc`, and all the files it lists) into a directory.
+    ///
+    /// Every file is checked against the size and sha256 in the index, and the `.dsc` is
+    /// checked to describe the same files as the index. If maintainer keys are provided,
+    /// the `.dsc` must be signed by one of them, under the same [`signing::SignaturePolicy`]
+    /// as release files.
+    pub fn download_source<P: AsRef<Path>>(
+        &self,
+        pkg: &Package,
+        list: &DownloadedList,
+        dest: P,
+        maintainer_keys: Option<&signing::Keys>,
+    ) -> Result<DownloadedSource, Error> {
+        fetch::block_on(dget::download_source(
+            &self.client()?,
+            &self.archives_dir,
+            pkg,
+            list,
+            dest.as_ref(),
+            maintainer_keys,
+            &self.policy,
+        ))
+    }
+
+    /// Download a source package into a directory, without blocking the calling thread.
+    pub async fn download_source_async<P: AsRef<Path>>(
+        &self,
+        pkg: &Package,
+        list: &DownloadedList,
+        dest: P,
+        maintainer_keys: Option<&signing::Keys>,
+    ) -> Result<DownloadedSource, Error> {
+        dget::download_source(
+            &self.client()?,
+            &self.archives_dir,
+            pkg,
+            list,
+            dest.as_ref(),
+            maintainer_keys,
+            &self.policy,
+        )
+        .await
+    }
+
     /// Open a `DownloadedList`, to access the packages inside it.
     pub fn open_listing(&self, list: &DownloadedList) -> Result<ListingBlocks, Error> {
         Ok(ListingBlocks {
@@ -159,17 +460,120 @@ impl System {
 
     /// Open the `dpkg` `status` database, to access the packages inside it.
     pub fn open_status(&self) -> Result<ListingBlocks, Error> {
-        let mut status = self
-            .dpkg_database
-            .as_ref()
-            .ok_or_else(|| anyhow!("dpkg database not set"))?
-            .to_path_buf();
-        status.push("status");
+        let status = self.dpkg_database()?.join("status");
 
         Ok(ListingBlocks {
             inner: rfc822::Blocks::new(fs::File::open(status)?, "status".to_string()),
         })
     }
+
+    /// Read the `dpkg` `status` database. See [crate::status].
+    pub fn status_database(&self) -> Result<status::Database, Error> {
+        let path = self.dpkg_database()?.join("status");
+        status::Database::read(
+            fs::File::open(&path).with_context(|| anyhow!("opening {:?}", path))?,
+        )
+        .with_context(|| anyhow!("reading {:?}", path))
+    }
+
+    /// Read the `dpkg` diversions, which move packages' files out of the way.
+    /// See [crate::ownership::Diversion].
+    pub fn diversions(&self) -> Result<Vec<ownership::Diversion>, Error> {
+        ownership::diversions_in(self.dpkg_database()?)
+    }
+
+    /// Read the `update-alternatives` link groups, by name. See [crate::alternatives].
+    pub fn alternatives(&self) -> Result<Vec<alternatives::Alternatives>, Error> {
+        alternatives::alternatives_in(self.dpkg_database()?)
+    }
+
+    /// Read apt's `extended_states`, which is empty until apt has installed something.
+    pub fn extended_states(&self) -> Result<Vec<selections::ExtendedState>, Error> {
+        let path = self
+            .extended_states
+            .as_ref()
+            .ok_or_else(|| anyhow!("extended_states not set"))?;
+        match fs::File::open(path) {
+            Ok(file) => selections::read_extended_states(file)
+                .with_context(|| anyhow!("reading {:?}", path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(Error::from(e).context(anyhow!("opening {:?}", path))),
+        }
+    }
+
+    /// Whether each installed package was installed automatically, and whether it's held.
+    /// apt records architecture-independent packages under the primary architecture, from
+    /// [System::set_arches]. See [crate::selections].
+    pub fn marks(&self) -> Result<Vec<selections::Mark>, Error> {
+        Ok(selections::marks(
+            &self.status_database()?,
+            &self.extended_states()?,
+            self.arches.first().map(String::as_str),
+        ))
+    }
+
+    /// Record a package, which was [deb::unpack]ed into `root`, in the dpkg database, as if
+    /// `dpkg` had installed it. See [crate::status::record].
+    pub fn record_unpacked<P: AsRef<Path>>(
+        &self,
+        root: P,
+        unpacked: &deb::Unpacked,
+        status: Status,
+    ) -> Result<(), Error> {
+        let dpkg = self.dpkg_database()?;
+        let entry = status::Entry::unpacked(root.as_ref(), unpacked, status)?;
+        status::record(dpkg, entry, &status::Info::from_unpacked(unpacked))
+            .with_context(|| anyhow!("recording {:?}", unpacked.control.package.name))
+    }
+
+    /// Remove a package from the dpkg database, without touching its files.
+    /// See [crate::status::forget].
+    pub fn forget_package(
+        &self,
+        package: &str,
+        arch: &str,
+    ) -> Result<Option<status::Entry>, Error> {
+        let dpkg = self.dpkg_database()?;
+        status::forget(dpkg, package, arch)
+            .with_context(|| anyhow!("forgetting {:?} on {:?}", package, arch))
+    }
+
+    /// Check the installed files against the hashes recorded in the dpkg database.
+    ///
+    /// `root` is where the packages are installed, usually `/`. See [crate::debsums].
+    pub fn debsums<P: AsRef<Path>>(&self, root: P) -> Result<debsums::Report, Error> {
+        let dpkg = self.dpkg_database()?;
+        debsums::check(&self.status_database()?, dpkg, root.as_ref())
+            .with_context(|| anyhow!("checking files under {:?}", root.as_ref()))
+    }
+
+    /// Index which installed package owns which files, from the dpkg database.
+    ///
+    /// `root` is where the packages are installed, usually `/`, and is consulted to see if
+    /// it is `/usr`-merged. See [crate::ownership].
+    pub fn file_index<P: AsRef<Path>>(&self, root: P) -> Result<ownership::FileIndex, Error> {
+        let dpkg = self.dpkg_database()?;
+        ownership::index(dpkg, root.as_ref())
+            .with_context(|| anyhow!("indexing files under {:?}", root.as_ref()))
+    }
+}
+
+fn deb_pool_file(pkg: &Package, list: &DownloadedList) -> Result<archives::PoolFile, Error> {
+    let file = pkg
+        .as_bin()
+        .ok_or_else(|| anyhow!("{:?} is not a binary package", pkg.name))?
+        .file
+        .as_ref()
+        .ok_or_else(|| anyhow!("{:?} has no Filename", pkg.name))?;
+
+    let (alg, hash) = file.strongest_hash()?;
+
+    Ok(archives::PoolFile {
+        url: list.release.req.pool_url(&file.name)?,
+        len: file.size,
+        alg,
+        hash,
+    })
 }
 
 /// The _Blocks_ of a _Listing_.
@@ -0,0 +1,189 @@
+//! Per-host TLS settings, for mirrors with private CAs or client certificates.
+//!
+//! These can also be set on a sources entry, as options named after apt's
+//! `Acquire::https` settings:
+//!
+//! ```text
+//! deb [ca-info=/etc/ssl/corp-ca.pem ssl-cert=/etc/fapt/me.pem ssl-key=/etc/fapt/me.key] https://deb.corp/ sid main
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Error;
+
+/// The TLS configuration for a host.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    /// Extra certificates to trust, in addition to the system's, PEM encoded.
+    pub root_certificates: Vec<Vec<u8>>,
+    /// A client certificate to present, PEM encoded.
+    pub client_identity: Option<ClientIdentity>,
+    /// Accept any certificate from the server. This is a terrible idea.
+    pub danger_accept_invalid_certs: bool,
+}
+
+/// A PEM encoded certificate and PKCS#8 private key, for mutual TLS.
+#[derive(Clone)]
+pub struct ClientIdentity {
+    pub certificate: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+impl TlsConfig {
+    /// Trust the certificate(s) in a PEM file.
+    pub fn add_root_certificate_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.root_certificates.push(
+            fs::read(path.as_ref())
+                .with_context(|| anyhow!("reading certificate {:?}", path.as_ref()))?,
+        );
+        Ok(())
+    }
+
+    /// Present this client certificate, from PEM files.
+    pub fn set_client_identity_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        certificate: P,
+        key: Q,
+    ) -> Result<(), Error> {
+        self.client_identity = Some(ClientIdentity {
+            certificate: fs::read(certificate.as_ref()).with_context(|| {
+                anyhow!("reading client certificate {:?}", certificate.as_ref())
+            })?,
+            key: fs::read(key.as_ref())
+                .with_context(|| anyhow!("reading client key {:?}", key.as_ref()))?,
+        });
+        Ok(())
+    }
+
+    /// Build a configuration from sources entry options, if any are present.
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Option<TlsConfig>, Error> {
+        let mut config = TlsConfig::default();
+        let mut any = false;
+
+        if let Some(path) = options.get("ca-info") {
+            config.add_root_certificate_file(path)?;
+            any = true;
+        }
+
+        match (options.get("ssl-cert"), options.get("ssl-key")) {
+            (Some(cert), Some(key)) => {
+                config.set_client_identity_files(cert, key)?;
+                any = true;
+            }
+            (None, None) => (),
+            _ => bail!("ssl-cert and ssl-key must be specified together"),
+        }
+
+        if let Some(verify) = options.get("verify-peer") {
+            config.danger_accept_invalid_certs = match verify.as_str() {
+                "yes" => false,
+                "no" => true,
+                other => bail!("invalid value for verify-peer: {:?}", other),
+            };
+            any = true;
+        }
+
+        Ok(if any { Some(config) } else { None })
+    }
+
+    pub(crate) fn apply(
+        &self,
+        mut builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, Error> {
+        for pem in &self.root_certificates {
+            for cert in reqwest::Certificate::from_pem_bundle(pem)
+                .with_context(|| anyhow!("loading root certificates"))?
+            {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        if let Some(identity) = &self.client_identity {
+            builder = builder.identity(
+                reqwest::Identity::from_pkcs8_pem(&identity.certificate, &identity.key)
+                    .with_context(|| anyhow!("loading client certificate"))?,
+            );
+        }
+
+        Ok(builder.danger_accept_invalid_certs(self.danger_accept_invalid_certs))
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("root_certificates", &self.root_certificates.len())
+            .field("client_identity", &self.client_identity.is_some())
+            .field(
+                "danger_accept_invalid_certs",
+                &self.danger_accept_invalid_certs,
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::TlsConfig;
+
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/client.pem");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/client.key");
+
+    fn options(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn from_options() {
+        assert!(TlsConfig::from_options(&options(&[("arch", "amd64")]))
+            .unwrap()
+            .is_none());
+
+        let config = TlsConfig::from_options(&options(&[
+            ("ca-info", CERT),
+            ("ssl-cert", CERT),
+            ("ssl-key", KEY),
+            ("verify-peer", "yes"),
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(1, config.root_certificates.len());
+        assert!(config.client_identity.is_some());
+        assert!(!config.danger_accept_invalid_certs);
+        config.apply(reqwest::Client::builder()).unwrap();
+
+        let config = TlsConfig::from_options(&options(&[("verify-peer", "no")]))
+            .unwrap()
+            .unwrap();
+        assert!(config.danger_accept_invalid_certs);
+    }
+
+    #[test]
+    fn invalid_options() {
+        let invalid = |pairs: &[(&str, &str)]| TlsConfig::from_options(&options(pairs)).is_err();
+        assert!(invalid(&[("ssl-cert", CERT)]));
+        assert!(invalid(&[("ssl-key", KEY)]));
+        assert!(invalid(&[("verify-peer", "maybe")]));
+        assert!(invalid(&[("ca-info", "/nonexistent/ca.pem")]));
+    }
+
+    #[test]
+    fn unusable_identity() {
+        // the certificate is not a key
+        let config = TlsConfig::from_options(&options(&[("ssl-cert", CERT), ("ssl-key", CERT)]))
+            .unwrap()
+            .unwrap();
+        assert!(config.apply(reqwest::Client::builder()).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::Error;
+use fapt::alternatives::read_alternatives;
+use fapt::alternatives::Mode;
+use fapt::ownership::read_diversions;
+use fapt::system::System;
+
+const DPKG: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dpkg");
+
+#[test]
+fn diversions() -> Result<(), Error> {
+    let diversions = read_diversions(&include_bytes!("dpkg/diversions")[..])?;
+    assert_eq!(2, diversions.len());
+    assert_eq!("/usr/share/man/man1/sh.1.gz", diversions[0].from);
+    assert_eq!("/usr/share/man/man1/sh.distrib.1.gz", diversions[0].to);
+    assert_eq!(Some("dash"), diversions[0].by.as_deref());
+    assert_eq!("/etc/motd.orig", diversions[1].to);
+    assert_eq!(None, diversions[1].by);
+
+    assert!(read_diversions(&b"/etc/motd\n/etc/motd.orig\n"[..]).is_err());
+    Ok(())
+}
+
+#[test]
+fn awk() -> Result<(), Error> {
+    let awk = read_alternatives("awk", &include_bytes!("dpkg/alternatives/awk")[..])?;
+    assert_eq!("awk", awk.name);
+    assert_eq!(Mode::Auto, awk.mode);
+    assert_eq!("/usr/bin/awk", awk.link);
+    assert_eq!(3, awk.slaves.len());
+    assert_eq!("nawk", awk.slaves[1].name);
+    assert_eq!("/usr/bin/nawk", awk.slaves[1].link);
+
+    assert_eq!(1, awk.candidates.len());
+    let mawk = &awk.candidates[0];
+    assert_eq!("/usr/bin/mawk", mawk.path);
+    assert_eq!(5, mawk.priority);
+    assert_eq!(
+        Some("/usr/bin/mawk"),
+        mawk.slaves.get("nawk").map(|s| s.as_str())
+    );
+    Ok(())
+}
+
+#[test]
+fn editor() -> Result<(), Error> {
+    let editor = read_alternatives("editor", &include_bytes!("dpkg/alternatives/editor")[..])?;
+    assert_eq!(Mode::Manual, editor.mode);
+    assert_eq!(3, editor.candidates.len());
+    // ed doesn't provide the man page
+    assert!(editor.candidates[2].slaves.is_empty());
+    assert_eq!(-100, editor.candidates[2].priority);
+    assert_eq!("/bin/nano", editor.best().unwrap().path);
+    Ok(())
+}
+
+#[test]
+fn invalid() {
+    let read = |text: &str| read_alternatives("foo", text.as_bytes());
+    assert!(read("").is_err());
+    assert!(read("sometimes\n/usr/bin/foo\n\n\n").is_err());
+    // truncated, with no blank line ending the candidates
+    assert!(read("auto\n/usr/bin/foo\n").is_err());
+    assert!(read("auto\n/usr/bin/foo\n\n/usr/bin/foo.a\nhigh\n\n").is_err());
+    // a candidate missing the target line for its slave
+    assert!(
+        read("auto\n/usr/bin/foo\nfoo.1\n/usr/share/man/man1/foo.1\n\n/usr/bin/foo.a\n5\n")
+            .is_err()
+    );
+    assert!(read("auto\n/usr/bin/foo\n\n\n").unwrap().best().is_none());
+}
+
+#[test]
+fn system() -> Result<(), Error> {
+    let lists = tempfile::tempdir()?;
+    let mut fapt = System::cache_only_in(lists.path())?;
+    assert!(fapt.alternatives().is_err());
+
+    fapt.set_dpkg_database(DPKG);
+    let groups = fapt.alternatives()?;
+    let names: Vec<&str> = groups.iter().map(|g| g.name.as_str()).collect();
+    assert_eq!(vec!["awk", "editor"], names);
+    assert_eq!(2, fapt.diversions()?.len());
+
+    // a database that has never had either
+    fapt.set_dpkg_database(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/debs"));
+    assert!(fapt.alternatives()?.is_empty());
+    assert!(fapt.diversions()?.is_empty());
+    Ok(())
+}
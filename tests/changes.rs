@@ -0,0 +1,58 @@
+use std::fs;
+use std::io;
+
+use anyhow::Error;
+use fapt::parse::Changes;
+use fapt::parse::Priority;
+use fapt::signing::SignatureStatus;
+
+mod common;
+
+const CHANGES: &str = include_str!("packages/hello-src_1.0-1_source.changes");
+
+#[test]
+fn read_signed_changes() -> Result<(), Error> {
+    let keyring = common::keyring()?;
+    let (changes, status) = Changes::read(io::Cursor::new(CHANGES), Some(&keyring))?;
+    assert_eq!(SignatureStatus::Verified, status);
+
+    assert_eq!("hello-src", changes.source);
+    assert_eq!("1.0-1", changes.version);
+    assert_eq!(vec!["unstable"], changes.distribution);
+    assert_eq!("medium", changes.urgency);
+    assert_eq!(vec!["source"], changes.architecture);
+    assert!(changes.binaries.is_empty());
+    assert_eq!("Other Person", changes.changed_by[0].name);
+    assert_eq!(vec![987654, 987655], changes.closes);
+    assert_eq!(
+        "hello-src (1.0-1) unstable; urgency=medium\n\n* Initial release. (Closes: #987654, #987655)",
+        changes.changes
+    );
+
+    assert_eq!(3, changes.files.len());
+    for file in &changes.files {
+        assert_eq!("misc", file.section);
+        assert_eq!(Priority::Optional, file.priority);
+        assert!(file.sha256.is_some());
+    }
+
+    assert!(changes.unparsed.is_empty(), "{:?}", changes.unparsed);
+    Ok(())
+}
+
+#[test]
+fn verify_files() -> Result<(), Error> {
+    let (changes, _) = Changes::read(io::Cursor::new(CHANGES), None)?;
+
+    let dir = tempfile::tempdir()?;
+    assert!(changes.verify_files(dir.path()).is_err());
+
+    fs::write(dir.path().join("hello-src_1.0-1.dsc"), "dsc\n")?;
+    fs::write(dir.path().join("hello-src_1.0.orig.tar.gz"), "orig\n")?;
+    fs::write(dir.path().join("hello-src_1.0-1.debian.tar.xz"), "debian\n")?;
+    changes.verify_files(dir.path())?;
+
+    fs::write(dir.path().join("hello-src_1.0-1.debian.tar.xz"), "debiaN\n")?;
+    assert!(changes.verify_files(dir.path()).is_err());
+    Ok(())
+}
@@ -0,0 +1,24 @@
+//! Fixtures shared by the integration tests; each test crate uses only some of them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::Error;
+use gpgrv::Keyring;
+
+/// The public half of the key the test repositories and documents are signed with.
+pub fn keyring() -> Result<Keyring, Error> {
+    let mut keyring = Keyring::new();
+    keyring.append_keys_from_armoured(io::Cursor::new(include_str!("../keys/test-repo.asc")))?;
+    Ok(keyring)
+}
+
+/// Write a file under `root`, creating its directories.
+pub fn write(root: &Path, path: &str, content: &str) -> Result<(), Error> {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, content)?;
+    Ok(())
+}
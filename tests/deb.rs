@@ -0,0 +1,158 @@
+use std::io;
+
+use anyhow::Error;
+use fapt::deb::Control;
+use fapt::deb::Deb;
+use fapt::deb::EntryKind;
+
+const FIXTURES: &[&[u8]] = &[
+    include_bytes!("debs/fapt-fixture_gzip.deb"),
+    include_bytes!("debs/fapt-fixture_xz.deb"),
+    include_bytes!("debs/fapt-fixture_zstd.deb"),
+];
+
+#[test]
+fn control() -> Result<(), Error> {
+    for deb in FIXTURES {
+        let control = Control::read(io::Cursor::new(deb))?;
+        let pkg = &control.package;
+        assert_eq!("fapt-fixture", pkg.name);
+        assert_eq!("1.2-3", pkg.version);
+        assert_eq!("utils", pkg.section);
+        let bin = pkg.as_bin().unwrap();
+        assert_eq!(12, bin.installed_size);
+        assert_eq!(2, bin.depends.len());
+
+        assert_eq!(
+            vec![
+                "/usr/bin/fapt-fixture",
+                "/usr/share/doc/fapt-fixture/README",
+                "/usr/share/doc/fapt-fixture/README.hardlink",
+            ],
+            control
+                .md5sums
+                .iter()
+                .map(|(path, _)| path.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(1, control.conffiles.len());
+        assert_eq!("/etc/fapt-fixture.conf", control.conffiles[0].path);
+
+        assert_eq!(vec!["postinst"], control.scripts.keys().collect::<Vec<_>>());
+        assert!(control.scripts["postinst"].starts_with(b"#!/bin/sh"));
+        assert!(control.other.contains_key("triggers"));
+    }
+    Ok(())
+}
+
+#[test]
+fn data() -> Result<(), Error> {
+    for deb in FIXTURES {
+        let deb = Deb::read(io::Cursor::new(deb))?;
+        let find = |path: &str| deb.data.iter().find(|e| e.path == path).unwrap();
+
+        assert_eq!(EntryKind::Directory, find("/").kind);
+
+        let bin = find("/usr/bin/fapt-fixture");
+        assert_eq!(EntryKind::File, bin.kind);
+        assert_eq!(0o755, bin.mode);
+        assert_eq!(21, bin.size);
+
+        assert_eq!(
+            EntryKind::Symlink("fapt-fixture".to_string()),
+            find("/usr/bin/fapt-fixture-link").kind
+        );
+        assert_eq!(
+            EntryKind::HardLink("/usr/share/doc/fapt-fixture/README".to_string()),
+            find("/usr/share/doc/fapt-fixture/README.hardlink").kind
+        );
+
+        let state = find("/var/lib/fapt-fixture");
+        assert_eq!(0o2775, state.mode);
+        assert_eq!(Some("nobody"), state.user.as_deref());
+        assert_eq!(Some("nogroup"), state.group.as_deref());
+    }
+    Ok(())
+}
+
+#[test]
+fn not_a_deb() {
+    assert!(Deb::read(io::Cursor::new(b"!<arch>\nnope")).is_err());
+    assert!(Control::read(io::Cursor::new(include_bytes!("deb.rs"))).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn unpack() -> Result<(), Error> {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    let root = tempfile::tempdir()?;
+    let root = root.path();
+
+    // an existing symlink pointing outside of the root must not be followed outside of it
+    std::os::unix::fs::symlink("/nonexistent-fapt-outside", root.join("var"))?;
+
+    let unpacked = fapt::deb::unpack(io::Cursor::new(FIXTURES[1]), root)?;
+    assert_eq!("fapt-fixture", unpacked.control.package.name);
+
+    let bin = root.join("usr/bin/fapt-fixture");
+    assert_eq!("#!/bin/sh\necho hello\n", fs::read_to_string(&bin)?);
+    assert_eq!(0o755, fs::metadata(&bin)?.mode() & 0o7777);
+
+    assert_eq!(
+        "fapt-fixture",
+        fs::read_link(root.join("usr/bin/fapt-fixture-link"))?
+            .to_str()
+            .unwrap()
+    );
+
+    let doc = root.join("usr/share/doc/fapt-fixture");
+    assert_eq!(
+        fs::metadata(doc.join("README"))?.ino(),
+        fs::metadata(doc.join("README.hardlink"))?.ino()
+    );
+
+    assert!(root
+        .join("nonexistent-fapt-outside/lib/fapt-fixture")
+        .is_dir());
+
+    let state = unpacked
+        .manifest
+        .iter()
+        .find(|m| "/var/lib/fapt-fixture" == m.entry.path)
+        .unwrap();
+    assert_eq!(0o2775, state.entry.mode);
+    assert_eq!(Some("nobody"), state.entry.user.as_deref());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn unpack_diverted() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    let root = root.path();
+
+    let unpacked = fapt::deb::unpack_diverted(io::Cursor::new(FIXTURES[2]), root, |path| {
+        if path.starts_with("/usr/share/doc") {
+            None
+        } else if "/usr/bin/fapt-fixture" == path {
+            Some("/usr/bin/fapt-fixture.distrib".to_string())
+        } else {
+            Some(path.to_string())
+        }
+    })?;
+
+    assert!(root.join("usr/bin/fapt-fixture.distrib").is_file());
+    assert!(!root.join("usr/bin/fapt-fixture").exists());
+    assert!(!root.join("usr/share/doc").exists());
+
+    let skipped = unpacked
+        .manifest
+        .iter()
+        .filter(|m| m.written_to.is_none())
+        .count();
+    assert_eq!(4, skipped);
+    Ok(())
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Error;
+use fapt::debsums::Problem;
+use fapt::system::System;
+
+mod common;
+
+const STATUS: &str = "\
+Package: hello
+Status: install ok installed
+Architecture: amd64
+Version: 1.0
+Conffiles:
+ /etc/hello.conf 5d41402abc4b2a76b9719d911017c592
+ /etc/hello.d/new newconffile
+ /etc/hello.old 5d41402abc4b2a76b9719d911017c592 obsolete
+
+Package: libhello
+Status: install ok installed
+Architecture: amd64
+Multi-Arch: same
+Version: 1.0
+
+Package: removed
+Status: deinstall ok config-files
+Architecture: all
+Version: 1.0
+Conffiles:
+ /etc/removed.conf 5d41402abc4b2a76b9719d911017c592
+
+Package: ancient
+Status: install ok installed
+Architecture: all
+Version: 1.0
+";
+
+/// An installed system, with a dpkg database in the usual place.
+fn installed(root: &Path) -> Result<(), Error> {
+    let info = "var/lib/dpkg/info";
+    common::write(root, "var/lib/dpkg/status", STATUS)?;
+    common::write(
+        root,
+        &format!("{}/hello.md5sums", info),
+        concat!(
+            "5d41402abc4b2a76b9719d911017c592  usr/bin/hello\n",
+            "7d793037a0760186574b0282f2f435e7  usr/share/doc/hello/a file\n",
+            "5d41402abc4b2a76b9719d911017c592  etc/hello.conf\n",
+        ),
+    )?;
+    common::write(
+        root,
+        &format!("{}/hello.conffiles", info),
+        "/etc/hello.conf\n/etc/hello.d/new\n",
+    )?;
+    common::write(
+        root,
+        &format!("{}/libhello:amd64.md5sums", info),
+        "5d41402abc4b2a76b9719d911017c592  usr/lib/libhello.so\n",
+    )?;
+    common::write(
+        root,
+        &format!("{}/removed.md5sums", info),
+        "5d41402abc4b2a76b9719d911017c592  usr/bin/removed\n",
+    )?;
+
+    common::write(root, "usr/bin/hello", "hello")?;
+    common::write(root, "usr/share/doc/hello/a file", "world")?;
+    common::write(root, "etc/hello.conf", "hello")?;
+    common::write(root, "etc/hello.d/new", "anything")?;
+    common::write(root, "usr/lib/libhello.so", "hello")?;
+    Ok(())
+}
+
+fn debsums(root: &Path) -> Result<fapt::debsums::Report, Error> {
+    let lists = tempfile::tempdir()?;
+    let mut system = System::cache_only_in(lists.path())?;
+    system.set_dpkg_database(root.join("var/lib/dpkg"));
+    system.debsums(root)
+}
+
+#[test]
+fn clean() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    installed(root.path())?;
+    let report = debsums(root.path())?;
+    assert_eq!(Vec::<Problem>::new(), report.problems);
+    assert_eq!(
+        vec![Problem::Unverifiable {
+            package: "hello".to_string(),
+            path: "/etc/hello.d/new".to_string(),
+            reason: "no hash recorded".to_string(),
+        }],
+        report.conffiles
+    );
+    assert_eq!(vec!["ancient".to_string()], report.unverified);
+    // the conffile listed in md5sums is only checked once
+    assert_eq!(5, report.checked);
+    Ok(())
+}
+
+#[test]
+fn tampered() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    let root = root.path();
+    installed(root)?;
+    common::write(root, "usr/bin/hello", "evil")?;
+    fs::remove_file(root.join("usr/lib/libhello.so"))?;
+    common::write(root, "etc/hello.conf", "edited")?;
+    fs::remove_file(root.join("usr/share/doc/hello/a file"))?;
+    fs::create_dir(root.join("usr/share/doc/hello/a file"))?;
+
+    let report = debsums(root)?;
+    assert_eq!(
+        vec![
+            Problem::Modified {
+                package: "hello".to_string(),
+                path: "/usr/bin/hello".to_string(),
+            },
+            Problem::Unverifiable {
+                package: "hello".to_string(),
+                path: "/usr/share/doc/hello/a file".to_string(),
+                reason: "not a regular file".to_string(),
+            },
+            Problem::Missing {
+                package: "libhello:amd64".to_string(),
+                path: "/usr/lib/libhello.so".to_string(),
+            },
+        ],
+        report.problems
+    );
+    assert_eq!(
+        vec!["/etc/hello.conf", "/etc/hello.d/new"],
+        report
+            .conffiles
+            .iter()
+            .map(|p| p.path())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        "/usr/bin/hello: modified (hello)",
+        report.problems[0].to_string()
+    );
+    Ok(())
+}
+
+#[test]
+fn symlinks_stay_inside_root() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    let root = root.path();
+    installed(root)?;
+    let host = tempfile::tempdir()?;
+    let outside = host.path().join("hello");
+    fs::write(&outside, "evil")?;
+
+    // the image's copy of the link target is the one checked, not the host's
+    fs::remove_file(root.join("usr/bin/hello"))?;
+    std::os::unix::fs::symlink(&outside, root.join("usr/bin/hello"))?;
+    common::write(
+        root,
+        outside.to_str().unwrap().trim_start_matches('/'),
+        "hello",
+    )?;
+    fs::OpenOptions::new()
+        .append(true)
+        .open(root.join("var/lib/dpkg/info/hello.md5sums"))?
+        .write_all(b"5d41402abc4b2a76b9719d911017c592  usr/../../etc/hostname\n5d41402abc4b2a76b9719d911017c592  usr/games/hello\n")?;
+
+    let report = debsums(root)?;
+    assert_eq!(
+        vec![
+            Problem::Unverifiable {
+                package: "hello".to_string(),
+                path: "/usr/../../etc/hostname".to_string(),
+                reason: "outside the root".to_string(),
+            },
+            Problem::Missing {
+                package: "hello".to_string(),
+                path: "/usr/games/hello".to_string(),
+            },
+        ],
+        report.problems
+    );
+    // checking doesn't change the image
+    assert!(!root.join("usr/games").exists());
+    Ok(())
+}
+
+#[test]
+fn diversions_and_odd_names() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    let root = root.path();
+    installed(root)?;
+
+    // another package's copy is at the original path; hello's has moved aside
+    common::write(
+        root,
+        "var/lib/dpkg/diversions",
+        "/usr/bin/hello\n/usr/bin/hello.distrib\nother\n",
+    )?;
+    common::write(root, "usr/bin/hello", "other")?;
+    common::write(root, "usr/bin/hello.distrib", "hello")?;
+
+    fs::OpenOptions::new()
+        .append(true)
+        .open(root.join("var/lib/dpkg/info/hello.md5sums"))?
+        .write_all(b"5d41402abc4b2a76b9719d911017c592  usr/bin/h\xffllo\n")?;
+
+    let report = debsums(root)?;
+    assert_eq!(
+        vec![Problem::Unverifiable {
+            package: "hello".to_string(),
+            path: "/usr/bin/h\u{fffd}llo".to_string(),
+            reason: "non-utf-8 path".to_string(),
+        }],
+        report.problems
+    );
+    // the rest of the package was still checked
+    assert_eq!(5, report.checked);
+    Ok(())
+}
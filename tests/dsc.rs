@@ -0,0 +1,54 @@
+use std::io;
+
+use anyhow::Error;
+use fapt::parse::Package;
+use fapt::parse::SourceFormat;
+use fapt::signing::SignatureStatus;
+
+mod common;
+
+const DSC: &str = include_str!("packages/hello-src_1.0-1.dsc");
+
+#[test]
+fn read_signed_dsc() -> Result<(), Error> {
+    let keyring = common::keyring()?;
+    let (pkg, status) = Package::read_dsc(io::Cursor::new(DSC), Some(&keyring))?;
+    assert_eq!(SignatureStatus::Verified, status);
+
+    assert_eq!("hello-src", pkg.name);
+    assert_eq!("1.0-1", pkg.version);
+    assert_eq!("", pkg.section);
+
+    let src = pkg.as_src().unwrap();
+    assert_eq!(SourceFormat::Quilt3dot0, src.format);
+    assert_eq!(None, src.directory);
+    assert_eq!(vec!["autopkgtest"], src.testsuite);
+    assert_eq!(vec!["python3", "shellcheck"], src.testsuite_triggers);
+    assert!(src.dgit.as_ref().unwrap().starts_with("5d2f7a0c"));
+
+    assert_eq!(2, src.files.len());
+    for file in &src.files {
+        assert!(file.sha1.is_some(), "{:?}", file.name);
+        assert!(file.sha256.is_some(), "{:?}", file.name);
+        assert!(file.sha512.is_some(), "{:?}", file.name);
+    }
+
+    assert!(pkg.unparsed.is_empty(), "{:?}", pkg.unparsed);
+    Ok(())
+}
+
+#[test]
+fn unchecked_without_keyring() -> Result<(), Error> {
+    let (pkg, status) = Package::read_dsc(io::Cursor::new(DSC), None)?;
+    assert_eq!(SignatureStatus::Unchecked, status);
+    assert_eq!("hello-src", pkg.name);
+    Ok(())
+}
+
+#[test]
+fn tampered() -> Result<(), Error> {
+    let keyring = common::keyring()?;
+    let tampered = DSC.replace("Version: 1.0-1", "Version: 1.0-2");
+    assert!(Package::read_dsc(io::Cursor::new(tampered), Some(&keyring)).is_err());
+    Ok(())
+}
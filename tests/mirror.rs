@@ -0,0 +1,557 @@
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+
+use anyhow::bail;
+use anyhow::Error;
+use fapt::mirror::Problem;
+use fapt::mirror::Selection;
+use fapt::parse::Package;
+use fapt::parse::PackageType;
+use fapt::proxy::ProxyConfig;
+use fapt::publish::Suite;
+use fapt::signing::Keys;
+use fapt::signing::SigningKey;
+use fapt::system::DownloadedList;
+use fapt::system::System;
+
+const SOURCES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/sources");
+
+/// A signed repository, with a binary package in main, and source packages in both components.
+fn upstream(root: &Path) -> Result<(), Error> {
+    let debs = root.join("pool/main/f/fapt-fixture");
+    fs::create_dir_all(&debs)?;
+    fs::write(
+        debs.join("fapt-fixture_1.2-3_all.deb"),
+        &include_bytes!("debs/fapt-fixture_xz.deb")[..],
+    )?;
+
+    for (prefix, dir) in &[
+        ("fapt-quilt_", "pool/main/f/fapt-quilt"),
+        ("fapt-old_", "pool/contrib/f/fapt-old"),
+    ] {
+        fs::create_dir_all(root.join(dir))?;
+        for file in fs::read_dir(SOURCES)? {
+            let file = file?;
+            let name = file.file_name().into_string().unwrap();
+            if name.starts_with(prefix) {
+                fs::copy(file.path(), root.join(dir).join(&name))?;
+            }
+        }
+    }
+
+    publish(root)
+}
+
+/// Generate and sign the indices for whatever is in the pool.
+fn publish(root: &Path) -> Result<(), Error> {
+    let mut suite = Suite::new("internal");
+    suite.arches = vec!["amd64".to_string()];
+    suite.by_hash = true;
+    let release = fapt::publish::publish(root, &suite)?;
+    let key = SigningKey::read(&include_bytes!("keys/test-repo.secret.asc")[..], None)?;
+    fapt::publish::sign(release, &key)?;
+    Ok(())
+}
+
+/// Serve files from `root` over http, returning the base url.
+fn serve(root: PathBuf) -> Result<String, Error> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            let mut words = request.split_whitespace();
+            let head = Some("HEAD") == words.next();
+            let path = words.next().unwrap_or("/");
+            let response = match fs::read(root.join(path.trim_start_matches('/'))) {
+                Ok(body) => {
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes();
+                    if !head {
+                        response.extend(body);
+                    }
+                    response
+                }
+                Err(e) if io::ErrorKind::NotFound == e.kind() => {
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_vec()
+                }
+                // e.g. a directory
+                Err(_) => b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_vec(),
+            };
+            // the client may hang up early, which is fine
+            let _ = stream.write_all(&response);
+        }
+    });
+    Ok(url)
+}
+
+fn system(url: &str, lists: &Path, components: &str) -> Result<System, Error> {
+    let mut system = System::cache_only_in(lists)?;
+    system.set_proxies(ProxyConfig::default());
+    system.set_arches(&["amd64"]);
+    system.add_keys_from(&include_bytes!("keys/test-repo.gpg")[..])?;
+    fapt::commands::add_sources_entries_from_str(
+        &mut system,
+        format!(
+            "deb {url} internal {components}\ndeb-src {url} internal {components}\n",
+            url = url,
+            components = components
+        ),
+    )?;
+    Ok(system)
+}
+
+fn files(root: &Path) -> Result<BTreeSet<String>, Error> {
+    let mut ret = BTreeSet::new();
+    let mut todo = vec![root.to_path_buf()];
+    while let Some(dir) = todo.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                todo.push(path);
+            } else {
+                let relative = path.strip_prefix(root)?.to_str().unwrap().to_string();
+                ret.insert(relative);
+            }
+        }
+    }
+    Ok(ret)
+}
+
+fn names(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// The package named `name`, binary or source, and the listing it's in.
+fn find(system: &System, name: &str) -> Result<(Package, DownloadedList), Error> {
+    for list in system.listings()? {
+        for block in system.open_listing(&list)? {
+            let pkg = block?.as_pkg()?;
+            if name == pkg.name {
+                return Ok((pkg, list));
+            }
+        }
+    }
+    bail!("no package named {:?}", name)
+}
+
+#[test]
+fn mirror() -> Result<(), Error> {
+    let upstream_dir = tempfile::tempdir()?;
+    upstream(upstream_dir.path())?;
+    let url = serve(upstream_dir.path().to_path_buf())?;
+
+    let lists = tempfile::tempdir()?;
+    let copy = tempfile::tempdir()?;
+    let copy_root = copy.path().join("mirror");
+
+    // everything: an exact copy
+    let all = system(&url, lists.path(), "main contrib")?;
+    let report = all.mirror(&copy_root, &Selection::All)?;
+    assert!(report.pruned.is_empty());
+    assert_eq!(files(upstream_dir.path())?, files(&copy_root)?);
+    for name in &["InRelease", "Release", "Release.gpg"] {
+        assert_eq!(
+            fs::read(upstream_dir.path().join("dists/internal").join(name))?,
+            fs::read(copy_root.join("dists/internal").join(name))?
+        );
+    }
+
+    // nothing has changed
+    let report = all.mirror(&copy_root, &Selection::All)?;
+    assert!(report.downloaded.is_empty());
+    assert!(report.pruned.is_empty());
+
+    // just main, and just one package from it
+    let main = system(&url, lists.path(), "main")?;
+    let report = main.mirror(&copy_root, &Selection::Packages(names(&["fapt-fixture"])))?;
+    let left = files(&copy_root)?;
+    assert!(left.contains("pool/main/f/fapt-fixture/fapt-fixture_1.2-3_all.deb"));
+    assert!(left.contains("dists/internal/main/binary-amd64/Packages.xz"));
+    assert!(!left.iter().any(|path| path.contains("fapt-quilt")));
+    assert!(!left.iter().any(|path| path.contains("contrib")));
+    assert!(report
+        .pruned
+        .contains(&PathBuf::from("pool/contrib/f/fapt-old/fapt-old_1.0-1.dsc")));
+    assert!(!copy_root.join("pool/contrib").exists());
+
+    // the source, by name
+    main.mirror(&copy_root, &Selection::Packages(names(&["fapt-quilt"])))?;
+    let left = files(&copy_root)?;
+    assert!(left.contains("pool/main/f/fapt-quilt/fapt-quilt_1.0-1.dsc"));
+    assert!(!left.iter().any(|path| path.contains("fapt-fixture_")));
+
+    // an InRelease which doesn't verify is skipped, if Release.gpg does
+    let in_release = upstream_dir.path().join("dists/internal/InRelease");
+    let text = fs::read_to_string(&in_release)?;
+    fs::write(&in_release, text.replace("Suite: internal", "Suite: evil"))?;
+    main.mirror(&copy_root, &Selection::All)?;
+    assert!(!copy_root.join("dists/internal/InRelease").exists());
+    assert!(copy_root.join("dists/internal/Release.gpg").exists());
+
+    // but not if there's nothing else
+    fs::remove_file(upstream_dir.path().join("dists/internal/Release.gpg"))?;
+    assert!(main.mirror(&copy_root, &Selection::All).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn by_hash_outlives_its_release() -> Result<(), Error> {
+    let upstream_dir = tempfile::tempdir()?;
+    let root = upstream_dir.path();
+    upstream(root)?;
+    let url = serve(root.to_path_buf())?;
+
+    let lists = tempfile::tempdir()?;
+    let copy = tempfile::tempdir()?;
+    let system = system(&url, lists.path(), "main")?;
+    let by_hash = || -> Result<BTreeSet<String>, Error> {
+        Ok(files(copy.path())?
+            .into_iter()
+            .filter(|path| path.contains("/by-hash/"))
+            .collect())
+    };
+
+    system.mirror(copy.path(), &Selection::All)?;
+    let first = by_hash()?;
+    assert!(!first.is_empty());
+
+    // the source package goes away, so Sources changes
+    for file in fs::read_dir(root.join("pool/main/f/fapt-quilt"))? {
+        fs::remove_file(file?.path())?;
+    }
+    publish(root)?;
+
+    // clients may still have the old InRelease, so can still fetch what it lists
+    system.mirror(copy.path(), &Selection::All)?;
+    let second = by_hash()?;
+    assert!(second.is_superset(&first));
+    assert!(second.len() > first.len());
+
+    // ..until the next run
+    let report = system.mirror(copy.path(), &Selection::All)?;
+    let third = by_hash()?;
+    assert!(third.is_subset(&second));
+    let gone: Vec<&String> = first.difference(&third).collect();
+    assert!(!gone.is_empty());
+    assert!(gone.iter().all(|path| path.contains("/source/by-hash/")));
+    for path in gone {
+        assert!(report.pruned.contains(&PathBuf::from(path)));
+    }
+    Ok(())
+}
+
+#[test]
+fn corrupt_pool() -> Result<(), Error> {
+    let upstream_dir = tempfile::tempdir()?;
+    upstream(upstream_dir.path())?;
+    let url = serve(upstream_dir.path().to_path_buf())?;
+
+    let deb = upstream_dir
+        .path()
+        .join("pool/main/f/fapt-fixture/fapt-fixture_1.2-3_all.deb");
+    let mut data = fs::read(&deb)?;
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    fs::write(&deb, data)?;
+
+    let lists = tempfile::tempdir()?;
+    let copy = tempfile::tempdir()?;
+    let system = system(&url, lists.path(), "main")?;
+    assert!(system.mirror(copy.path(), &Selection::All).is_err());
+    assert!(!copy.path().join("pool/main/f/fapt-fixture").exists());
+    assert!(!copy.path().join("dists").exists());
+    Ok(())
+}
+
+#[test]
+fn download_debs() -> Result<(), Error> {
+    let upstream_dir = tempfile::tempdir()?;
+    upstream(upstream_dir.path())?;
+    let url = serve(upstream_dir.path().to_path_buf())?;
+
+    let lists = tempfile::tempdir()?;
+    let archives = tempfile::tempdir()?;
+    let mut system = system(&url, lists.path(), "main")?;
+    system.set_archives_dir(archives.path());
+    system.update()?;
+
+    let (pkg, list) = find(&system, "fapt-fixture")?;
+    let paths = system.download_debs(&[(&pkg, &list), (&pkg, &list)])?;
+    assert_eq!(paths[0], paths[1]);
+    // e.g. SHA256/0123abcd...
+    assert_eq!(
+        Some(archives.path()),
+        paths[0].parent().and_then(Path::parent)
+    );
+    assert_eq!(
+        &include_bytes!("debs/fapt-fixture_xz.deb")[..],
+        &fs::read(&paths[0])?[..]
+    );
+
+    // already in the cache, so upstream isn't asked again
+    let deb = upstream_dir
+        .path()
+        .join("pool/main/f/fapt-fixture/fapt-fixture_1.2-3_all.deb");
+    let mut data = fs::read(&deb)?;
+    fs::remove_file(&deb)?;
+    assert_eq!(paths[0], system.download_deb(&pkg, &list)?);
+
+    // a new cache, and the upstream file has been tampered with
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    fs::write(&deb, data)?;
+    let other = tempfile::tempdir()?;
+    system.set_archives_dir(other.path());
+    assert!(system.download_deb(&pkg, &list).is_err());
+    assert_eq!(BTreeSet::new(), files(other.path())?);
+
+    // only binary packages have debs
+    let (source, source_list) = find(&system, "fapt-quilt")?;
+    assert!(system.download_deb(&source, &source_list).is_err());
+    Ok(())
+}
+
+#[test]
+fn download_source() -> Result<(), Error> {
+    let upstream_dir = tempfile::tempdir()?;
+    upstream(upstream_dir.path())?;
+    let url = serve(upstream_dir.path().to_path_buf())?;
+
+    let lists = tempfile::tempdir()?;
+    let system = system(&url, lists.path(), "main")?;
+    system.update()?;
+
+    let (pkg, list) = find(&system, "fapt-quilt")?;
+    let dest = tempfile::tempdir()?;
+    let out = dest.path().join("out");
+    let downloaded = system.download_source(&pkg, &list, &out, None)?;
+    assert_eq!(out.join("fapt-quilt_1.0-1.dsc"), downloaded.dsc);
+    let expected: BTreeSet<String> = pkg
+        .as_src()
+        .unwrap()
+        .files
+        .iter()
+        .map(|file| file.name.to_string())
+        .collect();
+    assert_eq!(expected, files(&out)?);
+
+    // the fixture's .dsc isn't signed, so no maintainer could have signed it
+    let mut maintainers = Keys::new();
+    maintainers.append_from(&include_bytes!("keys/test-repo.asc")[..])?;
+    assert!(system
+        .download_source(&pkg, &list, dest.path().join("signed"), Some(&maintainers))
+        .is_err());
+
+    // a hostile index can't write outside the destination
+    let mut hostile = pkg.clone();
+    if let PackageType::Source(src) = &mut hostile.style {
+        src.files[0].name = "../escaped".to_string();
+    }
+    assert!(system.download_source(&hostile, &list, &out, None).is_err());
+    assert!(!dest.path().join("escaped").exists());
+
+    // only source packages have sources
+    let (binary, binary_list) = find(&system, "fapt-fixture")?;
+    assert!(system
+        .download_source(&binary, &binary_list, &out, None)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn check() -> Result<(), Error> {
+    let upstream_dir = tempfile::tempdir()?;
+    let root = upstream_dir.path();
+    upstream(root)?;
+    let url = serve(root.to_path_buf())?;
+
+    let lists = tempfile::tempdir()?;
+    let system = system(&url, lists.path(), "main")?;
+
+    let report = system.check_mirror(&url, "internal", true)?;
+    assert_eq!(Vec::<Problem>::new(), report.problems);
+    // Packages and Sources in 3 compressions, in 2 components, and their by-hash aliases;
+    // then the .deb and 7 source files
+    assert_eq!(24 + 1 + 7, report.checked);
+
+    // uncompressed indices needn't be served
+    let dists = root.join("dists/internal");
+    fs::remove_file(dists.join("main/binary-amd64/Packages"))?;
+
+    let deb = root.join("pool/main/f/fapt-fixture/fapt-fixture_1.2-3_all.deb");
+    let data = fs::read(&deb)?;
+    fs::write(&deb, &data[..100])?;
+
+    fs::remove_file(root.join("pool/main/f/fapt-quilt/fapt-quilt_1.0-1.dsc"))?;
+    // the server fails, rather than saying it hasn't got it
+    let debian = root.join("pool/main/f/fapt-quilt/fapt-quilt_1.0-1.debian.tar.xz");
+    fs::remove_file(&debian)?;
+    fs::create_dir(&debian)?;
+
+    let orig = root.join("pool/contrib/f/fapt-old/fapt-old_1.0.orig.tar.gz");
+    let mut data = fs::read(&orig)?;
+    data[20] ^= 0xff;
+    fs::write(&orig, data)?;
+
+    let shallow = system.check_mirror(&url, "internal", false)?;
+    assert_eq!(
+        vec![
+            Problem::Truncated {
+                path: "pool/main/f/fapt-fixture/fapt-fixture_1.2-3_all.deb".to_string(),
+                expected: include_bytes!("debs/fapt-fixture_xz.deb").len() as u64,
+                actual: 100,
+            },
+            Problem::Unreachable {
+                path: "pool/main/f/fapt-quilt/fapt-quilt_1.0-1.debian.tar.xz".to_string(),
+                reason: format!(
+                    "couldn't find {}pool/main/f/fapt-quilt/fapt-quilt_1.0-1.debian.tar.xz: \
+                     server responded with 500",
+                    url
+                ),
+            },
+            Problem::Missing {
+                path: "pool/main/f/fapt-quilt/fapt-quilt_1.0-1.dsc".to_string(),
+            },
+        ],
+        shallow.problems
+    );
+
+    let deep = system.check_mirror(&url, "internal", true)?;
+    assert_eq!(4, deep.problems.len());
+    assert_eq!(
+        "pool/contrib/f/fapt-old/fapt-old_1.0.orig.tar.gz",
+        deep.problems[0].path()
+    );
+    assert!(deep.problems[0].to_string().contains("SHA256 mismatch"));
+
+    // nor against a release with no checksums
+    let release = fs::read_to_string(dists.join("Release"))?;
+    let bare: String = release
+        .lines()
+        .take_while(|line| !line.ends_with(':'))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let key = SigningKey::read(&include_bytes!("keys/test-repo.secret.asc")[..], None)?;
+    fs::write(dists.join("Release"), &bare)?;
+    fs::write(
+        dists.join("Release.gpg"),
+        key.sign_detached(bare.as_bytes())?,
+    )?;
+    fs::write(dists.join("InRelease"), key.clearsign(&bare)?)?;
+    assert!(system.check_mirror(&url, "internal", false).is_err());
+
+    // nothing can be checked without a trusted release
+    fs::remove_file(dists.join("InRelease"))?;
+    fs::remove_file(dists.join("Release.gpg"))?;
+    assert!(system.check_mirror(&url, "internal", false).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn listings_know_their_signer() -> Result<(), Error> {
+    let upstream_dir = tempfile::tempdir()?;
+    upstream(upstream_dir.path())?;
+    let url = serve(upstream_dir.path().to_path_buf())?;
+
+    let lists = tempfile::tempdir()?;
+    let system = system(&url, lists.path(), "main")?;
+    system.update()?;
+
+    let listings = system.listings()?;
+    assert!(!listings.is_empty());
+    let signer = listings[0]
+        .release
+        .signer
+        .as_ref()
+        .expect("signer recorded");
+    assert_eq!(
+        "7657602C7607078ADF5526328759C52696D8E20E",
+        signer.primary_fingerprint
+    );
+    assert_eq!(0x8759_C526_96D8_E20E, signer.key_id);
+    assert_eq!("SHA512", signer.hash.by_hash_name());
+    assert!(signer.user_id.is_some());
+    Ok(())
+}
+
+#[test]
+fn trusted_unsigned() -> Result<(), Error> {
+    let unsigned = |root: &Path| -> Result<String, Error> {
+        upstream(root)?;
+        let dists = root.join("dists/internal");
+        fs::remove_file(dists.join("InRelease"))?;
+        fs::remove_file(dists.join("Release.gpg"))?;
+        serve(root.to_path_buf())
+    };
+    let trusted_dir = tempfile::tempdir()?;
+    let trusted_url = unsigned(trusted_dir.path())?;
+    let other_dir = tempfile::tempdir()?;
+    let other_url = unsigned(other_dir.path())?;
+
+    let with_lines = |lists: &Path, lines: String| -> Result<System, Error> {
+        let mut system = System::cache_only_in(lists)?;
+        system.set_proxies(ProxyConfig::default());
+        system.set_arches(&["amd64"]);
+        system.add_keys_from(&include_bytes!("keys/test-repo.gpg")[..])?;
+        fapt::commands::add_sources_entries_from_str(&mut system, lines)?;
+        Ok(system)
+    };
+
+    let lists = tempfile::tempdir()?;
+    let untrusted = system(&trusted_url, lists.path(), "main")?;
+    assert!(untrusted.update().is_err());
+
+    let trusted = with_lines(
+        lists.path(),
+        format!("deb [trusted=yes] {} internal main\n", trusted_url),
+    )?;
+    trusted.update()?;
+    let listings = trusted.listings()?;
+    assert!(listings[0].release.unsigned);
+    assert!(listings[0].release.signer.is_none());
+
+    // ..and it's only trusted while it's configured as such
+    assert!(untrusted.listings().is_err());
+
+    // other sources still need signatures
+    let both = with_lines(
+        lists.path(),
+        format!(
+            "deb [trusted=yes] {} internal main\ndeb {} internal main\n",
+            trusted_url, other_url
+        ),
+    )?;
+    assert!(both.update().is_err());
+
+    // entries for the same release mustn't disagree
+    let conflicting = with_lines(
+        lists.path(),
+        format!(
+            "deb [trusted=yes] {url} internal main\ndeb-src {url} internal main\n",
+            url = trusted_url
+        ),
+    )?;
+    let err = conflicting.update().unwrap_err();
+    assert!(format!("{:#}", err).contains("conflicting values for trusted"));
+
+    Ok(())
+}
@@ -53,6 +53,10 @@ fn parse_provides() -> Result<(), Error> {
     assert_eq!("python3-cffi-backend", p.name.as_str());
     let bin = p.as_bin().unwrap();
     assert_eq!(3, bin.provides.len());
+    assert_eq!(
+        Some("install ok installed"),
+        bin.status.map(|s| s.to_string()).as_deref()
+    );
     assert_eq!(HashMap::new(), p.unparsed);
     Ok(())
 }
@@ -0,0 +1,112 @@
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use anyhow::Error;
+use fapt::ownership::FileIndex;
+use fapt::system::System;
+
+mod common;
+
+/// A `/usr`-merged root, where `dash` has diverted `bash`'s `/bin/sh`, and the admin has
+/// their own `/etc/issue`.
+fn installed(root: &Path) -> Result<FileIndex, Error> {
+    let dpkg = root.join("var/lib/dpkg");
+    fs::create_dir_all(root.join("usr/bin"))?;
+    symlink("usr/bin", root.join("bin"))?;
+
+    common::write(&dpkg, "info/dash.list", "/.\n/bin\n/bin/dash\n/bin/sh\n")?;
+    common::write(&dpkg, "info/bash.list", "/.\n/bin\n/bin/bash\n/bin/sh\n")?;
+    common::write(
+        &dpkg,
+        "info/libc6:amd64.list",
+        "/.\n/usr\n/usr/bin\n/usr/bin/ldd\n/etc\n/etc/issue\n",
+    )?;
+    common::write(&dpkg, "info/bash.md5sums", "not a list\n")?;
+    common::write(
+        &dpkg,
+        "diversions",
+        "/usr/bin/sh\n/usr/bin/sh.distrib\ndash\n/etc/issue\n/etc/issue.dist\n:\n",
+    )?;
+
+    let lists = tempfile::tempdir()?;
+    let mut system = System::cache_only_in(lists.path())?;
+    system.set_dpkg_database(&dpkg);
+    system.file_index(root)
+}
+
+#[test]
+fn owners() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    let index = installed(root.path())?;
+
+    assert_eq!(vec!["bash"], index.owners("/bin/bash"));
+    assert_eq!(vec!["bash"], index.owners("/usr/bin/bash"));
+    assert_eq!(vec!["libc6:amd64"], index.owners("/bin/ldd"));
+    assert_eq!(
+        vec!["bash", "dash", "libc6:amd64"],
+        index.owners("/usr/bin/")
+    );
+    assert!(index.owners("/usr/bin/zsh").is_empty());
+
+    // the diversion is listed as /usr/bin/sh, the packages as /bin/sh
+    assert_eq!(vec!["dash"], index.owners("/bin/sh"));
+    assert_eq!(vec!["bash"], index.owners("/bin/sh.distrib"));
+    assert_eq!(
+        Some("dash"),
+        index.diversion("/bin/sh").unwrap().by.as_deref()
+    );
+
+    // only the admin's copy is at the diverted path
+    assert!(index.owners("/etc/issue").is_empty());
+    assert_eq!(vec!["libc6:amd64"], index.owners("/etc/issue.dist"));
+    assert_eq!(None, index.diversion("/etc/issue").unwrap().by);
+    Ok(())
+}
+
+#[test]
+fn files() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    let index = installed(root.path())?;
+
+    assert_eq!(
+        Some(vec![
+            "/".to_string(),
+            "/bin".to_string(),
+            "/bin/bash".to_string(),
+            "/usr/bin/sh.distrib".to_string(),
+        ]),
+        index.files("bash")
+    );
+    assert_eq!(
+        Some("/bin/sh"),
+        index.files("dash").unwrap().last().map(|s| s.as_str())
+    );
+    assert_eq!(None, index.files("libc6"));
+    assert!(index.files("libc6:amd64").is_some());
+    Ok(())
+}
+
+#[test]
+fn search() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    let index = installed(root.path())?;
+
+    assert_eq!(
+        vec![
+            ("bash", "/usr/bin/sh.distrib".to_string()),
+            ("dash", "/bin/sh".to_string()),
+        ],
+        index.search("bin/sh")
+    );
+    // matches either side of the /usr merge
+    assert_eq!(
+        vec![("bash", "/bin/bash".to_string())],
+        index.search("/usr/bin/ba?h")
+    );
+    assert_eq!(
+        vec![("libc6:amd64", "/etc/issue.dist".to_string())],
+        index.search("*issue*")
+    );
+    Ok(())
+}
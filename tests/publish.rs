@@ -0,0 +1,156 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::Error;
+use fapt::parse::Package;
+use fapt::publish::Suite;
+use fapt::rfc822;
+use fapt::rfc822::RfcMapExt;
+
+const SOURCES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/sources");
+
+fn pool(root: &Path) -> Result<(), Error> {
+    let debs = root.join("pool/main/f/fapt-fixture");
+    fs::create_dir_all(&debs)?;
+    fs::write(
+        debs.join("fapt-fixture_1.2-3_all.deb"),
+        &include_bytes!("debs/fapt-fixture_xz.deb")[..],
+    )?;
+
+    let quilt = root.join("pool/main/f/fapt-quilt");
+    let old = root.join("pool/contrib/f/fapt-old");
+    fs::create_dir_all(&quilt)?;
+    fs::create_dir_all(&old)?;
+    for file in fs::read_dir(SOURCES)? {
+        let file = file?;
+        let name = file.file_name().into_string().unwrap();
+        if name.starts_with("fapt-quilt_") {
+            fs::copy(file.path(), quilt.join(&name))?;
+        } else if name.starts_with("fapt-old_") {
+            fs::copy(file.path(), old.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+fn packages(path: &Path) -> Result<Vec<Package>, Error> {
+    rfc822::Blocks::new(fs::File::open(path)?, format!("{:?}", path))
+        .map(|block| Package::parse(&mut rfc822::fields_in_block(&block?).collect_to_map()?))
+        .collect()
+}
+
+#[test]
+fn publish() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    let root = root.path();
+    pool(root)?;
+
+    let mut suite = Suite::new("internal");
+    suite.codename = Some("box".to_string());
+    suite.arches = vec!["amd64".to_string(), "arm64".to_string()];
+    suite.by_hash = true;
+    let release = fapt::publish::publish(root, &suite)?;
+
+    let dists = root.join("dists/internal");
+    assert_eq!(dists.join("Release"), release);
+
+    for arch in &["amd64", "arm64"] {
+        let bins = packages(&dists.join(format!("main/binary-{}/Packages", arch)))?;
+        assert_eq!(1, bins.len());
+        assert_eq!("fapt-fixture", bins[0].name);
+        assert_eq!("utils", bins[0].section);
+        let file = &bins[0].as_bin().unwrap().file.as_ref().unwrap();
+        assert!(root.join(&file.name).is_file());
+        assert_eq!(64, file.sha256.len());
+
+        assert!(packages(&dists.join(format!("contrib/binary-{}/Packages", arch)))?.is_empty());
+    }
+
+    let srcs = packages(&dists.join("main/source/Sources"))?;
+    assert_eq!(1, srcs.len());
+    assert_eq!("fapt-quilt", srcs[0].name);
+    assert_eq!("misc", srcs[0].section);
+    let src = srcs[0].as_src().unwrap();
+    let directory = src.directory.as_ref().unwrap();
+    assert_eq!("pool/main/f/fapt-quilt", directory);
+    assert_eq!(4, src.files.len());
+    assert!(src.files.iter().all(|file| file.sha256.is_some()));
+
+    // everything needed to unpack it is where the index says
+    let dest = tempfile::tempdir()?;
+    fapt::source::unpack(
+        root.join(directory).join("fapt-quilt_1.0-1.dsc"),
+        dest.path().join("out"),
+    )?;
+
+    assert_eq!(
+        "fapt-old",
+        packages(&dists.join("contrib/source/Sources"))?[0].name
+    );
+
+    let text = fs::read_to_string(&release)?;
+    let mut map = rfc822::fields_in_block(&text).collect_to_map()?;
+    assert_eq!("box", map.remove_value("Codename").one_line_req()?);
+    assert_eq!("yes", map.remove_value("Acquire-By-Hash").one_line_req()?);
+    assert_eq!(
+        vec!["amd64", "arm64"],
+        map.remove_value("Architectures").split_whitespace()?
+    );
+    assert_eq!(
+        vec!["contrib", "main"],
+        map.remove_value("Components").split_whitespace()?
+    );
+
+    // even the empty ones
+    for listing in &[
+        "main/binary-amd64/Packages",
+        "contrib/binary-amd64/Packages",
+    ] {
+        let mut decompressed = Vec::new();
+        lzma_rust2::XzReader::new(
+            fs::File::open(dists.join(listing).with_extension("xz"))?,
+            true,
+        )
+        .read_to_end(&mut decompressed)?;
+        assert_eq!(fs::read(dists.join(listing))?, decompressed);
+    }
+
+    let sha256 = map.remove_value("SHA256").required()?.to_vec();
+    // Packages for two architectures and Sources, in three compressions, in two components
+    assert_eq!(18, sha256.len());
+    for line in sha256 {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let content = fs::read(dists.join(parts[2]))?;
+        assert_eq!(parts[1], content.len().to_string());
+
+        let dir = Path::new(parts[2]).parent().unwrap();
+        assert_eq!(
+            content,
+            fs::read(dists.join(dir).join("by-hash/SHA256").join(parts[0]))?
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn duplicates() -> Result<(), Error> {
+    let root = tempfile::tempdir()?;
+    let root = root.path();
+    pool(root)?;
+    fs::copy(
+        root.join("pool/main/f/fapt-fixture/fapt-fixture_1.2-3_all.deb"),
+        root.join("pool/contrib/f/fapt-old/copy.deb"),
+    )?;
+
+    let mut suite = Suite::new("internal");
+    suite.arches = vec!["amd64".to_string()];
+    assert!(fapt::publish::publish(root, &suite).is_err());
+
+    // unless we leave that component out
+    suite.components = vec!["main".to_string()];
+    fapt::publish::publish(root, &suite)?;
+    assert!(!root.join("dists/internal/contrib").exists());
+    Ok(())
+}
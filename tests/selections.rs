@@ -0,0 +1,149 @@
+use std::fs;
+use std::io;
+
+use anyhow::Error;
+use fapt::parse::Want;
+use fapt::selections::read_extended_states;
+use fapt::selections::read_selections;
+use fapt::selections::Mark;
+use fapt::selections::Selection;
+use fapt::status::Database;
+use fapt::system::System;
+
+const DPKG: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dpkg");
+
+#[test]
+fn extended_states() -> Result<(), Error> {
+    let states = read_extended_states(&include_bytes!("dpkg/extended_states")[..])?;
+    assert_eq!(4, states.len());
+    assert_eq!("libc6", states[1].package);
+    assert_eq!(Some("i386"), states[1].arch.as_deref());
+    assert!(!states[1].auto_installed);
+
+    let old = read_extended_states(&b"Package: foo\nAuto-Installed: 1\n"[..])?;
+    assert_eq!(None, old[0].arch);
+    assert!(read_extended_states(&b"Package: foo\nAuto-Installed: yes\n"[..]).is_err());
+    Ok(())
+}
+
+fn listed(status: &Database, native: &str) -> Vec<String> {
+    status
+        .selections(native)
+        .iter()
+        .map(|s| match &s.arch {
+            Some(arch) => format!("{}:{} {}", s.package, arch, s.want),
+            None => format!("{} {}", s.package, s.want),
+        })
+        .collect()
+}
+
+#[test]
+fn selections() -> Result<(), Error> {
+    let dump = "adduser\t\t\t\t\t\tinstall\nlibc6:i386\t\t\t\thold\n\nvim deinstall\n";
+    assert_eq!(
+        vec![
+            Selection {
+                package: "adduser".to_string(),
+                arch: None,
+                want: Want::Install,
+            },
+            Selection {
+                package: "libc6".to_string(),
+                arch: Some("i386".to_string()),
+                want: Want::Hold,
+            },
+            Selection {
+                package: "vim".to_string(),
+                arch: None,
+                want: Want::Deinstall,
+            },
+        ],
+        read_selections(io::Cursor::new(dump))?
+    );
+    assert!(read_selections(io::Cursor::new("vim keep\n")).is_err());
+    assert!(read_selections(io::Cursor::new("vim\n")).is_err());
+
+    let status = Database::read(&include_bytes!("dpkg/status")[..])?;
+    // vim wants installing, but isn't installed, so isn't listed
+    assert_eq!(
+        vec![
+            "base-files hold",
+            "libc6:amd64 install",
+            "libc6:i386 install",
+            "man-db install",
+            "openssh-server deinstall",
+        ],
+        listed(&status, "amd64")
+    );
+
+    // on an i386 system, the amd64 packages are the foreign ones
+    assert_eq!(
+        vec![
+            "base-files:amd64 hold",
+            "libc6:amd64 install",
+            "libc6:i386 install",
+            "man-db:amd64 install",
+            "openssh-server:amd64 deinstall",
+        ],
+        listed(&status, "i386")
+    );
+    Ok(())
+}
+
+#[test]
+fn marks() -> Result<(), Error> {
+    let lists = tempfile::tempdir()?;
+    let mut system = System::cache_only_in(lists.path())?;
+    system.set_dpkg_database(DPKG);
+    assert!(system.marks().is_err());
+
+    system.set_extended_states(format!("{}/extended_states", DPKG));
+    let mark = |package: &str, arch: &str, auto_installed, held| Mark {
+        package: package.to_string(),
+        arch: Some(arch.to_string()),
+        auto_installed,
+        held,
+    };
+    // only installed packages, and the half-installed man-db isn't
+    assert_eq!(
+        vec![
+            mark("base-files", "amd64", true, true),
+            mark("libc6", "amd64", true, false),
+            mark("libc6", "i386", false, false),
+        ],
+        system.marks()?
+    );
+
+    // architecture-independent packages are recorded as the native architecture
+    let dir = tempfile::tempdir()?;
+    fs::write(
+        dir.path().join("status"),
+        "Package: adduser\nStatus: install ok installed\nArchitecture: all\n",
+    )?;
+    fs::write(
+        dir.path().join("extended_states"),
+        "Package: adduser\nArchitecture: amd64\nAuto-Installed: 1\n",
+    )?;
+    system.set_dpkg_database(dir.path());
+    system.set_extended_states(dir.path().join("extended_states"));
+    assert_eq!(vec![mark("adduser", "all", true, false)], system.marks()?);
+
+    // recorded under more than one, so only the native architecture will do
+    fs::write(
+        dir.path().join("extended_states"),
+        "Package: adduser\nArchitecture: amd64\nAuto-Installed: 1\n\n\
+         Package: adduser\nArchitecture: i386\nAuto-Installed: 0\n",
+    )?;
+    assert_eq!(vec![mark("adduser", "all", false, false)], system.marks()?);
+    system.set_arches(&["amd64", "i386"]);
+    assert_eq!(vec![mark("adduser", "all", true, false)], system.marks()?);
+    system.set_arches(&["i386"]);
+    assert_eq!(vec![mark("adduser", "all", false, false)], system.marks()?);
+
+    // apt hasn't recorded anything yet
+    let empty = tempfile::tempdir()?;
+    system.set_extended_states(empty.path().join("extended_states"));
+    assert!(system.marks()?.iter().all(|m| !m.auto_installed));
+    assert!(fs::read_dir(empty.path())?.next().is_none());
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::io;
+
+use anyhow::Error;
+use fapt::signing::read_clearsigned;
+use fapt::signing::Keys;
+use fapt::signing::SignatureStatus;
+use fapt::signing::SigningKey;
+use gpgrv::Keyring;
+
+mod common;
+
+const SECRET: &[u8] = include_bytes!("keys/test-repo.secret.asc");
+const PROTECTED: &[u8] = include_bytes!("keys/test-repo.protected.asc");
+
+#[test]
+fn clearsign() -> Result<(), Error> {
+    let key = SigningKey::read(SECRET, None)?;
+    assert_eq!(
+        "7657602C7607078ADF5526328759C52696D8E20E",
+        key.fingerprint()
+    );
+    assert_eq!(0x8759_C526_96D8_E20E, key.key_id());
+
+    let text = "Origin: test\n-dashed: line  \nend\n";
+    let signed = key.clearsign(text)?;
+    assert!(signed.contains("\n- -dashed: line  \n"));
+
+    let (content, status) = read_clearsigned(io::Cursor::new(&signed), Some(&common::keyring()?))?;
+    assert_eq!(SignatureStatus::Verified, status);
+    // trailing whitespace isn't signed, so isn't returned
+    assert_eq!("Origin: test\n-dashed: line\nend", content.trim_end());
+
+    let tampered = signed.replace("Origin: test", "Origin: tEst");
+    assert!(read_clearsigned(io::Cursor::new(&tampered), Some(&common::keyring()?)).is_err());
+    Ok(())
+}
+
+#[test]
+fn detached() -> Result<(), Error> {
+    let key = SigningKey::read(SECRET, None)?;
+    let data = b"Origin: test\nLabel: test\n";
+    let signature = key.sign_detached(data)?;
+    // checked against the data by the unit tests, as gpgrv can't check all of our signatures
+    assert!(signature.starts_with("-----BEGIN PGP SIGNATURE-----\n"));
+    assert!(signature.ends_with("-----END PGP SIGNATURE-----\n"));
+    Ok(())
+}
+
+#[test]
+fn passphrase() -> Result<(), Error> {
+    assert!(SigningKey::read(PROTECTED, None).is_err());
+    assert!(SigningKey::read(PROTECTED, Some("wrong")).is_err());
+
+    let key = SigningKey::read(PROTECTED, Some("correct horse"))?;
+    let signed = key.clearsign("Origin: test\n")?;
+    read_clearsigned(io::Cursor::new(&signed), Some(&common::keyring()?))?;
+    Ok(())
+}
+
+#[test]
+fn public_keys_are_not_secret() {
+    assert!(SigningKey::read(&include_bytes!("keys/test-repo.asc")[..], None).is_err());
+}
+
+/// Does the keyring trust the test key?
+fn trusts(keyring: &Keyring) -> Result<bool, Error> {
+    let signed = SigningKey::read(SECRET, None)?.clearsign("Origin: test\n")?;
+    Ok(read_clearsigned(io::Cursor::new(&signed), Some(keyring)).is_ok())
+}
+
+/// A key with an algorithm no-one supports, under a new-format header.
+fn unsupported_key() -> Vec<u8> {
+    let mut data = vec![0xc6, 8, 4, 0, 0, 0, 0, 99, 1, 2];
+    data.extend_from_slice(b"\xcd\x07nobody?");
+    data
+}
+
+#[test]
+fn key_formats() -> Result<(), Error> {
+    for data in &[
+        &include_bytes!("keys/test-repo.asc")[..],
+        &include_bytes!("keys/test-repo.gpg")[..],
+        &include_bytes!("keys/test-repo.kbx")[..],
+    ] {
+        let mut keys = Keys::new();
+        assert!(!trusts(keys.keyring())?);
+        assert_eq!(1, keys.append_from(*data)?);
+        assert!(trusts(keys.keyring())?);
+    }
+    Ok(())
+}
+
+#[test]
+fn unusable_keys_are_skipped() -> Result<(), Error> {
+    let mut binary = unsupported_key();
+    binary.extend_from_slice(include_bytes!("keys/test-repo.gpg"));
+    let mut keys = Keys::new();
+    assert_eq!(1, keys.append_from(&binary[..])?);
+    assert!(trusts(keys.keyring())?);
+
+    // only the key block is of interest
+    let armoured = format!(
+        "{}\n{}",
+        SigningKey::read(SECRET, None)?.sign_detached(b"unrelated")?,
+        include_str!("keys/test-repo.asc"),
+    );
+    let mut keys = Keys::new();
+    assert_eq!(1, keys.append_from(armoured.as_bytes())?);
+    assert!(trusts(keys.keyring())?);
+
+    let mut keys = Keys::new();
+    assert_eq!(0, keys.append_from(&unsupported_key()[..])?);
+    assert!(keys.append_from(&b"not a key"[..]).is_err());
+    assert!(keys.append_from(&b""[..]).is_err());
+    Ok(())
+}
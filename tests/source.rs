@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Error;
+use fapt::parse::SourceFormat;
+
+const SOURCES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/sources");
+
+fn read(root: &Path, path: &str) -> String {
+    fs::read_to_string(root.join(path)).unwrap()
+}
+
+#[test]
+fn quilt() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let dest = dir.path().join("fapt-quilt-1.0");
+    let pkg = fapt::source::unpack(Path::new(SOURCES).join("fapt-quilt_1.0-1.dsc"), &dest)?;
+    assert_eq!(SourceFormat::Quilt3dot0, pkg.as_src().unwrap().format);
+
+    assert_eq!("upstream readme\n", read(&dest, "README"));
+    assert_eq!("one\nTWO\nthree\n", read(&dest, "src/main.txt"));
+    assert_eq!("now with newline\n", read(&dest, "src/tail.txt"));
+    assert_eq!("added by a patch\n", read(&dest, "src/added.txt"));
+    assert_eq!("component data\n", read(&dest, "extra/data.txt"));
+    assert_eq!("3.0 (quilt)\n", read(&dest, "debian/source/format"));
+    assert!(!dest.join(".pc").exists());
+
+    // only the unpacked tree is left behind
+    assert_eq!(1, fs::read_dir(dir.path())?.count());
+    Ok(())
+}
+
+#[test]
+fn original() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let dest = dir.path().join("fapt-old-1.0");
+    fapt::source::unpack(Path::new(SOURCES).join("fapt-old_1.0-1.dsc"), &dest)?;
+
+    assert_eq!("a\nB\nc\n", read(&dest, "file.txt"));
+    assert_eq!("1.0\n", read(&dest, "debian/source/format"));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(dest.join("debian/rules"))?
+            .permissions()
+            .mode();
+        assert_eq!(0o755, mode & 0o777);
+    }
+    Ok(())
+}
+
+#[test]
+fn native() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let dest = dir.path().join("out");
+    fapt::source::unpack(Path::new(SOURCES).join("fapt-native_1.0.dsc"), &dest)?;
+    assert_eq!("native\n", read(&dest, "file.txt"));
+
+    // refuses to overwrite
+    assert!(fapt::source::unpack(Path::new(SOURCES).join("fapt-native_1.0.dsc"), &dest).is_err());
+    Ok(())
+}
+
+#[test]
+fn corrupt() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    for name in &["fapt-native_1.0.dsc", "fapt-native_1.0.tar.xz"] {
+        fs::copy(Path::new(SOURCES).join(name), dir.path().join(name))?;
+    }
+    fs::write(dir.path().join("fapt-native_1.0.tar.xz"), "nope")?;
+
+    let dest = dir.path().join("out");
+    assert!(fapt::source::unpack(dir.path().join("fapt-native_1.0.dsc"), &dest).is_err());
+    assert!(!dest.exists());
+    Ok(())
+}
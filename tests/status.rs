@@ -0,0 +1,238 @@
+use std::fs;
+use std::io;
+
+use anyhow::Error;
+use fapt::parse::Conffile;
+use fapt::parse::Flag;
+use fapt::parse::State;
+use fapt::parse::Want;
+use fapt::status::Database;
+use fapt::status::Entry;
+use fapt::system::System;
+
+fn database() -> Result<Database, Error> {
+    Database::read(io::Cursor::new(&include_bytes!("dpkg/status")[..]))
+}
+
+#[test]
+fn installed() -> Result<(), Error> {
+    let status = database()?;
+    assert_eq!(6, status.entries().len());
+
+    // half-installed, config-files only, and merely selected packages aren't installed
+    assert_eq!(
+        vec!["base-files:amd64", "libc6:amd64", "libc6:i386"],
+        status
+            .installed()
+            .map(|e| format!("{}:{}", e.package, e.arch.as_deref().unwrap()))
+            .collect::<Vec<_>>()
+    );
+
+    let vim = status.get("vim", "amd64").unwrap();
+    assert_eq!(Want::Install, vim.status.want);
+    assert_eq!(State::NotInstalled, vim.status.state);
+    assert_eq!(None, vim.version);
+    assert!(vim.as_pkg().is_err());
+    Ok(())
+}
+
+#[test]
+fn lookups() -> Result<(), Error> {
+    let status = database()?;
+    assert_eq!(2, status.by_name("libc6").len());
+    assert!(status.get("libc6", "arm64").is_none());
+    assert!(status.get("nope", "amd64").is_none());
+
+    let libc = status.get("libc6", "i386").unwrap();
+    assert_eq!("libc6:i386", libc.info_name());
+    assert_eq!(
+        vec!["ldconfig", "/usr/lib/i386-linux-gnu"],
+        libc.triggers_pending
+    );
+    let bin = libc.as_pkg()?.as_bin().cloned().unwrap();
+    assert_eq!(Some("glibc"), bin.source.as_deref());
+    assert_eq!(State::TriggersPending, bin.status.unwrap().state);
+
+    let base = status.get("base-files", "amd64").unwrap();
+    assert_eq!("base-files", base.info_name());
+    Ok(())
+}
+
+#[test]
+fn fields() -> Result<(), Error> {
+    let status = database()?;
+
+    let man = status.get("man-db", "amd64").unwrap();
+    assert_eq!(Flag::ReinstReq, man.status.flag);
+    assert_eq!(State::HalfInstalled, man.status.state);
+    assert_eq!(Some("2.11.1-1"), man.config_version.as_deref());
+    assert_eq!(vec!["libc6:i386"], man.triggers_awaited);
+
+    let base = status.get("base-files", "amd64").unwrap();
+    assert_eq!(
+        vec![
+            ("/etc/debian_version", true, false),
+            ("/etc/dpkg/origins/debian", true, false),
+            ("/etc/host.conf", true, true),
+            ("/etc/issue", false, false),
+        ],
+        base.conffiles
+            .iter()
+            .map(|c| (c.path.as_str(), c.md5.is_some(), c.obsolete))
+            .collect::<Vec<_>>()
+    );
+
+    let ssh = status.get("openssh-server", "amd64").unwrap();
+    assert_eq!(Want::Deinstall, ssh.status.want);
+    assert_eq!(
+        vec![Conffile {
+            path: "/etc/ssh/moduli".to_string(),
+            md5: Some("8c3a2f9b1f4e0c0d2f4bb1c0b1c9e0f4".to_string()),
+            obsolete: false,
+            remove_on_upgrade: true,
+        }],
+        ssh.conffiles
+    );
+    Ok(())
+}
+
+#[test]
+fn invalid() {
+    for bad in &[
+        "Package: a\nStatus: install ok\n",
+        "Package: a\nStatus: install ok installed\nConffiles:\n /etc/a\n",
+        "Status: install ok installed\n",
+        "Package: a\n",
+    ] {
+        assert!(Database::read(io::Cursor::new(bad)).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn round_trip() -> Result<(), Error> {
+    let mut written = Vec::new();
+    database()?.write(&mut written)?;
+    let written = String::from_utf8(written)?;
+    assert_eq!(include_str!("dpkg/status"), written.as_str());
+    // as dpkg writes it, with a blank line after the last entry too
+    assert!(written.ends_with("\n\n"));
+    Ok(())
+}
+
+#[test]
+fn edits() -> Result<(), Error> {
+    let mut status = database()?;
+
+    let mut man = status.get("man-db", "amd64").unwrap().clone();
+    man.status = "install ok installed".parse()?;
+    man.config_version = None;
+    man.triggers_awaited.clear();
+    man.conffiles
+        .push(Conffile::parse("/etc/manpath.config newconffile")?);
+    status.set(man);
+
+    status.set(Entry::new(
+        "Package: hello\nVersion: 1.0\nArchitecture: amd64\nDescription: hi\n",
+        "install ok unpacked".parse()?,
+    )?);
+    assert!(status.remove("vim", "amd64").is_some());
+    assert!(status.remove("vim", "amd64").is_none());
+
+    assert_eq!(
+        vec![
+            "base-files",
+            "hello",
+            "libc6",
+            "libc6",
+            "man-db",
+            "openssh-server"
+        ],
+        status
+            .entries()
+            .iter()
+            .map(|e| e.package.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    let mut written = Vec::new();
+    status.write(&mut written)?;
+    let written = String::from_utf8(written)?;
+    assert!(written.contains(concat!(
+        "Package: hello\n",
+        "Status: install ok unpacked\n",
+        "Architecture: amd64\n",
+        "Version: 1.0\n",
+        "Description: hi\n\n",
+    )));
+    assert!(written.contains(concat!(
+        "Version: 2.11.2-2\n",
+        "Conffiles:\n",
+        " /etc/manpath.config newconffile\n",
+        "Description: tools for reading manual pages\n",
+        " This package provides the man command.\n\n",
+    )));
+
+    let rendered = |status: &Database| -> Vec<String> {
+        status.entries().iter().map(|e| e.to_string()).collect()
+    };
+    let reread = Database::read(io::Cursor::new(written))?;
+    assert_eq!(rendered(&status), rendered(&reread));
+    Ok(())
+}
+
+#[test]
+fn record_and_forget() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().join("root");
+    let dpkg = dir.path().join("dpkg");
+    let mut system = System::cache_only_in(dir.path().join("lists"))?;
+    system.set_dpkg_database(&dpkg);
+
+    let unpacked = fapt::deb::unpack(&include_bytes!("debs/fapt-fixture_xz.deb")[..], &root)?;
+    system.record_unpacked(&root, &unpacked, "install ok installed".parse()?)?;
+
+    let status = system.status_database()?;
+    let entry = status.get("fapt-fixture", "all").unwrap();
+    assert!(entry.is_installed());
+    assert_eq!(Some("1.2-3"), entry.version.as_deref());
+    assert_eq!(
+        vec![Conffile {
+            path: "/etc/fapt-fixture.conf".to_string(),
+            md5: Some("1507fd22b0eda3acc1a9c6bb9213ca67".to_string()),
+            obsolete: false,
+            remove_on_upgrade: false,
+        }],
+        entry.conffiles
+    );
+
+    let info = dpkg.join("info");
+    let list = fs::read_to_string(info.join("fapt-fixture.list"))?;
+    assert!(list.starts_with("/.\n/etc\n/etc/fapt-fixture.conf\n"));
+    assert!(fs::read_to_string(info.join("fapt-fixture.md5sums"))?
+        .contains("d604a220708aa59433ba410986cd4ffa  usr/bin/fapt-fixture\n"));
+    assert_eq!(
+        "/etc/fapt-fixture.conf\n",
+        fs::read_to_string(info.join("fapt-fixture.conffiles"))?
+    );
+
+    // the files are checkable, as if dpkg had installed them
+    let report = system.debsums(&root)?;
+    assert!(report.problems.is_empty());
+    assert!(report.conffiles.is_empty());
+
+    let written = fs::read(dpkg.join("status"))?;
+    let inode = |path| -> Result<u64, Error> {
+        Ok(std::os::unix::fs::MetadataExt::ino(&fs::metadata(path)?))
+    };
+    let written_inode = inode(dpkg.join("status"))?;
+    assert!(system.forget_package("fapt-fixture", "all")?.is_some());
+    assert!(system.forget_package("fapt-fixture", "all")?.is_none());
+    assert!(system.status_database()?.entries().is_empty());
+    assert_eq!(written, fs::read(dpkg.join("status-old"))?);
+    // the previous file itself, not a copy which could have been left half-written
+    assert_eq!(written_inode, inode(dpkg.join("status-old"))?);
+    assert!(!dpkg.join("status-old.dpkg-new").exists());
+    assert!(!info.join("fapt-fixture.list").exists());
+    assert!(!info.join("fapt-fixture.conffiles").exists());
+    Ok(())
+}